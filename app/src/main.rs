@@ -1,43 +1,123 @@
 use chrono::Local;
-use clap::Parser;
-use mystia_core::Compiler;
+use clap::{Parser, Subcommand, ValueEnum};
+use manifest::{Allocator, Manifest};
+use mystia_core::{Compiler, Diagnostic, format_source, parse_source};
 use sha2::{Digest, Sha256};
 use std::{
-    env::{current_dir, set_current_dir},
-    fs::{File, read_to_string},
+    collections::BTreeMap,
+    fs::{File, create_dir_all, read_to_string, write},
     io::Write,
     path::Path,
+    process::exit,
 };
 
+mod manifest;
+
 #[derive(Parser)]
 #[command(
     name = "Mystia",
-    about = "A programming language that will be compiled to WebAssembly "
+    about = "A programming language that will be compiled to WebAssembly ",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Source code file path
-    path: String,
+    #[arg(required = true)]
+    path: Option<String>,
     /// Show type inference summary
     #[arg(long = "summary", short = 's')]
     summary: bool,
+    /// Print the intermediate representation instead of compiling
+    #[arg(long = "emit", value_enum)]
+    emit: Option<Emit>,
+    /// Heap allocator generated in the program
+    #[arg(long = "allocator", value_enum, default_value_t = Allocator::FreeList)]
+    allocator: Allocator,
+    /// Build the program for release, that doesn't check the bounds of arrays by default
+    #[arg(long = "release")]
+    release: bool,
+    /// Abort on indexing out of the bounds of arrays. Without the checks, the index wraps around
+    /// the length by `%`, so reading out of the range silently returns another element
+    /// [default: false with --release, otherwise true]
+    #[arg(long = "bounds-check", value_name = "BOOL")]
+    bounds_check: Option<bool>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Emit {
+    /// Source code of the syntax tree, that syntax sugars are expanded
+    AstSource,
+    /// Source code of the syntax tree, that macro calls are expanded too
+    Expanded,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Format source code files in the canonical style
+    Fmt {
+        /// Source code file paths
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Check whether the files are formatted without overwriting them
+        #[arg(long)]
+        check: bool,
+    },
+    /// Build the project in the directory by its manifest `mystia.toml`
+    Build {
+        /// Directory of the project
+        #[arg(default_value = ".")]
+        path: String,
+        /// Build the program for release, that doesn't check the bounds of arrays
+        /// unless the manifest sets `bounds_check`
+        #[arg(long = "release")]
+        release: bool,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Fmt { paths, check }) => return format_files(&paths, check),
+        Some(Command::Build { path, release }) => return build_project(Path::new(&path), release),
+        None => {}
+    }
+    let path = cli.path.unwrap_or_default();
     let mut compiler = Compiler::new();
-    let filename = Path::new(&cli.path);
+    let filename = Path::new(&path);
 
     let Ok(source) = read_to_string(filename) else {
         eprintln!("Failed to read source file");
-        return;
+        exit(1);
     };
-    let original_dir = current_dir().unwrap();
-    set_current_dir(Path::new(filename).parent().unwrap()).unwrap();
-    let Some(wat_code) = compiler.build(&source) else {
-        let error_message = "failed to parse, compile or check type consistency";
-        let err = compiler.occurred_error.unwrap_or(error_message.to_owned());
-        eprintln!("Error: {err}");
+    // Imported modules are read relative to the directory of the source file
+    compiler.file = path.clone();
+    compiler.heap = cli.allocator.into();
+    compiler.bounds_check = cli.bounds_check.unwrap_or(!cli.release);
+    if let Some(emit) = cli.emit {
+        let ast = match emit {
+            Emit::AstSource => parse_source(&source),
+            Emit::Expanded => compiler.expand(&source),
+        };
+        match ast {
+            Ok(ast) => {
+                let code = ast.to_string();
+                print!("{}", format_source(&code).unwrap_or(code + "\n"));
+            }
+            Err(diagnostics) => {
+                report(&diagnostics, |x| compiler.render(x, &source, &path));
+                exit(1);
+            }
+        }
         return;
+    }
+    let wat_code = match compiler.build(&source) {
+        Ok(artifact) => artifact.wat,
+        Err(diagnostics) => {
+            report(&diagnostics, |x| compiler.render(x, &source, &path));
+            exit(1);
+        }
     };
     if cli.summary {
         println!("# Type Inference Summary");
@@ -66,6 +146,25 @@ fn main() {
         for (name, typ) in &compiler.global_type {
             println!(" - {name}: {}", typ.compress_alias(&compiler).format());
         }
+        println!("Generics:");
+        let generics = compiler
+            .generic_type
+            .iter()
+            .map(|(name, (params, _))| (name, params));
+        let generics = generics.chain(
+            compiler
+                .generic_code
+                .iter()
+                .map(|(name, (params, _))| (name, params)),
+        );
+        for (name, params) in generics {
+            println!(" - {name}<{}>:", params.join(", "));
+            for (instance, (generic, _)) in &compiler.instance {
+                if generic == name {
+                    println!("    - {instance}");
+                }
+            }
+        }
         println!("Aliases:");
         for (name, typ) in &compiler.type_alias {
             println!(" - {name}: {}", typ.format());
@@ -84,7 +183,6 @@ fn main() {
         }
     );
 
-    set_current_dir(original_dir).unwrap();
     let Ok(mut output_file) = File::create(filename.with_extension("wat")) else {
         eprintln!("Failed to create output WAT file");
         return;
@@ -106,3 +204,112 @@ fn main() {
         return;
     };
 }
+
+/// Build the project into the WebAssembly binary in the output directory,
+/// exiting with failure if it can't
+fn build_project(dir: &Path, release: bool) {
+    let manifest = match Manifest::load(dir) {
+        Ok(manifest) => manifest,
+        Err(errmsg) => {
+            eprintln!("{errmsg}");
+            exit(1);
+        }
+    };
+    let mut packages = BTreeMap::new();
+    if let Err(errmsg) = manifest.resolve(dir, &mut packages, &mut vec![]) {
+        eprintln!("{errmsg}");
+        exit(1);
+    }
+    let package = &manifest.package;
+    let entry = dir.join(&package.entry);
+    let path = entry.to_string_lossy().to_string();
+    let Ok(source) = read_to_string(&entry) else {
+        eprintln!("Failed to read entry file: {path}");
+        exit(1);
+    };
+
+    let mut compiler = Compiler::new();
+    compiler.file = path.clone();
+    compiler.memory = package.memory;
+    compiler.max_memory = package.max_memory;
+    compiler.heap = package.allocator.into();
+    compiler.bounds_check = package.bounds_check.unwrap_or(!release);
+    for (name, entry) in packages {
+        let entry = entry.to_string_lossy().to_string();
+        compiler.packages.insert(name, entry);
+    }
+    let wat_code = match compiler.build(&source) {
+        Ok(artifact) => artifact.wat,
+        Err(diagnostics) => {
+            report(&diagnostics, |x| compiler.render(x, &source, &path));
+            exit(1);
+        }
+    };
+    // Functions loaded from the modules that the runtime doesn't have must be given by the host
+    let modules = package.runtime.modules();
+    for code in &compiler.import_code {
+        let name = code.split('"').nth(3).unwrap_or_default();
+        if let Some((module, _)) = name.split_once('.')
+            && !modules.contains(&module)
+        {
+            let runtime = format!("{:?}", package.runtime).to_lowercase();
+            eprintln!("Warning: module `{module}` isn't provided by the {runtime} runtime");
+        }
+    }
+
+    let Ok(binary) = wat::parse_str(&wat_code) else {
+        eprintln!("Failed to compile WAT code");
+        exit(1);
+    };
+    let output = dir.join(&package.output);
+    let file = output.join(format!("{}.wasm", package.name));
+    if create_dir_all(&output).is_err() || write(&file, binary).is_err() {
+        eprintln!("Failed to write the WASM file: {}", file.display());
+        exit(1);
+    }
+    println!("Built {} to {}", package.name, file.display());
+}
+
+fn report(diagnostics: &[Diagnostic], render: impl Fn(&Diagnostic) -> String) {
+    for diagnostic in diagnostics {
+        eprintln!("{}\n", render(diagnostic));
+    }
+}
+
+/// Format the files, or report the unformatted ones and exit with failure in check mode
+fn format_files(paths: &[String], check: bool) {
+    let mut is_failed = false;
+    for path in paths {
+        let Ok(source) = read_to_string(path) else {
+            eprintln!("Failed to read source file: {path}");
+            is_failed = true;
+            continue;
+        };
+        let formatted = match format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(diagnostics) => {
+                report(&diagnostics, |x| x.render(&source, path));
+                is_failed = true;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            let line = source
+                .lines()
+                .zip(formatted.lines())
+                .take_while(|(before, after)| before == after)
+                .count();
+            eprintln!("Not formatted: {path}:{}", line + 1);
+            is_failed = true;
+        } else if write(path, formatted).is_err() {
+            eprintln!("Failed to write formatted code in the file: {path}");
+            is_failed = true;
+        }
+    }
+    if is_failed {
+        exit(1);
+    }
+}
@@ -0,0 +1,157 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+/// File name of the project manifest
+pub const MANIFEST: &str = "mystia.toml";
+
+/// Project manifest `mystia.toml`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub package: Package,
+    /// Other Mystia projects on the local paths, imported by the names like `import "name"`
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, Dependency>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Package {
+    pub name: String,
+    /// Source file of the program, or the one imported by the dependent projects
+    #[serde(default = "Package::default_entry")]
+    pub entry: String,
+    /// Directory where the WebAssembly binary is written
+    #[serde(default = "Package::default_output")]
+    pub output: String,
+    #[serde(default)]
+    pub runtime: Runtime,
+    /// Initial size of the linear memory in pages of 64 KiB
+    #[serde(default = "Package::default_memory")]
+    pub memory: usize,
+    /// Size in pages that the memory can grow to, or unlimited
+    pub max_memory: Option<usize>,
+    #[serde(default)]
+    pub allocator: Allocator,
+    /// Whether indexing aborts out of the bounds of arrays, or only unless it's built for release
+    pub bounds_check: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dependency {
+    /// Directory of the project, relative to the one depending on it
+    pub path: String,
+}
+
+/// JavaScript runtime that the program runs on
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Runtime {
+    #[default]
+    Web,
+    Node,
+}
+
+/// Heap allocator generated in the program
+#[derive(Clone, Copy, Debug, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Allocator {
+    /// Bump the pointer, never reusing freed memory
+    Bump,
+    /// Reuse freed memory by the free lists of size classes
+    #[default]
+    FreeList,
+    /// Collect unreachable objects when the heap is exhausted
+    Gc,
+}
+
+impl From<Allocator> for mystia_core::Allocator {
+    fn from(allocator: Allocator) -> Self {
+        match allocator {
+            Allocator::Bump => mystia_core::Allocator::Bump,
+            Allocator::FreeList => mystia_core::Allocator::FreeList,
+            Allocator::Gc => mystia_core::Allocator::Gc,
+        }
+    }
+}
+
+impl Package {
+    fn default_entry() -> String {
+        String::from("main.ms")
+    }
+
+    fn default_output() -> String {
+        String::from("dist")
+    }
+
+    fn default_memory() -> usize {
+        64
+    }
+}
+
+impl Runtime {
+    /// Modules of the runtime library that functions can be loaded from `load module.name`
+    pub fn modules(&self) -> &[&str] {
+        match self {
+            Runtime::Web => &["math", "random", "datetime", "time"],
+            Runtime::Node => &["math", "os", "random", "datetime", "time"],
+        }
+    }
+}
+
+impl Manifest {
+    /// Read the manifest in the directory of the project
+    pub fn load(dir: &Path) -> Result<Manifest, String> {
+        let path = dir.join(MANIFEST);
+        let Ok(text) = read_to_string(&path) else {
+            return Err(format!("Failed to read manifest: {}", path.display()));
+        };
+        toml::from_str(&text).map_err(|err| format!("Invalid manifest {}: {err}", path.display()))
+    }
+
+    /// Collect the entry files of the dependencies including the indirect ones,
+    /// with the directories and names of the projects depending on them to detect cycles
+    pub fn resolve(
+        &self,
+        dir: &Path,
+        packages: &mut BTreeMap<String, PathBuf>,
+        dependents: &mut Vec<(PathBuf, String)>,
+    ) -> Result<(), String> {
+        let canonical = |dir: &Path| {
+            dir.canonicalize()
+                .map_err(|_| format!("Failed to find project: {}", dir.display()))
+        };
+        dependents.push((canonical(dir)?, self.package.name.clone()));
+        for (name, dependency) in &self.dependencies {
+            let dir = dir.join(&dependency.path);
+            if dependents
+                .iter()
+                .any(|(x, _)| Ok(x) == canonical(&dir).as_ref())
+            {
+                let chain = dependents.iter().map(|(_, name)| name.as_str());
+                let chain = chain.chain([name.as_str()]).collect::<Vec<_>>();
+                return Err(format!("Circular dependency: {}", chain.join(" -> ")));
+            }
+            let manifest = Manifest::load(&dir)?;
+            let entry = dir.join(&manifest.package.entry);
+            if let Some(exist) = packages.get(name) {
+                if canonical(exist)? != canonical(&entry)? {
+                    return Err(format!(
+                        "Dependency `{name}` is declared with different paths"
+                    ));
+                }
+                continue;
+            }
+            packages.insert(name.clone(), entry);
+            manifest.resolve(&dir, packages, dependents)?;
+        }
+        dependents.pop();
+        Ok(())
+    }
+}
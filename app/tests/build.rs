@@ -0,0 +1,254 @@
+use std::{
+    env::temp_dir,
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+/// Write the files of the projects in the directory for the test
+fn project(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = temp_dir().join(format!("mystia-build-{test}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, source) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+    dir
+}
+
+fn build(dir: PathBuf) -> Output {
+    build_with(dir, &[])
+}
+
+fn build_with(dir: PathBuf, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mystia"))
+        .arg("build")
+        .arg(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Message that the build failed with
+fn failure(dir: PathBuf) -> String {
+    let output = build(dir);
+    assert!(!output.status.success());
+    String::from_utf8(output.stderr).unwrap().trim().to_string()
+}
+
+#[test]
+fn default_settings() {
+    let dir = project(
+        "default",
+        &[
+            ("mystia.toml", "[package]\nname = \"demo\""),
+            ("main.ms", "1 + 2"),
+        ],
+    );
+    let output = build(dir.clone());
+    assert!(output.status.success(), "{output:?}");
+    // Entry is main.ms and the binary is written in dist
+    let file = dir.join("dist/demo.wasm");
+    assert!(file.exists());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.trim(), format!("Built demo to {}", file.display()));
+}
+
+#[test]
+fn settings() {
+    let manifest = "[package]
+name = \"demo\"
+entry = \"src/app.ms\"
+output = \"out\"
+runtime = \"node\"
+memory = 2
+max_memory = 4
+allocator = \"gc\"
+bounds_check = false";
+    let dir = project(
+        "settings",
+        &[("mystia.toml", manifest), ("src/app.ms", "[1, 2][0]")],
+    );
+    let output = build(dir.clone());
+    assert!(output.status.success(), "{output:?}");
+    assert!(dir.join("out/demo.wasm").exists());
+}
+
+#[test]
+fn release_mode() {
+    // Message of the bounds check is in the binary only if the indexing is checked
+    let is_checked = |manifest: &str, args: &[&str]| {
+        let dir = project(
+            "release",
+            &[("mystia.toml", manifest), ("main.ms", "[1, 2][0]")],
+        );
+        let output = build_with(dir.clone(), args);
+        assert!(output.status.success(), "{output:?}");
+        let binary = fs::read(dir.join("dist/demo.wasm")).unwrap();
+        let message = b"index out of bounds";
+        binary.windows(message.len()).any(|x| x == message)
+    };
+    let manifest = "[package]\nname = \"demo\"";
+    assert!(is_checked(manifest, &[]));
+    assert!(!is_checked(manifest, &["--release"]));
+    let manifest = "[package]\nname = \"demo\"\nbounds_check = true";
+    assert!(is_checked(manifest, &["--release"]));
+}
+
+#[test]
+fn dependencies() {
+    let dir = project(
+        "dependencies",
+        &[
+            (
+                "app/mystia.toml",
+                "[package]\nname = \"app\"\n[dependencies]\nshape = { path = \"../shape\" }",
+            ),
+            ("app/main.ms", "import \"shape\"; shape.area(3)"),
+            (
+                "shape/mystia.toml",
+                "[package]\nname = \"shape\"\nentry = \"lib.ms\"\n[dependencies]\nmath = { path = \"../math\" }",
+            ),
+            (
+                "shape/lib.ms",
+                "import \"math\"; pub let area(w: int) = math.square(w)",
+            ),
+            ("math/mystia.toml", "[package]\nname = \"math\""),
+            ("math/main.ms", "pub let square(x: int) = x * x"),
+        ],
+    );
+    let output = build(dir.join("app"));
+    assert!(output.status.success(), "{output:?}");
+    assert!(dir.join("app/dist/app.wasm").exists());
+}
+
+#[test]
+fn circular_dependency() {
+    let dir = project(
+        "circular",
+        &[
+            (
+                "a/mystia.toml",
+                "[package]\nname = \"a\"\n[dependencies]\nb = { path = \"../b\" }",
+            ),
+            ("a/main.ms", "0"),
+            (
+                "b/mystia.toml",
+                "[package]\nname = \"b\"\n[dependencies]\na = { path = \"../a\" }",
+            ),
+            ("b/main.ms", "0"),
+        ],
+    );
+    assert_eq!(failure(dir.join("a")), "Circular dependency: a -> b -> a");
+}
+
+#[test]
+fn duplicate_dependency() {
+    // Indirect dependency of the same name refers another project
+    let dir = project(
+        "duplicate",
+        &[
+            (
+                "app/mystia.toml",
+                "[package]\nname = \"app\"\n[dependencies]\nutil = { path = \"../util\" }\nlib = { path = \"../lib\" }",
+            ),
+            ("app/main.ms", "0"),
+            (
+                "lib/mystia.toml",
+                "[package]\nname = \"lib\"\n[dependencies]\nutil = { path = \"../other\" }",
+            ),
+            ("lib/main.ms", "0"),
+            ("util/mystia.toml", "[package]\nname = \"util\""),
+            ("util/main.ms", "0"),
+            ("other/mystia.toml", "[package]\nname = \"util\""),
+            ("other/main.ms", "0"),
+        ],
+    );
+    assert_eq!(
+        failure(dir.join("app")),
+        "Dependency `util` is declared with different paths"
+    );
+}
+
+#[test]
+fn missing_files() {
+    let dir = project("no-manifest", &[("main.ms", "0")]);
+    let path = dir.join("mystia.toml");
+    assert_eq!(
+        failure(dir),
+        format!("Failed to read manifest: {}", path.display())
+    );
+
+    let manifest = "[package]\nname = \"demo\"\nentry = \"src/main.ms\"";
+    let dir = project("no-entry", &[("mystia.toml", manifest), ("main.ms", "0")]);
+    let path = dir.join("src/main.ms");
+    assert_eq!(
+        failure(dir),
+        format!("Failed to read entry file: {}", path.display())
+    );
+
+    let manifest = "[package]\nname = \"app\"\n[dependencies]\nlib = { path = \"../lib\" }";
+    let dir = project(
+        "no-dependency",
+        &[("app/mystia.toml", manifest), ("app/main.ms", "0")],
+    );
+    let path = dir.join("app/../lib/mystia.toml");
+    assert_eq!(
+        failure(dir.join("app")),
+        format!("Failed to read manifest: {}", path.display())
+    );
+}
+
+#[test]
+fn invalid_manifest() {
+    for (test, manifest, message) in [
+        (
+            "no-name",
+            "[package]\nentry = \"main.ms\"",
+            "missing field `name`",
+        ),
+        (
+            "unknown-field",
+            "[package]\nname = \"demo\"\nsource = \"main.ms\"",
+            "unknown field `source`",
+        ),
+        (
+            "runtime",
+            "[package]\nname = \"demo\"\nruntime = \"deno\"",
+            "unknown variant `deno`",
+        ),
+    ] {
+        let dir = project(test, &[("mystia.toml", manifest), ("main.ms", "0")]);
+        let errmsg = failure(dir.clone());
+        let path = dir.join("mystia.toml");
+        assert!(
+            errmsg.starts_with(&format!("Invalid manifest {}: ", path.display())),
+            "{errmsg}"
+        );
+        assert!(errmsg.contains(message), "{errmsg}");
+    }
+}
+
+#[test]
+fn single_file_failure() {
+    // Compiling or emitting a file without the project exits with failure if it has errors
+    let dir = project(
+        "single",
+        &[("type.ms", "1 + \"x\""), ("syntax.ms", "let = 1")],
+    );
+    for (file, args) in [
+        ("type.ms", &[][..]),
+        ("syntax.ms", &[][..]),
+        ("syntax.ms", &["--emit", "expanded"][..]),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_mystia"))
+            .arg(dir.join(file))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{file} {args:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("error"), "{stderr}");
+    }
+}
@@ -0,0 +1,62 @@
+use mystia_core::{Block, Compiler, Node, Parser};
+use std::time::Instant;
+
+/// Number of lines in the generated program
+const LINES: usize = 10_000;
+
+/// Milliseconds that parsing and compiling the program may take at most in the release build,
+/// with the headroom for slower machines
+const BUDGET: [(&str, f64); 2] = [("parse", 500.0), ("compile", 3000.0)];
+
+/// Generate a program that has typical statements: functions, loops, literals and comments
+fn program() -> String {
+    let mut result = vec![String::from("load print(text: str): void;")];
+    let mut n = 0;
+    while result.len() < LINES {
+        result.extend([
+            format!("~~ unit {n} ~~"),
+            format!("let calc{n}(x: int, y: int) = {{"),
+            format!("    let z = (x + y) * {n} - x / 2;"),
+            "    if z > 100 then z % 7 else z + 1".to_string(),
+            "};".to_string(),
+            format!("let v{n} = @{{ id: {n}, name: \"item{n}\", scores: [1, 2, 3] }};"),
+            "let i = 0;".to_string(),
+            format!("while i < calc{n}(v{n}.id, 2) loop {{"),
+            format!("    let i + v{n}.scores[i % 3]"),
+            "};".to_string(),
+        ]);
+        n += 1;
+    }
+    result.push(String::from("print(\"done\")"));
+    result.join("\n")
+}
+
+/// Best time of several runs in milliseconds
+fn measure(task: impl Fn()) -> f64 {
+    let mut best = f64::MAX;
+    for _ in 0..3 {
+        let start = Instant::now();
+        task();
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+    best * 1000.0
+}
+
+fn main() {
+    let source = program();
+    let lines = source.lines().count();
+    let parse = measure(|| {
+        Block::parse(&mut Parser::new(&source)).expect("failed to parse");
+    });
+    let compile = measure(|| {
+        if let Err(diagnostics) = Compiler::new().build(&source) {
+            panic!("{:?}", diagnostics.first());
+        }
+    });
+    let mut is_over = false;
+    for ((task, budget), time) in BUDGET.into_iter().zip([parse, compile]) {
+        println!("{task} {lines} lines: {time:.3} ms (budget {budget} ms)");
+        is_over |= time > budget;
+    }
+    assert!(!is_over, "compiler exceeded the time budget");
+}
@@ -0,0 +1,451 @@
+use crate::*;
+
+/// Number of the size classes, whose blocks have the power of two bytes up to 2^31
+const CLASSES: i32 = 32;
+/// Bytes of the shadow stack, that has the objects local variables and temporaries refer
+const STACK: i32 = 1 << 17;
+
+// Header of the block in GC mode has the size class in the lowest bits,
+// followed by the mark, the kind of the object and the bitmap of the words referring objects
+const MARK: i32 = 1 << 5;
+const KIND: i32 = 6;
+/// Object having no references, like strings
+const RAW: i32 = 0;
+const FIELDS: i32 = 1 << KIND;
+const ELEMENTS: i32 = 2 << KIND;
+const PINNED: i32 = 3 << KIND;
+const FREE: i32 = 4 << KIND;
+const BITMAP: i32 = 9;
+/// Words at the start of the object that the bitmap can tell
+const BITMAP_WORDS: usize = 23;
+
+/// Heap allocator generated in the module, that's selected in compile time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Allocator {
+    /// Allocate by bumping the pointer, that never reuses the freed memory
+    Bump,
+    /// Reuse the freed blocks by the free lists of the size classes
+    #[default]
+    FreeList,
+    /// Free the unreachable objects by mark-sweep garbage collection on the free lists,
+    /// that runs when the heap is exhausted
+    Gc,
+}
+
+impl Allocator {
+    /// Code of `$malloc` and `$free` exported for the host and `$alloc` that the program uses
+    /// with the layout of the object, whose heap starts after the static data
+    pub fn code(&self, heap: i32, roots: &str) -> String {
+        let code = match self {
+            Allocator::Bump => format!(
+                "(global $allocator (export \"allocator\") (mut i32) (i32.const {heap}))
+                (func $malloc (export \"malloc\") (param $size i32) (result i32)
+                    (global.get $allocator)
+                    (global.set $allocator (i32.add (global.get $allocator) (local.get $size)))
+                    (call $mem/reserve (global.get $allocator)))
+                (func $free (export \"free\") (param $ptr i32))
+                (func $alloc (param $size i32) (param $layout i32) (result i32)
+                    (call $malloc (local.get $size)))"
+            ),
+            // Block has the size class in the header before the object, and the freed one
+            // has the next block of the list in place of the object. Null and static objects
+            // out of the heap are ignored by `$free`
+            Allocator::FreeList => format!(
+                "(global $allocator (export \"allocator\") (mut i32) (i32.const {start}))
+                (func $malloc (export \"malloc\") (param $size i32) (result i32)
+                    (local $class i32) (local $head i32) (local $block i32)
+                    (local.set $class (i32.sub (i32.const 32)
+                        (i32.clz (i32.add (local.get $size) (i32.const 3)))))
+                    (local.set $class (select (local.get $class) (i32.const 3)
+                        (i32.gt_u (local.get $class) (i32.const 3))))
+                    (local.set $head (i32.add (i32.const {heap})
+                        (i32.shl (local.get $class) (i32.const 2))))
+                    (local.set $block (i32.load (local.get $head)))
+                    (if (local.get $block)
+                        (then (i32.store (local.get $head)
+                            (i32.load (i32.add (local.get $block) (i32.const 4)))))
+                        (else
+                            (local.set $block (global.get $allocator))
+                            (global.set $allocator (i32.add (global.get $allocator)
+                                (i32.shl (i32.const 1) (local.get $class))))
+                            (call $mem/reserve (global.get $allocator))))
+                    (i32.store (local.get $block) (local.get $class))
+                    (i32.add (local.get $block) (i32.const 4)))
+                (func $free (export \"free\") (param $ptr i32) (local $head i32)
+                    (if (i32.or (i32.lt_u (local.get $ptr) (i32.const {start}))
+                        (i32.ge_u (local.get $ptr) (global.get $allocator)))
+                        (then (return)))
+                    (local.set $head (i32.add (i32.const {heap})
+                        (i32.shl (i32.load (i32.sub (local.get $ptr) (i32.const 4))) (i32.const 2))))
+                    (i32.store (local.get $ptr) (i32.load (local.get $head)))
+                    (i32.store (local.get $head) (i32.sub (local.get $ptr) (i32.const 4))))
+                (func $alloc (param $size i32) (param $layout i32) (result i32)
+                    (call $malloc (local.get $size)))",
+                start = heap + CLASSES * BYTES
+            ),
+            // Free lists are followed by the shadow stack, whose objects are the roots with the
+            // globals. Objects that the program allocates are pushed to it until the statement
+            // ends, and ones of the host are pinned not to be collected.
+            // Sweeping merges the runs of unreachable blocks into the largest ones, that are split
+            // again if neither the free list nor the unallocated space has the block needed.
+            // Memory grows only if the collection doesn't free it
+            Allocator::Gc => format!(
+                "(global $allocator (export \"allocator\") (mut i32) (i32.const {start}))
+                (func $gc/init (call $mem/reserve (i32.const {start})))
+                (start $gc/init)
+                (global $gc/sp (mut i32) (i32.const {stack}))
+                (global $gc/top (mut i32) (i32.const 0))
+                (global $gc/overflow (mut i32) (i32.const 0))
+                (func $malloc (export \"malloc\") (param $size i32) (result i32)
+                    (call $gc/alloc (local.get $size) (i32.const {PINNED})))
+                (func $alloc (param $size i32) (param $layout i32) (result i32)
+                    (call $gc/push (call $gc/alloc (local.get $size) (local.get $layout))))
+                (func $gc/alloc (param $size i32) (param $layout i32) (result i32)
+                    (local $class i32) (local $block i32)
+                    (local.set $class (i32.sub (i32.const 32)
+                        (i32.clz (i32.add (local.get $size) (i32.const 3)))))
+                    (local.set $class (select (local.get $class) (i32.const 3)
+                        (i32.gt_u (local.get $class) (i32.const 3))))
+                    (local.set $block (call $gc/take (local.get $class)))
+                    (if (i32.eqz (local.get $block)) (then
+                        (call $gc/collect)
+                        (local.set $block (call $gc/take (local.get $class)))
+                        (if (i32.eqz (local.get $block)) (then
+                            (local.set $block (global.get $allocator))
+                            (global.set $allocator (i32.add (global.get $allocator)
+                                (i32.shl (i32.const 1) (local.get $class))))
+                            (call $mem/reserve (global.get $allocator))))))
+                    (i32.store (local.get $block) (i32.or (local.get $class) (local.get $layout)))
+                    (memory.fill (i32.add (local.get $block) (i32.const 4)) (i32.const 0)
+                        (i32.sub (i32.shl (i32.const 1) (local.get $class)) (i32.const 4)))
+                    (i32.add (local.get $block) (i32.const 4)))
+                (func $gc/take (param $class i32) (result i32) (local $head i32) (local $block i32)
+                    (local.set $head (i32.add (i32.const {heap})
+                        (i32.shl (local.get $class) (i32.const 2))))
+                    (local.set $block (i32.load (local.get $head)))
+                    (if (local.get $block) (then
+                        (i32.store (local.get $head)
+                            (i32.load (i32.add (local.get $block) (i32.const 4))))
+                        (return (local.get $block))))
+                    (if (i32.le_u (i32.add (global.get $allocator) (i32.shl (i32.const 1) (local.get $class)))
+                        (i32.shl (memory.size) (i32.const 16)))
+                        (then
+                            (local.set $block (global.get $allocator))
+                            (global.set $allocator (i32.add (global.get $allocator)
+                                (i32.shl (i32.const 1) (local.get $class))))
+                            (return (local.get $block))))
+                    (call $gc/split (local.get $class)))
+                (func $gc/split (param $class i32) (result i32) (local $larger i32) (local $block i32)
+                    (local.set $larger (local.get $class))
+                    (loop $each
+                        (local.set $larger (i32.add (local.get $larger) (i32.const 1)))
+                        (if (i32.ge_u (local.get $larger) (i32.const {CLASSES}))
+                            (then (return (i32.const 0))))
+                        (local.set $block (i32.load (i32.add (i32.const {heap})
+                            (i32.shl (local.get $larger) (i32.const 2)))))
+                        (br_if $each (i32.eqz (local.get $block))))
+                    (i32.store (i32.add (i32.const {heap}) (i32.shl (local.get $larger) (i32.const 2)))
+                        (i32.load (i32.add (local.get $block) (i32.const 4))))
+                    (block $done (loop $each
+                        (br_if $done (i32.le_u (local.get $larger) (local.get $class)))
+                        (local.set $larger (i32.sub (local.get $larger) (i32.const 1)))
+                        (call $gc/carve (i32.add (local.get $block) (i32.shl (i32.const 1) (local.get $larger)))
+                            (i32.add (local.get $block) (i32.shl (i32.const 2) (local.get $larger))))
+                        (br $each)))
+                    (local.get $block))
+                (func $gc/carve (param $block i32) (param $end i32) (local $class i32)
+                    (block $done (loop $each
+                        (br_if $done (i32.ge_u (local.get $block) (local.get $end)))
+                        (local.set $class (i32.sub (i32.const 31)
+                            (i32.clz (i32.sub (local.get $end) (local.get $block)))))
+                        (i32.store (local.get $block) (local.get $class))
+                        (call $gc/release (local.get $block))
+                        (local.set $block (i32.add (local.get $block)
+                            (i32.shl (i32.const 1) (local.get $class))))
+                        (br $each))))
+                (func $free (export \"free\") (param $ptr i32)
+                    (if (i32.or (i32.lt_u (local.get $ptr) (i32.const {start}))
+                        (i32.ge_u (local.get $ptr) (global.get $allocator)))
+                        (then (return)))
+                    (call $gc/release (i32.sub (local.get $ptr) (i32.const 4))))
+                (func $gc/release (param $block i32) (local $class i32) (local $head i32)
+                    (local.set $class (i32.and (i32.load (local.get $block)) (i32.const 31)))
+                    (local.set $head (i32.add (i32.const {heap})
+                        (i32.shl (local.get $class) (i32.const 2))))
+                    (i32.store (local.get $block) (i32.or (local.get $class) (i32.const {FREE})))
+                    (i32.store (i32.add (local.get $block) (i32.const 4)) (i32.load (local.get $head)))
+                    (i32.store (local.get $head) (local.get $block)))
+                (func $gc/push (param $ptr i32) (result i32)
+                    (if (i32.ge_u (global.get $gc/sp) (i32.const {stack_end})) (then (unreachable)))
+                    (i32.store (global.get $gc/sp) (local.get $ptr))
+                    (global.set $gc/sp (i32.add (global.get $gc/sp) (i32.const 4)))
+                    (local.get $ptr))
+                (func $gc/enter (param $size i32) (result i32) (local $frame i32)
+                    (local.set $frame (global.get $gc/sp))
+                    (global.set $gc/sp (i32.add (local.get $frame) (local.get $size)))
+                    (if (i32.gt_u (global.get $gc/sp) (i32.const {stack_end})) (then (unreachable)))
+                    (memory.fill (local.get $frame) (i32.const 0) (local.get $size))
+                    (local.get $frame))
+                (func $gc/return (param $frame i32) (param $ptr i32) (result i32)
+                    (global.set $gc/sp (local.get $frame))
+                    (call $gc/push (local.get $ptr)))
+                (func $gc/mark (param $ptr i32) (local $header i32) (local $kind i32)
+                    (if (i32.or (i32.lt_u (local.get $ptr) (i32.const {start}))
+                        (i32.ge_u (local.get $ptr) (global.get $allocator)))
+                        (then (return)))
+                    (local.set $header (i32.load (i32.sub (local.get $ptr) (i32.const 4))))
+                    (local.set $kind (i32.and (local.get $header) (i32.const {kinds})))
+                    (if (i32.or (i32.and (local.get $header) (i32.const {MARK}))
+                        (i32.eq (local.get $kind) (i32.const {FREE})))
+                        (then (return)))
+                    (i32.store (i32.sub (local.get $ptr) (i32.const 4))
+                        (i32.or (local.get $header) (i32.const {MARK})))
+                    (if (i32.or (i32.eq (local.get $kind) (i32.const {FIELDS}))
+                        (i32.eq (local.get $kind) (i32.const {ELEMENTS})))
+                        (then (if (i32.lt_u (global.get $gc/top) (i32.const {stack_end}))
+                            (then
+                                (i32.store (global.get $gc/top) (local.get $ptr))
+                                (global.set $gc/top (i32.add (global.get $gc/top) (i32.const 4))))
+                            (else (global.set $gc/overflow (i32.const 1)))))))
+                (func $gc/scan (param $ptr i32) (param $header i32) (local $index i32) (local $bits i32)
+                    (if (i32.eq (i32.and (local.get $header) (i32.const {kinds})) (i32.const {ELEMENTS}))
+                        (then
+                            (local.set $bits (i32.load (local.get $ptr)))
+                            (block $done (loop $each
+                                (br_if $done (i32.ge_u (local.get $index) (local.get $bits)))
+                                (local.set $index (i32.add (local.get $index) (i32.const 1)))
+                                (call $gc/mark (i32.load (i32.add (local.get $ptr)
+                                    (i32.shl (local.get $index) (i32.const 2)))))
+                                (br $each)))
+                            (return)))
+                    (local.set $bits (i32.shr_u (local.get $header) (i32.const {BITMAP})))
+                    (block $done (loop $each
+                        (br_if $done (i32.eqz (local.get $bits)))
+                        (if (i32.and (local.get $bits) (i32.const 1))
+                            (then (call $gc/mark (i32.load (i32.add (local.get $ptr)
+                                (i32.shl (local.get $index) (i32.const 2)))))))
+                        (local.set $bits (i32.shr_u (local.get $bits) (i32.const 1)))
+                        (local.set $index (i32.add (local.get $index) (i32.const 1)))
+                        (br $each))))
+                (func $gc/trace (local $ptr i32) (local $block i32) (local $header i32)
+                    (loop $again
+                        (block $done (loop $each
+                            (br_if $done (i32.le_u (global.get $gc/top) (global.get $gc/sp)))
+                            (global.set $gc/top (i32.sub (global.get $gc/top) (i32.const 4)))
+                            (local.set $ptr (i32.load (global.get $gc/top)))
+                            (call $gc/scan (local.get $ptr)
+                                (i32.load (i32.sub (local.get $ptr) (i32.const 4))))
+                            (br $each)))
+                        (if (global.get $gc/overflow) (then
+                            (global.set $gc/overflow (i32.const 0))
+                            (local.set $block (i32.const {start}))
+                            (block $done (loop $each
+                                (br_if $done (i32.ge_u (local.get $block) (global.get $allocator)))
+                                (local.set $header (i32.load (local.get $block)))
+                                (if (i32.and (local.get $header) (i32.const {MARK}))
+                                    (then (call $gc/scan (i32.add (local.get $block) (i32.const 4))
+                                        (local.get $header))))
+                                (local.set $block (i32.add (local.get $block)
+                                    (i32.shl (i32.const 1) (i32.and (local.get $header) (i32.const 31)))))
+                                (br $each)))
+                            (br $again)))))
+                (func $gc/roots {roots})
+                (func $gc/collect (local $ptr i32) (local $block i32) (local $header i32) (local $run i32)
+                    (global.set $gc/top (global.get $gc/sp))
+                    (global.set $gc/overflow (i32.const 0))
+                    (call $gc/roots)
+                    (local.set $ptr (i32.const {stack}))
+                    (block $done (loop $each
+                        (br_if $done (i32.ge_u (local.get $ptr) (global.get $gc/sp)))
+                        (call $gc/mark (i32.load (local.get $ptr)))
+                        (local.set $ptr (i32.add (local.get $ptr) (i32.const 4)))
+                        (br $each)))
+                    (call $gc/trace)
+                    (memory.fill (i32.const {heap}) (i32.const 0) (i32.const {table}))
+                    (local.set $block (i32.const {start}))
+                    (block $done (loop $each
+                        (br_if $done (i32.ge_u (local.get $block) (global.get $allocator)))
+                        (local.set $header (i32.load (local.get $block)))
+                        (if (i32.or (i32.and (local.get $header) (i32.const {MARK}))
+                            (i32.eq (i32.and (local.get $header) (i32.const {kinds})) (i32.const {PINNED})))
+                            (then
+                                (i32.store (local.get $block)
+                                    (i32.and (local.get $header) (i32.const {unmark})))
+                                (if (local.get $run) (then
+                                    (call $gc/carve (local.get $run) (local.get $block))
+                                    (local.set $run (i32.const 0)))))
+                            (else (if (i32.eqz (local.get $run))
+                                (then (local.set $run (local.get $block))))))
+                        (local.set $block (i32.add (local.get $block)
+                            (i32.shl (i32.const 1) (i32.and (local.get $header) (i32.const 31)))))
+                        (br $each)))
+                    (if (local.get $run) (then (global.set $allocator (local.get $run)))))",
+                table = CLASSES * BYTES,
+                stack = heap + CLASSES * BYTES,
+                stack_end = heap + CLASSES * BYTES + STACK,
+                start = heap + CLASSES * BYTES + STACK,
+                kinds = 7 << KIND,
+                unmark = !MARK,
+            ),
+        };
+        code.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// Code of `$mem/reserve` that grows the memory to have the end address,
+    /// calling `$mem/abort` by the message if it can't, or trapping without it
+    pub fn reserve(message: Option<&str>) -> String {
+        let abort = message
+            .map(|message| format!("(call $mem/abort {message})"))
+            .unwrap_or_default();
+        let code = format!(
+            "(func $mem/reserve (param $end i32) (local $size i32)
+                (local.set $size (i32.shl (memory.size) (i32.const 16)))
+                (if (i32.gt_u (local.get $end) (local.get $size)) (then
+                    (if (i32.eq (i32.const -1) (memory.grow (i32.shr_u
+                        (i32.add (i32.sub (local.get $end) (local.get $size)) (i32.const 65535))
+                        (i32.const 16))))
+                        (then {abort} (unreachable))))))"
+        );
+        code.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+impl Compiler {
+    /// Call of `$alloc` for the object of the size and the layout
+    pub(crate) fn alloc(&mut self, size: i32, layout: i32) -> String {
+        self.allocates = true;
+        format!("(call $alloc (i32.const {size}) (i32.const {layout}))")
+    }
+
+    /// Layout of the object whose words have the types, telling GC which ones refer to objects
+    pub(crate) fn layout(&mut self, words: &[Type]) -> Option<i32> {
+        let mut bitmap = 0;
+        for (index, typ) in words.iter().enumerate() {
+            if !self.is_object(typ)? {
+                continue;
+            }
+            if index >= BITMAP_WORDS {
+                let errmsg =
+                    format!("GC can't trace objects referring others after {BITMAP_WORDS} fields");
+                let note = "put the fields referring objects first, or use other allocator";
+                self.report(Diagnostic::error(errmsg).with_note(note));
+                return None;
+            }
+            bitmap |= 1 << index;
+        }
+        Some(if bitmap == 0 {
+            RAW
+        } else {
+            FIELDS | bitmap << BITMAP
+        })
+    }
+
+    /// Layout of the array whose elements follow the length, the dictionary or the string
+    pub(crate) fn object_layout(&mut self, typ: &Type) -> Option<i32> {
+        match typ.type_infer(self)? {
+            Type::Array(element) => Some(if self.is_object(&element)? {
+                ELEMENTS
+            } else {
+                RAW
+            }),
+            Type::Dict(dict) => {
+                let fields = dict.values().map(|(_, typ)| typ.clone());
+                self.layout(&fields.collect::<Vec<_>>())
+            }
+            _ => Some(RAW),
+        }
+    }
+
+    /// Whether the value of the type is a pointer to the object that GC traces
+    fn is_object(&mut self, typ: &Type) -> Option<bool> {
+        let typ = typ.type_infer(self)?;
+        let is_ref = matches!(typ, Type::Function(_, _) | Type::Nullable(_));
+        Some(is_ptr!(typ, self) || typ.is_tagged() || is_ref)
+    }
+
+    /// Set the local variable to the value, that's kept in the frame of the shadow stack
+    /// if it refers an object in GC mode
+    pub(crate) fn set_local(&mut self, name: &str, value: String) -> Option<String> {
+        let typ = self
+            .variable_type
+            .get(name)
+            .or(self.argument_type.get(name));
+        if self.heap != Allocator::Gc || !self.is_object(&typ?.clone())? {
+            return Some(format!("(local.set ${name} {value})"));
+        }
+        let (slot, _) = self.stack_slots.insert_full(name.to_string());
+        Some(format!(
+            "(i32.store offset={} (local.get $gc/frame) (local.tee ${name} {value}))",
+            slot as i32 * BYTES
+        ))
+    }
+
+    /// Start compiling the function, returning the slots of the outer one
+    pub(crate) fn open_frame(&mut self) -> IndexSet<String> {
+        std::mem::take(&mut self.stack_slots)
+    }
+
+    /// Function body that allocates the frame of the shadow stack in GC mode, and that frees it
+    /// keeping the object returned unless it's exported, not to grow the stack by the host calls
+    pub(crate) fn close_frame(
+        &mut self,
+        outer: IndexSet<String>,
+        body: String,
+        ret: &Type,
+        is_export: bool,
+    ) -> Option<String> {
+        if self.heap != Allocator::Gc {
+            self.stack_slots = outer;
+            return Some(body);
+        }
+        // Arguments are kept too, not to lose the objects that only they refer after reassigned
+        let mut keep = vec![];
+        for (name, typ) in self.argument_type.clone() {
+            if self.is_object(&typ)? {
+                keep.push(self.set_local(&name, format!("(local.get ${name})"))?);
+            }
+        }
+        let size = std::mem::replace(&mut self.stack_slots, outer).len() as i32 * BYTES;
+        let enter = format!(
+            "(local.set $gc/frame (call $gc/enter (i32.const {size}))) {}",
+            join!(keep)
+        );
+        let leave = "(global.set $gc/sp (local.get $gc/frame))";
+        let ret = ret.type_infer(self)?;
+        Some(if let Type::Void = ret {
+            format!("(local $gc/frame i32) {enter} (block $gc/body {body}) {leave}")
+        } else if !is_export && self.is_object(&ret)? {
+            let body = format!("(block $gc/body (result i32) {body})");
+            format!("(local $gc/frame i32) {enter} (call $gc/return (local.get $gc/frame) {body})")
+        } else {
+            let typ = ret.compile(self)?;
+            let body = format!("(block $gc/body (result {typ}) {body})");
+            format!("(local $gc/frame i32) {enter} {body} {leave}")
+        })
+    }
+
+    /// Code saving the top of the shadow stack and restoring it to drop the temporary objects
+    /// that the statements allocated, or nothing unless it's GC mode
+    pub(crate) fn stack_mark(&mut self) -> (String, String) {
+        if self.heap != Allocator::Gc {
+            return (String::new(), String::new());
+        }
+        let mark = format!("mark.{}", self.variable_type.len());
+        self.variable_type.insert(mark.clone(), Type::Integer);
+        (
+            format!("(local.set ${mark} (global.get $gc/sp))"),
+            format!("(global.set $gc/sp (local.get ${mark}))"),
+        )
+    }
+
+    /// Code marking the roots other than the shadow stack, that are the global variables
+    pub(crate) fn gc_roots(&mut self) -> Option<String> {
+        let mut roots = vec![];
+        for (name, typ) in self.global_type.clone() {
+            if self.is_object(&typ)? {
+                roots.push(format!("(call $gc/mark (global.get ${name}))"));
+            }
+        }
+        Some(join!(roots))
+    }
+}
@@ -1,46 +1,148 @@
 use crate::*;
+use std::fmt::{self, Display};
 
 #[derive(Clone, Debug)]
-pub struct Block(pub Vec<Stmt>);
+pub struct Block(pub Vec<(Stmt, Span)>);
 
 impl Node for Block {
-    fn parse(source: &str) -> Option<Block> {
-        Some(Block(
-            tokenize(source, &[";"], false, false, false)?
-                .iter()
-                .map(|line| Stmt::parse(&line))
-                .collect::<Option<Vec<_>>>()?,
-        ))
+    fn parse(parser: &mut Parser) -> Option<Block> {
+        let mut result = vec![];
+        let mut is_failed = false;
+        loop {
+            let start = parser.span();
+            let stmt = Stmt::parse(parser);
+            let is_parsed = stmt.is_some();
+            match stmt {
+                Some(stmt) => result.push((stmt, parser.span_from(start))),
+                None => is_failed = true,
+            }
+            let is_end = parser.is_eof() || parser.is(&TokenKind::Symbol('}'));
+            if !is_end && !parser.is(&TokenKind::Symbol(';')) {
+                if is_parsed {
+                    let found = parser.found();
+                    parser.error::<()>(format!("expected `;` after statement, found {found}"));
+                }
+                // Recover at the end of the statement to report other errors
+                is_failed = true;
+                parser.skip_statement();
+            }
+            if !parser.eat(&TokenKind::Symbol(';')) {
+                break;
+            }
+            while parser.eat(&TokenKind::Symbol(';')) {}
+        }
+        (!is_failed).then_some(Block(result))
     }
 
     fn compile(&self, ctx: &mut Compiler) -> Option<String> {
         let mut result = vec![];
-        for (n, line) in self.0.iter().enumerate() {
+        let outer_span = ctx.span;
+        // Objects that the statement allocated are dropped after it, other than the last one
+        let (save, restore) = match self.0.len() {
+            0 | 1 => (String::new(), String::new()),
+            _ => ctx.stack_mark(),
+        };
+        result.push(save);
+        for (n, (line, span)) in self.0.iter().enumerate() {
+            ctx.span = Some(*span);
             let mut output = line.compile(ctx)?;
             if n != self.0.len() - 1 {
-                if !matches!(line.type_infer(ctx)?, Type::Void) {
+                let typ = match ctx.stmt_type.get(span) {
+                    Some(Some(typ)) => typ.clone(),
+                    _ => {
+                        let narrowed = ctx.narrowed.clone();
+                        let typ = line.type_infer(ctx)?;
+                        ctx.narrowed = narrowed;
+                        typ
+                    }
+                };
+                if !matches!(typ, Type::Void) {
                     output.push_str("(drop)");
                 }
+                output.push_str(&restore);
             }
             result.push(output);
         }
+        ctx.span = outer_span;
         Some(join!(result))
     }
 
     fn type_infer(&self, ctx: &mut Compiler) -> Option<Type> {
-        let var_ctx = ctx.variable_type.clone();
-        let fun_ctx = ctx.function_type.clone();
-        let mcr_ctx = ctx.macro_code.clone();
-
-        let Block(block) = self.clone();
-        let mut result = Type::Void;
-        for line in block {
-            result = line.type_infer(ctx)?;
+        // Scope is restored by dropping what's added after these lengths,
+        // and putting back the outer definitions that the statements shadow
+        let (var_len, fun_len, gen_len) = (
+            ctx.variable_type.len(),
+            ctx.function_type.len(),
+            ctx.generic_code.len(),
+        );
+        let shadowed = (self.0.iter())
+            .filter_map(|(line, _)| Block::defined(line))
+            .map(|name| {
+                let function = ctx.function_type.get(name).cloned();
+                (name.clone(), function, ctx.generic_code.get(name).cloned())
+            })
+            .collect::<Vec<_>>();
+        let outer_span = ctx.span;
+
+        let mut result = Some(Type::Void);
+        let mut is_failed = false;
+        for (n, (line, span)) in self.0.iter().enumerate() {
+            ctx.span = Some(*span);
+            result = line.type_infer(ctx);
+            if result.is_none() {
+                // Keep checking following statements, poisoning the failed definition
+                line.poison(ctx);
+                is_failed = true;
+            } else if let Some(typ) = result.as_ref().filter(|_| n != self.0.len() - 1) {
+                // Copies of the statement by macros or generics share the span,
+                // and it's inferred again in compiling if their types differ
+                let cached = ctx.stmt_type.entry(*span).or_insert(Some(typ.clone()));
+                if cached.as_ref() != Some(typ) {
+                    *cached = None;
+                }
+            }
         }
 
-        ctx.variable_type = var_ctx;
-        ctx.function_type = fun_ctx;
-        ctx.macro_code = mcr_ctx;
-        Some(result)
+        // Instances of generic functions are declared globally, so they outlive the block
+        let mut index = 0;
+        ctx.function_type.retain(|name, _| {
+            index += 1;
+            index <= fun_len || ctx.instance.contains_key(name)
+        });
+        ctx.variable_type.truncate(var_len);
+        ctx.generic_code.truncate(gen_len);
+        for (name, function, generic) in shadowed {
+            if let Some(function) = function {
+                ctx.function_type.insert(name.clone(), function);
+            }
+            if let Some(generic) = generic {
+                ctx.generic_code.insert(name, generic);
+            }
+        }
+        ctx.span = outer_span;
+        if is_failed { None } else { result }
+    }
+}
+
+impl Block {
+    /// Name of the function or the generic one that the statement defines in the block
+    fn defined(stmt: &Stmt) -> Option<&String> {
+        match stmt {
+            Stmt::Let(_, Expr::Call(name, _), _) => Some(name),
+            Stmt::Let(_, Expr::Operator(oper), _) => match &**oper {
+                Op::Cast(Expr::Call(name, _), _) => Some(name),
+                _ => None,
+            },
+            Stmt::Import(_, (name, _, _)) => Some(name),
+            Stmt::Generic(_, define) => Block::defined(define),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stmts = self.0.iter().map(|(stmt, _)| stmt.to_string());
+        write!(f, "{}", stmts.collect::<Vec<_>>().join("; "))
     }
 }
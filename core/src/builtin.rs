@@ -0,0 +1,318 @@
+use crate::*;
+
+/// Function generated in WebAssembly text format, that the module includes only if it's used,
+/// so that strings can be handled without functions imported from the host
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    /// New string joining two ones
+    Concat,
+    /// Order of two strings by the bytes: -1, 0 or 1
+    Compare,
+    /// Length of the string in bytes of UTF-8
+    Length,
+    /// Decimal notation of the 64-bit integer
+    FormatInt,
+    /// Decimal notation of the number, with 6 fractional digits at most
+    FormatNum,
+    /// Integer part of the number that the string starts with, or 0 if it doesn't
+    ParseInt,
+    /// Number that the string starts with, or NaN if it doesn't
+    ParseNum,
+    /// Count of the decimal digits
+    Digits,
+    /// Write the decimal digits backward from the end address
+    Write,
+    /// Number that the string starts with in double precision
+    Parse,
+    /// New object copying the bytes of the one, with the layout that GC traces it by
+    Copy,
+    /// Address of the element in the array, counting negative index from the end,
+    /// that aborts if it's out of the bounds
+    Index,
+}
+
+impl Builtin {
+    /// Builtin function that the program can call by the name, unless it defines or loads one
+    pub fn function(name: &str) -> Option<(Builtin, Vec<Type>, Type)> {
+        match name {
+            "strlen" => Some((Builtin::Length, vec![Type::String], Type::Integer)),
+            _ => None,
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Builtin::Concat => "$str/concat",
+            Builtin::Compare => "$str/compare",
+            Builtin::Length => "$str/length",
+            Builtin::FormatInt => "$str/format_int",
+            Builtin::FormatNum => "$str/format_num",
+            Builtin::ParseInt => "$str/parse_int",
+            Builtin::ParseNum => "$str/parse_num",
+            Builtin::Digits => "$str/digits",
+            Builtin::Write => "$str/write",
+            Builtin::Parse => "$str/parse",
+            Builtin::Copy => "$mem/copy",
+            Builtin::Index => "$mem/index",
+        }
+    }
+
+    /// Whether the code allocates objects in the heap
+    pub fn allocates(&self) -> bool {
+        matches!(
+            self,
+            Builtin::Concat
+                | Builtin::FormatInt
+                | Builtin::FormatNum
+                | Builtin::Copy
+                | Builtin::Index
+        )
+    }
+
+    /// Other builtin functions that the code calls
+    fn requires(&self) -> &'static [Builtin] {
+        match self {
+            Builtin::Concat => &[Builtin::Length],
+            Builtin::FormatInt | Builtin::FormatNum => &[Builtin::Digits, Builtin::Write],
+            Builtin::ParseInt | Builtin::ParseNum => &[Builtin::Parse],
+            Builtin::Index => &[Builtin::Concat, Builtin::FormatInt],
+            _ => &[],
+        }
+    }
+
+    pub fn code(&self) -> String {
+        let code = match self {
+            Builtin::Concat => {
+                "(func $str/concat (param $a i32) (param $b i32) (result i32)
+                    (local $a_len i32) (local $b_len i32) (local $str i32)
+                    (local.set $a_len (call $str/length (local.get $a)))
+                    (local.set $b_len (call $str/length (local.get $b)))
+                    (local.set $str (call $alloc
+                        (i32.add (i32.add (local.get $a_len) (local.get $b_len)) (i32.const 1))
+                        (i32.const 0)))
+                    (memory.copy (local.get $str) (local.get $a) (local.get $a_len))
+                    (memory.copy (i32.add (local.get $str) (local.get $a_len))
+                        (local.get $b) (local.get $b_len))
+                    (i32.store8 (i32.add (local.get $str)
+                        (i32.add (local.get $a_len) (local.get $b_len))) (i32.const 0))
+                    (local.get $str))"
+            }
+            Builtin::Compare => {
+                "(func $str/compare (param $a i32) (param $b i32) (result i32)
+                    (local $x i32) (local $y i32)
+                    (loop $scan
+                        (local.set $x (i32.load8_u (local.get $a)))
+                        (local.set $y (i32.load8_u (local.get $b)))
+                        (if (i32.and (i32.eq (local.get $x) (local.get $y)) (i32.ne (local.get $x) (i32.const 0)))
+                            (then
+                                (local.set $a (i32.add (local.get $a) (i32.const 1)))
+                                (local.set $b (i32.add (local.get $b) (i32.const 1)))
+                                (br $scan))))
+                    (i32.sub (i32.gt_u (local.get $x) (local.get $y))
+                        (i32.lt_u (local.get $x) (local.get $y))))"
+            }
+            Builtin::Length => {
+                "(func $str/length (param $str i32) (result i32) (local $end i32)
+                    (local.set $end (local.get $str))
+                    (block $done (loop $scan
+                        (br_if $done (i32.eqz (i32.load8_u (local.get $end))))
+                        (local.set $end (i32.add (local.get $end) (i32.const 1)))
+                        (br $scan)))
+                    (i32.sub (local.get $end) (local.get $str)))"
+            }
+            // Minus sign is overwritten by the digits if the integer isn't negative
+            Builtin::FormatInt => {
+                "(func $str/format_int (param $n i64) (result i32)
+                    (local $abs i64) (local $len i32) (local $str i32)
+                    (local.set $abs (select (i64.sub (i64.const 0) (local.get $n)) (local.get $n)
+                        (i64.lt_s (local.get $n) (i64.const 0))))
+                    (local.set $len (i32.add (call $str/digits (local.get $abs))
+                        (i64.lt_s (local.get $n) (i64.const 0))))
+                    (local.set $str (call $alloc (i32.add (local.get $len) (i32.const 1)) (i32.const 0)))
+                    (i32.store8 (local.get $str) (i32.const 45))
+                    (call $str/write (i32.add (local.get $str) (local.get $len))
+                        (local.get $abs) (call $str/digits (local.get $abs)))
+                    (i32.store8 (i32.add (local.get $str) (local.get $len)) (i32.const 0))
+                    (local.get $str))"
+            }
+            // Fraction is rounded to 6 digits, and the trailing zeros are trimmed like JavaScript
+            Builtin::FormatNum => {
+                "(func $str/format_num (param $x f32) (result i32)
+                    (local $abs f64) (local $int i64) (local $frac i64) (local $width i32)
+                    (local $sign i32) (local $digits i32) (local $len i32) (local $str i32)
+                    (local.set $abs (f64.abs (f64.promote_f32 (local.get $x))))
+                    (local.set $sign (f32.lt (local.get $x) (f32.const 0)))
+                    (if (f64.ne (local.get $abs) (local.get $abs)) (then
+                        (local.set $str (call $alloc (i32.const 4) (i32.const 0)))
+                        (i32.store (local.get $str) (i32.const 0x004E614E))
+                        (return (local.get $str))))
+                    (if (f64.eq (local.get $abs) (f64.const inf)) (then
+                        (local.set $str (call $alloc
+                            (i32.add (local.get $sign) (i32.const 9)) (i32.const 0)))
+                        (i32.store8 (local.get $str) (i32.const 45))
+                        (i64.store (i32.add (local.get $str) (local.get $sign))
+                            (i64.const 0x7974696E69666E49))
+                        (i32.store8 (i32.add (local.get $str) (i32.add (local.get $sign) (i32.const 8)))
+                            (i32.const 0))
+                        (return (local.get $str))))
+                    (local.set $int (i64.trunc_sat_f64_u (local.get $abs)))
+                    (local.set $frac (i64.trunc_sat_f64_u (f64.nearest (f64.mul
+                        (f64.sub (local.get $abs) (f64.convert_i64_u (local.get $int)))
+                        (f64.const 1000000)))))
+                    (if (i64.ge_u (local.get $frac) (i64.const 1000000)) (then
+                        (local.set $int (i64.add (local.get $int) (i64.const 1)))
+                        (local.set $frac (i64.const 0))))
+                    (local.set $width (select (i32.const 0) (i32.const 6) (i64.eqz (local.get $frac))))
+                    (block $trimmed (loop $trim
+                        (br_if $trimmed (i32.eqz (local.get $width)))
+                        (br_if $trimmed (i64.ne (i64.rem_u (local.get $frac) (i64.const 10)) (i64.const 0)))
+                        (local.set $frac (i64.div_u (local.get $frac) (i64.const 10)))
+                        (local.set $width (i32.sub (local.get $width) (i32.const 1)))
+                        (br $trim)))
+                    (local.set $sign (i32.and (local.get $sign)
+                        (i32.or (i64.ne (local.get $int) (i64.const 0)) (i32.ne (local.get $width) (i32.const 0)))))
+                    (local.set $digits (i32.add (local.get $sign) (call $str/digits (local.get $int))))
+                    (local.set $len (i32.add (local.get $digits)
+                        (select (i32.add (local.get $width) (i32.const 1)) (i32.const 0) (local.get $width))))
+                    (local.set $str (call $alloc (i32.add (local.get $len) (i32.const 1)) (i32.const 0)))
+                    (i32.store8 (local.get $str) (i32.const 45))
+                    (call $str/write (i32.add (local.get $str) (local.get $digits))
+                        (local.get $int) (i32.sub (local.get $digits) (local.get $sign)))
+                    (if (local.get $width) (then
+                        (i32.store8 (i32.add (local.get $str) (local.get $digits)) (i32.const 46))
+                        (call $str/write (i32.add (local.get $str) (local.get $len))
+                            (local.get $frac) (local.get $width))))
+                    (i32.store8 (i32.add (local.get $str) (local.get $len)) (i32.const 0))
+                    (local.get $str))"
+            }
+            Builtin::ParseInt => {
+                "(func $str/parse_int (param $str i32) (result i32)
+                    (i32.trunc_sat_f64_s (call $str/parse (local.get $str))))"
+            }
+            Builtin::ParseNum => {
+                "(func $str/parse_num (param $str i32) (result f32)
+                    (f32.demote_f64 (call $str/parse (local.get $str))))"
+            }
+            Builtin::Digits => {
+                "(func $str/digits (param $n i64) (result i32) (local $count i32)
+                    (loop $digit
+                        (local.set $count (i32.add (local.get $count) (i32.const 1)))
+                        (local.set $n (i64.div_u (local.get $n) (i64.const 10)))
+                        (br_if $digit (i64.ne (local.get $n) (i64.const 0))))
+                    (local.get $count))"
+            }
+            Builtin::Write => {
+                "(func $str/write (param $end i32) (param $n i64) (param $width i32)
+                    (block $done (loop $digit
+                        (br_if $done (i32.eqz (local.get $width)))
+                        (local.set $end (i32.sub (local.get $end) (i32.const 1)))
+                        (i32.store8 (local.get $end) (i32.add (i32.const 48)
+                            (i32.wrap_i64 (i64.rem_u (local.get $n) (i64.const 10)))))
+                        (local.set $n (i64.div_u (local.get $n) (i64.const 10)))
+                        (local.set $width (i32.sub (local.get $width) (i32.const 1)))
+                        (br $digit))))"
+            }
+            // Leading spaces, sign, digits with a decimal point and exponent, like `parseFloat`
+            Builtin::Parse => {
+                "(func $str/parse (param $str i32) (result f64)
+                    (local $c i32) (local $sign f64) (local $value f64) (local $digits i32)
+                    (local $is_fraction i32) (local $exp i32) (local $exp_sign i32) (local $n i32)
+                    (local.set $sign (f64.const 1))
+                    (block $start (loop $space
+                        (local.set $c (i32.load8_u (local.get $str)))
+                        (br_if $start (i32.eqz (i32.or (i32.eq (local.get $c) (i32.const 32))
+                            (i32.lt_u (i32.sub (local.get $c) (i32.const 9)) (i32.const 5)))))
+                        (local.set $str (i32.add (local.get $str) (i32.const 1)))
+                        (br $space)))
+                    (if (i32.eq (local.get $c) (i32.const 45)) (then (local.set $sign (f64.const -1))))
+                    (if (i32.or (i32.eq (local.get $c) (i32.const 43)) (i32.eq (local.get $c) (i32.const 45)))
+                        (then (local.set $str (i32.add (local.get $str) (i32.const 1)))))
+                    (block $end (loop $digit
+                        (local.set $c (i32.load8_u (local.get $str)))
+                        (local.set $str (i32.add (local.get $str) (i32.const 1)))
+                        (if (i32.and (i32.eq (local.get $c) (i32.const 46)) (i32.eqz (local.get $is_fraction)))
+                            (then (local.set $is_fraction (i32.const 1)) (br $digit)))
+                        (br_if $end (i32.gt_u (i32.sub (local.get $c) (i32.const 48)) (i32.const 9)))
+                        (local.set $value (f64.add (f64.mul (local.get $value) (f64.const 10))
+                            (f64.convert_i32_u (i32.sub (local.get $c) (i32.const 48)))))
+                        (local.set $digits (i32.add (local.get $digits) (i32.const 1)))
+                        (local.set $exp (i32.sub (local.get $exp) (local.get $is_fraction)))
+                        (br $digit)))
+                    (if (i32.eqz (local.get $digits)) (then (return (f64.const nan))))
+                    (if (i32.eq (i32.or (local.get $c) (i32.const 32)) (i32.const 101)) (then
+                        (local.set $c (i32.load8_u (local.get $str)))
+                        (local.set $exp_sign (select (i32.const -1) (i32.const 1)
+                            (i32.eq (local.get $c) (i32.const 45))))
+                        (if (i32.or (i32.eq (local.get $c) (i32.const 43)) (i32.eq (local.get $c) (i32.const 45)))
+                            (then (local.set $str (i32.add (local.get $str) (i32.const 1)))))
+                        (block $exp_end (loop $exp_digit
+                            (local.set $c (i32.sub (i32.load8_u (local.get $str)) (i32.const 48)))
+                            (br_if $exp_end (i32.gt_u (local.get $c) (i32.const 9)))
+                            (local.set $n (i32.add (i32.mul (local.get $n) (i32.const 10)) (local.get $c)))
+                            (local.set $n (select (i32.const 1000) (local.get $n)
+                                (i32.gt_s (local.get $n) (i32.const 1000))))
+                            (local.set $str (i32.add (local.get $str) (i32.const 1)))
+                            (br $exp_digit)))
+                        (local.set $exp (i32.add (local.get $exp)
+                            (i32.mul (local.get $exp_sign) (local.get $n))))))
+                    (block $scaled (loop $scale
+                        (br_if $scaled (i32.le_s (local.get $exp) (i32.const 0)))
+                        (local.set $value (f64.mul (local.get $value) (f64.const 10)))
+                        (local.set $exp (i32.sub (local.get $exp) (i32.const 1)))
+                        (br $scale)))
+                    (block $scaled (loop $scale
+                        (br_if $scaled (i32.ge_s (local.get $exp) (i32.const 0)))
+                        (local.set $value (f64.div (local.get $value) (f64.const 10)))
+                        (local.set $exp (i32.add (local.get $exp) (i32.const 1)))
+                        (br $scale)))
+                    (f64.mul (local.get $sign) (local.get $value)))"
+            }
+            Builtin::Copy => {
+                "(func $mem/copy (param $from i32) (param $size i32) (param $layout i32) (result i32)
+                    (local $to i32)
+                    (local.set $to (call $alloc (local.get $size) (local.get $layout)))
+                    (memory.copy (local.get $to) (local.get $from) (local.get $size))
+                    (local.get $to))"
+            }
+            // Message is copied from the passive data segment, only when it aborts
+            Builtin::Index => {
+                "(data $mem/out_of_bounds \"index out of bounds: index \\00, length \\00\")
+                (func $mem/index (param $array i32) (param $index i32) (result i32)
+                    (local $length i32) (local $at i32) (local $text i32)
+                    (local.set $length (i32.load (local.get $array)))
+                    (local.set $at (local.get $index))
+                    (if (i32.lt_s (local.get $at) (i32.const 0))
+                        (then (local.set $at (i32.add (local.get $at) (local.get $length)))))
+                    (if (i32.ge_u (local.get $at) (local.get $length)) (then
+                        (local.set $text (call $alloc (i32.const 38) (i32.const 0)))
+                        (memory.init $mem/out_of_bounds (local.get $text) (i32.const 0) (i32.const 38))
+                        (call $mem/abort (call $str/concat
+                            (call $str/concat
+                                (call $str/concat (local.get $text)
+                                    (call $str/format_int (i64.extend_i32_s (local.get $index))))
+                                (i32.add (local.get $text) (i32.const 28)))
+                            (call $str/format_int (i64.extend_i32_s (local.get $length)))))
+                        (unreachable)))
+                    (i32.add (local.get $array)
+                        (i32.shl (i32.add (local.get $at) (i32.const 1)) (i32.const 2))))"
+            }
+        };
+        code.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+impl Compiler {
+    /// Call the builtin function by the compiled arguments, including it in the module
+    pub(crate) fn call_builtin(&mut self, builtin: Builtin, args: &[String]) -> String {
+        self.include_builtin(builtin);
+        format!("(call {} {})", builtin.id(), join!(args))
+    }
+
+    fn include_builtin(&mut self, builtin: Builtin) {
+        if self.builtins.insert(builtin) {
+            for required in builtin.requires() {
+                self.include_builtin(*required);
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::*;
+use std::fmt::{self, Display};
+
+/// Lossless concrete syntax tree, that keeps whitespaces and comments
+/// so that printing it reproduces the source code exactly
+#[derive(Clone, Debug)]
+pub struct Cst {
+    pub items: Vec<Syntax>,
+    /// Whitespaces and comments after the last token
+    pub trailing: Vec<Trivia>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Syntax {
+    Leaf(Leaf),
+    /// Bracketed group `( ... )`, `[ ... ]` or `{ ... }` that might be unclosed
+    Group(Leaf, Vec<Syntax>, Option<Leaf>),
+}
+
+/// Token with its text and the trivia before it
+#[derive(Clone, Debug)]
+pub struct Leaf {
+    pub trivia: Vec<Trivia>,
+    pub token: Token,
+    pub text: String,
+}
+
+/// Text between tokens, that the abstract syntax tree drops
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trivia {
+    Whitespace(String),
+    /// Comment including the delimiters `~~ ... ~~`
+    Comment(String),
+}
+
+impl Cst {
+    pub fn parse(source: &str) -> Result<Cst, Vec<Diagnostic>> {
+        let (tokens, errors) = tokenize(source, 0);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let mut trivia = vec![];
+        let mut last_end = 0;
+        let mut stack: Vec<(Leaf, Vec<Syntax>)> = vec![];
+        let mut items = vec![];
+        for token in tokens {
+            let Span { start, end } = token.span;
+            if last_end < start {
+                trivia.push(Trivia::Whitespace(source[last_end..start].to_string()));
+            }
+            last_end = end;
+            let text = source[start..end].to_string();
+            if let TokenKind::Comment(_) = token.kind {
+                trivia.push(Trivia::Comment(text));
+                continue;
+            }
+            let trivia = std::mem::take(&mut trivia);
+            let leaf = Leaf {
+                trivia,
+                token,
+                text,
+            };
+            match leaf.token.kind {
+                TokenKind::Symbol('(' | '[' | '{') => {
+                    stack.push((leaf, std::mem::take(&mut items)));
+                }
+                TokenKind::Symbol(c @ (')' | ']' | '}'))
+                    if stack.last().map(|(open, _)| open.closer()) == Some(Some(c)) =>
+                {
+                    let (open, outer) = stack.pop().unwrap();
+                    let inner = std::mem::replace(&mut items, outer);
+                    items.push(Syntax::Group(open, inner, Some(leaf)));
+                }
+                _ => items.push(Syntax::Leaf(leaf)),
+            }
+        }
+        while let Some((open, outer)) = stack.pop() {
+            let inner = std::mem::replace(&mut items, outer);
+            items.push(Syntax::Group(open, inner, None));
+        }
+        if last_end < source.len() {
+            trivia.push(Trivia::Whitespace(source[last_end..].to_string()));
+        }
+        Ok(Cst {
+            items,
+            trailing: trivia,
+        })
+    }
+}
+
+impl Leaf {
+    /// Closing bracket of the group that this token opens
+    pub fn closer(&self) -> Option<char> {
+        match self.token.kind {
+            TokenKind::Symbol('(') => Some(')'),
+            TokenKind::Symbol('[') => Some(']'),
+            TokenKind::Symbol('{') => Some('}'),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Cst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.items {
+            write!(f, "{item}")?;
+        }
+        for trivia in &self.trailing {
+            write!(f, "{trivia}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Syntax::Leaf(leaf) => write!(f, "{leaf}"),
+            Syntax::Group(open, items, close) => {
+                write!(f, "{open}")?;
+                for item in items {
+                    write!(f, "{item}")?;
+                }
+                if let Some(close) = close {
+                    write!(f, "{close}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for Leaf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for trivia in &self.trivia {
+            write!(f, "{trivia}")?;
+        }
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Display for Trivia {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trivia::Whitespace(text) | Trivia::Comment(text) => write!(f, "{text}"),
+        }
+    }
+}
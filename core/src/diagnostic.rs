@@ -0,0 +1,168 @@
+use crate::*;
+
+/// Byte range in the source code
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/// Secondary message attached to other place of the source code
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Problem found in the source code while parsing, checking or compiling
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    /// Path of the imported file that the spans point, or none if it's the main one
+    pub file: Option<String>,
+}
+
+/// Node of the syntax tree that may know where it's written in the source code
+pub trait Locate {
+    fn span(&self) -> Option<Span> {
+        None
+    }
+}
+
+impl Locate for Type {}
+
+impl Locate for Expr {
+    fn span(&self) -> Option<Span> {
+        match self {
+            Expr::Spanned(_, span) => Some(*span),
+            _ => None,
+        }
+    }
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Line and column number (both 1-origin) where the span starts
+    pub fn location(&self, source: &str) -> (usize, usize) {
+        let before = source.get(..self.start).unwrap_or(source);
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        (line, column)
+    }
+
+    pub fn contains(&self, other: &Span) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl ToString) -> Self {
+        Diagnostic {
+            severity,
+            message: message.to_string(),
+            span: None,
+            labels: vec![],
+            notes: vec![],
+            file: None,
+        }
+    }
+
+    pub fn error(message: impl ToString) -> Self {
+        Diagnostic::new(Severity::Error, message)
+    }
+
+    pub fn with_span(mut self, span: Option<Span>) -> Self {
+        self.span = self.span.or(span);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl ToString) -> Self {
+        let message = message.to_string();
+        self.labels.push(Label { span, message });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl ToString) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Render as human readable message with caret-underlined source snippets
+    pub fn render(&self, source: &str, path: &str) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        };
+        let mut result = vec![format!("{severity}: {}", self.message)];
+        let mut gutter = String::from(" ");
+        if let Some(span) = self.span {
+            gutter = " ".repeat(span.location(source).0.to_string().len());
+            let (line, column) = span.location(source);
+            result.push(format!(" --> {path}:{line}:{column}"));
+            result.extend(snippet(source, span, '^', ""));
+        }
+        for label in &self.labels {
+            result.extend(snippet(source, label.span, '-', &label.message));
+        }
+        for note in &self.notes {
+            result.push(format!("{gutter} = note: {note}"));
+        }
+        result.join("\n")
+    }
+}
+
+/// Underline the span in the first line it covers
+fn snippet(source: &str, span: Span, marker: char, message: &str) -> Vec<String> {
+    let (line, column) = span.location(source);
+    let text = source.lines().nth(line - 1).unwrap_or_default();
+    let width = text.chars().count().saturating_sub(column - 1);
+    let length = source.get(span.start..span.end).unwrap_or_default();
+    let length = length.lines().next().unwrap_or_default().chars().count();
+    let gutter = " ".repeat(line.to_string().len());
+    vec![
+        format!("{gutter} |"),
+        format!("{line} | {text}"),
+        format!(
+            "{gutter} | {}{} {message}",
+            " ".repeat(column - 1),
+            marker.to_string().repeat(length.clamp(1, width.max(1)))
+        )
+        .trim_end()
+        .to_string(),
+    ]
+}
+
+impl Compiler {
+    /// Record the diagnostic, attaching span of the statement being processed
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        let diagnostic = self.locate(diagnostic.with_span(self.span));
+        if !self.diagnostics.contains(&diagnostic) {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Render the diagnostic in the source code of the file it came from,
+    /// that's the main one at the path unless it's imported
+    pub fn render(&self, diagnostic: &Diagnostic, source: &str, path: &str) -> String {
+        let file = diagnostic.file.as_ref();
+        match file.and_then(|file| Some((file, self.modules.get(file)?))) {
+            Some((file, module)) => diagnostic.render(&module.source, file),
+            None => diagnostic.render(source, path),
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use crate::*;
+use std::fmt::{self, Display};
+
+/// Parameter of the macro. The argument is substituted in the body as it is,
+/// or evaluated once and bound to a local variable if it's marked by `let`
+#[derive(Clone, Debug)]
+pub struct MacroParam {
+    pub name: String,
+    pub is_bound: bool,
+    /// Whether it takes the rest of arguments `...name`
+    pub is_rest: bool,
+    /// Expression used if the argument is omitted, that can refer the preceding parameters
+    pub default: Option<Expr>,
+}
+
+impl Compiler {
+    /// Parse the program, import the modules and expand the macro calls in its syntax tree
+    pub fn expand(&mut self, source: &str) -> Result<Block, Vec<Diagnostic>> {
+        let ast = parse_source(source)?;
+        // Local variables renamed in the macro bodies avoid every name in the program
+        for token in tokenize(source, 0).0 {
+            if let TokenKind::Identifier(name) = token.kind {
+                self.macro_names.insert(name);
+            }
+        }
+        let main = Module::new(source, 0, "");
+        self.modules.insert(self.file.clone(), main);
+        let ast = ast.expand_file(self);
+        if let Some(main) = self.modules.first_mut() {
+            main.1.is_expanding = false;
+        }
+        ast.ok_or_else(|| {
+            if self.diagnostics.is_empty() {
+                let msg = "failed to parse, import or expand macros";
+                self.report(Diagnostic::error(msg));
+            }
+            self.diagnostics.clone()
+        })
+    }
+
+    /// Name of the local variable bound in the macro body, that's renamed for hygiene.
+    /// It shadows the definition of the file that has the same name
+    fn bind(&mut self, name: &str) -> String {
+        if self.macro_stack.is_empty() {
+            self.namespace.shift_remove(name);
+            return name.to_string();
+        }
+        let renamed = self.fresh(name);
+        self.macro_scope
+            .insert(name.to_string(), Expr::Variable(renamed.clone()));
+        renamed
+    }
+
+    /// Name suffixed by the number, that no other name in the program has
+    fn fresh(&mut self, name: &str) -> String {
+        let renamed = (0..)
+            .map(|n| format!("{name}_{n}"))
+            .find(|x| !self.macro_names.contains(x))
+            .unwrap_or_default();
+        self.macro_names.insert(renamed.clone());
+        renamed
+    }
+}
+
+impl Block {
+    pub fn expand(&self, ctx: &mut Compiler) -> Option<Block> {
+        let mcr_ctx = ctx.macro_code.clone();
+        let scope_ctx = ctx.macro_scope.clone();
+        let name_ctx = ctx.namespace.clone();
+        let visible_ctx = ctx.macro_visible.clone();
+        let outer_span = ctx.span;
+
+        let mut result = vec![];
+        let mut is_failed = false;
+        for (stmt, span) in &self.0 {
+            ctx.span = Some(*span);
+            match stmt.expand(ctx) {
+                Some(stmt) => result.push((stmt, *span)),
+                None => is_failed = true,
+            }
+        }
+
+        ctx.macro_code = mcr_ctx;
+        ctx.macro_scope = scope_ctx;
+        ctx.namespace = name_ctx;
+        ctx.macro_visible = visible_ctx;
+        ctx.span = outer_span;
+        if is_failed { None } else { Some(Block(result)) }
+    }
+}
+
+impl Stmt {
+    pub(crate) fn expand(&self, ctx: &mut Compiler) -> Option<Stmt> {
+        Some(match self {
+            Stmt::Expr(expr) => Stmt::Expr(expr.expand(ctx)?),
+            Stmt::Let(Scope::Local, Expr::Variable(name), value) => {
+                let value = value.expand(ctx)?;
+                // Macro body assigns the variable of the caller, and binds a new one otherwise
+                let is_caller = !ctx.macro_stack.is_empty() && ctx.macro_visible.contains(name);
+                if !ctx.macro_scope.contains_key(name) && !is_caller {
+                    ctx.bind(name);
+                }
+                let name = Expr::Variable(name.clone()).expand(ctx)?;
+                if let Expr::Variable(name) = &name {
+                    ctx.macro_visible.insert(name.clone());
+                }
+                Stmt::Let(Scope::Local, name, value)
+            }
+            // Function definition, whose signature isn't a call
+            Stmt::Let(scope, sig @ (Expr::Call(_, _) | Expr::Operator(_)), body) => {
+                let Some((Expr::Call(name, args), ret)) = Stmt::signature(sig) else {
+                    let errmsg =
+                        "left side of `let` must be a variable, field, index or function signature";
+                    ctx.report(Diagnostic::error(errmsg).with_note(format!("found `{sig}`")));
+                    // Kept as poisoned one that fails type inference without other errors
+                    return Some(Stmt::Let(*scope, sig.clone(), body.expand(ctx)?));
+                };
+                let name_ctx = ctx.namespace.clone();
+                let visible_ctx = std::mem::take(&mut ctx.macro_visible);
+                let mut params = vec![];
+                for arg in args {
+                    params.push(match arg {
+                        Expr::Operator(oper) => Expr::Operator(Box::new(match *oper {
+                            Op::Cast(arg, typ) => Op::Cast(arg, typ.expand(ctx)?),
+                            oper => oper,
+                        })),
+                        arg => arg,
+                    });
+                }
+                // Arguments shadow the definitions of the file
+                for arg in &params {
+                    let name = match arg {
+                        Expr::Operator(oper) => match &**oper {
+                            Op::Cast(Expr::Variable(name), _) => name,
+                            _ => continue,
+                        },
+                        Expr::Variable(name) => name,
+                        _ => continue,
+                    };
+                    ctx.namespace.shift_remove(name);
+                    ctx.macro_visible.insert(name.clone());
+                }
+                let body = body.expand(ctx);
+                ctx.namespace = name_ctx;
+                ctx.macro_visible = visible_ctx;
+                let mut sig = Expr::Call(ctx.resolve_name(&name)?, params);
+                if let Some(ret) = ret {
+                    sig = Expr::Operator(Box::new(Op::Cast(sig, ret.expand(ctx)?)));
+                }
+                Stmt::Let(*scope, sig, body?)
+            }
+            Stmt::Let(scope, name, value) => {
+                Stmt::Let(*scope, name.expand(ctx)?, value.expand(ctx)?)
+            }
+            Stmt::If(cond, then, r#else) => Stmt::If(
+                cond.expand(ctx)?,
+                then.expand(ctx)?,
+                match r#else {
+                    Some(r#else) => Some(Box::new(r#else.expand(ctx)?)),
+                    None => None,
+                },
+            ),
+            Stmt::While(cond, body) => Stmt::While(cond.expand(ctx)?, body.expand(ctx)?),
+            Stmt::For(name, iter, body) => {
+                // Iteration over the rest parameter is unrolled for each argument,
+                // in the loop that counts them so that `break` and `next` leave the body
+                if let Iter::Array(Expr::Variable(rest)) = iter
+                    && let Some(Expr::Literal(Value::Array(args))) =
+                        ctx.macro_scope.get(&format!("...{rest}")).cloned()
+                {
+                    let span = ctx.span.unwrap_or_default();
+                    let step = Expr::Variable(ctx.fresh("step"));
+                    let int = |n: usize| Expr::Literal(Value::Integer(n as i32));
+                    let operator = |oper: Op| Expr::Operator(Box::new(oper));
+                    let advance = operator(Op::Add(step.clone(), int(1)));
+                    let mut result = vec![(Stmt::Let(Scope::Local, step.clone(), advance), span)];
+                    for (index, arg) in args.iter().enumerate() {
+                        let scope_ctx = ctx.macro_scope.clone();
+                        ctx.macro_scope.insert(name.clone(), arg.clone());
+                        let body = body.expand(ctx);
+                        ctx.macro_scope = scope_ctx;
+                        let is_current = operator(Op::Eql(step.clone(), int(index + 1)));
+                        result.push((Stmt::If(is_current, body?, None), span));
+                    }
+                    let is_rest = operator(Op::Lt(step.clone(), int(args.len())));
+                    let body = Expr::Block(Block(result));
+                    return Some(Stmt::Expr(Expr::Block(Block(vec![
+                        (Stmt::Let(Scope::Local, step, int(0)), span),
+                        (Stmt::While(is_rest, body), span),
+                    ]))));
+                }
+                let iter = match iter {
+                    Iter::Array(array) => Iter::Array(array.expand(ctx)?),
+                    Iter::Range(start, end) => Iter::Range(start.expand(ctx)?, end.expand(ctx)?),
+                    Iter::RangeInclusive(start, end) => {
+                        Iter::RangeInclusive(start.expand(ctx)?, end.expand(ctx)?)
+                    }
+                };
+                let name = ctx.bind(name);
+                ctx.macro_visible.insert(name.clone());
+                Stmt::For(name, iter, body.expand(ctx)?)
+            }
+            Stmt::Generic(params, define) => {
+                // Type parameters shadow the definitions of the file
+                let name_ctx = ctx.namespace.clone();
+                for param in params {
+                    ctx.namespace.shift_remove(param);
+                }
+                let define = define.expand(ctx);
+                ctx.namespace = name_ctx;
+                Stmt::Generic(params.clone(), Box::new(define?))
+            }
+            Stmt::Try(expr, catch) => Stmt::Try(expr.expand(ctx)?, Box::new(catch.expand(ctx)?)),
+            Stmt::Macro(scope, name, params, body) => {
+                let name = ctx.resolve_name(name)?;
+                let define = (params.clone(), body.clone());
+                ctx.macro_code.insert(name.clone(), define);
+                Stmt::Macro(*scope, name, params.clone(), body.clone())
+            }
+            Stmt::Type(scope, name, typ) => {
+                Stmt::Type(*scope, ctx.resolve_name(name)?, typ.expand(ctx)?)
+            }
+            Stmt::Overload(id, (lhs, rhs), name) => Stmt::Overload(
+                *id,
+                (lhs.expand(ctx)?, rhs.expand(ctx)?),
+                ctx.resolve_name(name)?,
+            ),
+            Stmt::Import(module, (name, args, ret)) => {
+                let mut params = vec![];
+                for (name, typ) in args {
+                    params.push((name.clone(), typ.expand(ctx)?));
+                }
+                Stmt::Import(module.clone(), (name.clone(), params, ret.expand(ctx)?))
+            }
+            Stmt::Module(path, _) => {
+                let errmsg = format!("module `{path}` must be imported at the top level");
+                ctx.report(Diagnostic::error(errmsg));
+                return None;
+            }
+            Stmt::Return(Some(expr)) => Stmt::Return(Some(expr.expand(ctx)?)),
+            Stmt::Return(None) | Stmt::Break | Stmt::Next => self.clone(),
+        })
+    }
+}
+
+impl Expr {
+    fn expand(&self, ctx: &mut Compiler) -> Option<Expr> {
+        let expand = Expr::expand_args;
+        Some(match self {
+            Expr::Literal(Value::Array(elms)) => Expr::Literal(Value::Array(expand(elms, ctx)?)),
+            Expr::Literal(Value::Dict(dict)) => {
+                let mut result = IndexMap::new();
+                for (name, elm) in dict {
+                    result.insert(name.clone(), elm.expand(ctx)?);
+                }
+                Expr::Literal(Value::Dict(result))
+            }
+            Expr::Literal(Value::Enum(typ, variant, args)) => Expr::Literal(Value::Enum(
+                typ.expand(ctx)?,
+                variant.clone(),
+                expand(args, ctx)?,
+            )),
+            Expr::Literal(_) => self.clone(),
+            Expr::Variable(name) => match ctx.macro_scope.get(name) {
+                Some(arg) => arg.clone(),
+                None => Expr::Variable(ctx.resolve_name(name)?),
+            },
+            Expr::Operator(oper) => Expr::Operator(Box::new(oper.expand(ctx)?)),
+            Expr::Call(name, args) => {
+                let (name, args) = match args.split_first() {
+                    // Function or macro of the imported module `alias.name(args, ...)`
+                    Some((Expr::Variable(alias), args)) if ctx.aliases.contains_key(alias) => {
+                        (ctx.qualify(alias, name)?, args)
+                    }
+                    _ if ctx.macro_scope.contains_key(name) => (name.clone(), &args[..]),
+                    _ => (ctx.resolve_name(name)?, &args[..]),
+                };
+                if ctx.macro_def(&name).is_some() {
+                    return Expr::expand_macro(&name, args, ctx);
+                }
+                let args = expand(args, ctx)?;
+                match ctx.macro_scope.get(&name) {
+                    None => Expr::Call(name, args),
+                    Some(Expr::Variable(name)) => Expr::Call(name.clone(), args),
+                    Some(arg) => {
+                        let errmsg =
+                            format!("can't call `{arg}` given to macro parameter `{name}`");
+                        let note = format!("bind the argument by `let {name}` to call its value");
+                        ctx.report(Diagnostic::error(errmsg).with_note(note));
+                        return None;
+                    }
+                }
+            }
+            Expr::Index(array, index) => {
+                Expr::Index(Box::new(array.expand(ctx)?), Box::new(index.expand(ctx)?))
+            }
+            // Function of the imported module referred as value `alias.name`
+            Expr::Field(dict, key) => match &**dict {
+                Expr::Variable(alias) if ctx.aliases.contains_key(alias) => {
+                    Expr::Variable(ctx.qualify(alias, key)?)
+                }
+                _ => Expr::Field(Box::new(dict.expand(ctx)?), key.clone()),
+            },
+            Expr::OptionalField(dict, key) => {
+                Expr::OptionalField(Box::new(dict.expand(ctx)?), key.clone())
+            }
+            Expr::Block(block) => Expr::Block(block.expand(ctx)?),
+            Expr::Clone(from) => Expr::Clone(Box::new(from.expand(ctx)?)),
+            Expr::Peek(addr, typ) => Expr::Peek(Box::new(addr.expand(ctx)?), typ.expand(ctx)?),
+            Expr::Poke(addr, value) => {
+                Expr::Poke(Box::new(addr.expand(ctx)?), Box::new(value.expand(ctx)?))
+            }
+            Expr::Match(subject, arms) => {
+                let subject = subject.expand(ctx)?;
+                let mut result = vec![];
+                for (patterns, body) in arms {
+                    let scope_ctx = ctx.macro_scope.clone();
+                    let name_ctx = ctx.namespace.clone();
+                    let visible_ctx = ctx.macro_visible.clone();
+                    let mut expanded = vec![];
+                    for pattern in patterns {
+                        expanded.push(match pattern {
+                            Pattern::Variant(typ, variant, names) => {
+                                let typ = typ.expand(ctx)?;
+                                let names: Vec<_> =
+                                    names.iter().map(|name| ctx.bind(name)).collect();
+                                ctx.macro_visible.extend(names.clone());
+                                Pattern::Variant(typ, variant.clone(), names)
+                            }
+                            Pattern::Literal(Value::Enum(typ, variant, args)) => Pattern::Literal(
+                                Value::Enum(typ.expand(ctx)?, variant.clone(), args.clone()),
+                            ),
+                            _ => pattern.clone(),
+                        });
+                    }
+                    let body = body.expand(ctx);
+                    ctx.macro_scope = scope_ctx;
+                    ctx.namespace = name_ctx;
+                    ctx.macro_visible = visible_ctx;
+                    result.push((expanded, body?));
+                }
+                Expr::Match(Box::new(subject), result)
+            }
+            Expr::Lambda(params, body) => {
+                let scope_ctx = ctx.macro_scope.clone();
+                let name_ctx = ctx.namespace.clone();
+                let visible_ctx = ctx.macro_visible.clone();
+                let mut bound = vec![];
+                for (name, typ) in params {
+                    let name = ctx.bind(name);
+                    ctx.macro_visible.insert(name.clone());
+                    bound.push((name, typ.expand(ctx)?));
+                }
+                let body = body.expand(ctx);
+                ctx.macro_scope = scope_ctx;
+                ctx.namespace = name_ctx;
+                ctx.macro_visible = visible_ctx;
+                Expr::Lambda(bound, Box::new(body?))
+            }
+            Expr::Spread(_) => self.clone(),
+            Expr::Spanned(expr, span) => Expr::Spanned(Box::new(expr.expand(ctx)?), *span),
+        })
+    }
+
+    /// Arguments that the rest parameter of the macro `...name` is spread in
+    fn expand_args(args: &[Expr], ctx: &mut Compiler) -> Option<Vec<Expr>> {
+        let mut result = vec![];
+        for arg in args {
+            if let Expr::Spread(name) = arg
+                && let Some(Expr::Literal(Value::Array(rest))) =
+                    ctx.macro_scope.get(&format!("...{name}"))
+            {
+                result.extend(rest.clone());
+            } else {
+                result.push(arg.expand(ctx)?);
+            }
+        }
+        Some(result)
+    }
+
+    /// Body of the macro whose parameters are replaced with the arguments
+    fn expand_macro(name: &str, args: &[Expr], ctx: &mut Compiler) -> Option<Expr> {
+        let ((params, body), file) = ctx.macro_def(name)?;
+        if ctx.macro_stack.iter().any(|x| x == name) {
+            let errmsg = format!("macro `{name}` can't be expanded recursively");
+            ctx.report(Diagnostic::error(errmsg));
+            return None;
+        }
+        let args = Expr::expand_args(args, ctx)?;
+        let required = params
+            .iter()
+            .filter(|x| x.default.is_none() && !x.is_rest)
+            .count();
+        let is_variadic = params.iter().any(|x| x.is_rest);
+        let positional = params.len() - is_variadic as usize;
+        if args.len() < required || (!is_variadic && args.len() > positional) {
+            let paramlen = if is_variadic {
+                format!("at least {required}")
+            } else if required < positional {
+                format!("{required} to {positional}")
+            } else {
+                required.to_string()
+            };
+            let arglen = args.len();
+            let errmsg = format!(
+                "arguments of macro `{name}` length should be {paramlen}, but passed {arglen} values"
+            );
+            ctx.report(Diagnostic::error(errmsg));
+            return None;
+        }
+
+        // Body only sees the parameters, not the variables of the macro that's calling,
+        // and the names of the file defining it
+        let scope_ctx = std::mem::take(&mut ctx.macro_scope);
+        let file_ctx = file.map(|file| ctx.enter(&file));
+        ctx.macro_stack.push(name.to_string());
+        let mut binds = vec![];
+        let mut args = args.into_iter();
+        for param in &params {
+            let mut bind = |arg: Expr, ctx: &mut Compiler| {
+                if !param.is_bound {
+                    return arg;
+                }
+                let name = Expr::Variable(ctx.bind(&param.name));
+                binds.push(Stmt::Let(Scope::Local, name.clone(), arg));
+                name
+            };
+            if param.is_rest {
+                // Rest of arguments are also registered with `...` that identifiers can't have
+                let rest = args.by_ref().map(|arg| bind(arg, ctx)).collect();
+                let rest = Expr::Literal(Value::Array(rest));
+                ctx.macro_scope
+                    .insert(format!("...{}", param.name), rest.clone());
+                ctx.macro_scope.insert(param.name.clone(), rest);
+                continue;
+            }
+            let arg = match (args.next(), &param.default) {
+                (Some(arg), _) => Some(arg),
+                (None, Some(default)) => default.expand(ctx),
+                (None, None) => None,
+            };
+            let Some(arg) = arg else {
+                ctx.macro_stack.pop();
+                ctx.macro_scope = scope_ctx;
+                if let Some(file_ctx) = file_ctx {
+                    ctx.leave(file_ctx);
+                }
+                return None;
+            };
+            let arg = bind(arg, ctx);
+            ctx.macro_scope.insert(param.name.clone(), arg);
+        }
+        let body = body.expand(ctx);
+        ctx.macro_stack.pop();
+        ctx.macro_scope = scope_ctx;
+        if let Some(file_ctx) = file_ctx {
+            ctx.leave(file_ctx);
+        }
+
+        let body = body?;
+        if binds.is_empty() {
+            return Some(body);
+        }
+        let span = ctx.span.unwrap_or_default();
+        binds.push(Stmt::Expr(body));
+        Some(Expr::Block(Block(
+            binds.into_iter().map(|x| (x, span)).collect(),
+        )))
+    }
+}
+
+impl Op {
+    fn expand(&self, ctx: &mut Compiler) -> Option<Op> {
+        Some(match self {
+            Op::Neg(expr) => Op::Neg(expr.expand(ctx)?),
+            Op::LNot(expr) => Op::LNot(expr.expand(ctx)?),
+            Op::BNot(expr) => Op::BNot(expr.expand(ctx)?),
+            Op::Cast(expr, typ) => Op::Cast(expr.expand(ctx)?, typ.expand(ctx)?),
+            Op::NullCheck(expr) => Op::NullCheck(expr.expand(ctx)?),
+            Op::Nullable(typ) => Op::Nullable(typ.expand(ctx)?),
+            Op::Transmute(expr, typ) => Op::Transmute(expr.expand(ctx)?, typ.expand(ctx)?),
+            _ => {
+                let (lhs, rhs) = self.binop_term()?;
+                Op::binary(self.token()?, lhs.expand(ctx)?, rhs.expand(ctx)?)?
+            }
+        })
+    }
+}
+
+impl Type {
+    /// Type whose names are qualified by the modules defining them
+    fn expand(&self, ctx: &mut Compiler) -> Option<Type> {
+        let expand = |types: &[Type], ctx: &mut Compiler| {
+            types
+                .iter()
+                .map(|x| x.expand(ctx))
+                .collect::<Option<Vec<_>>>()
+        };
+        Some(match self {
+            Type::Alias(name) => Type::Alias(ctx.resolve_name(name)?),
+            Type::Generic(name, args) => Type::Generic(ctx.resolve_name(name)?, expand(args, ctx)?),
+            Type::Array(typ) => Type::Array(Box::new(typ.expand(ctx)?)),
+            Type::Nullable(typ) => Type::Nullable(Box::new(typ.expand(ctx)?)),
+            Type::Dict(dict) => {
+                let mut result = IndexMap::new();
+                for (name, (offset, typ)) in dict {
+                    result.insert(name.clone(), (*offset, typ.expand(ctx)?));
+                }
+                Type::Dict(result)
+            }
+            Type::Enum(variants) => {
+                let mut result = IndexMap::new();
+                for (name, fields) in variants {
+                    result.insert(name.clone(), expand(fields, ctx)?);
+                }
+                Type::Enum(result)
+            }
+            Type::Function(args, ret) => {
+                Type::Function(expand(args, ctx)?, Box::new(ret.expand(ctx)?))
+            }
+            _ => self.clone(),
+        })
+    }
+}
+
+impl Display for MacroParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_bound {
+            write!(f, "let ")?;
+        }
+        if self.is_rest {
+            write!(f, "...")?;
+        }
+        write!(f, "{}", self.name)?;
+        if let Some(default) = &self.default {
+            write!(f, " = {default}")?;
+        }
+        Ok(())
+    }
+}
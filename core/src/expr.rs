@@ -1,4 +1,5 @@
 use crate::*;
+use std::fmt::{self, Display};
 
 #[derive(Debug, Clone)]
 pub enum Expr {
@@ -8,126 +9,97 @@ pub enum Expr {
     Call(String, Vec<Expr>),
     Index(Box<Expr>, Box<Expr>),
     Field(Box<Expr>, String),
+    /// Field access that's null if the dictionary is null `dict?.field`
+    OptionalField(Box<Expr>, String),
     Block(Block),
     Clone(Box<Expr>),
     Peek(Box<Expr>, Type),
     Poke(Box<Expr>, Box<Expr>),
+    Match(Box<Expr>, Vec<Arm>),
+    Lambda(Vec<(String, Type)>, Box<Expr>),
+    /// Rest parameter of the macro spread in the arguments `...name`
+    Spread(String),
+    /// Operand with its span in the source code, that its type errors are reported at
+    Spanned(Box<Expr>, Span),
 }
 
 impl Node for Expr {
-    fn parse(source: &str) -> Option<Expr> {
-        let token = source.trim();
-        // Operator
-        if let Some(literal) = Op::parse(&token) {
-            Some(Expr::Operator(Box::new(literal)))
-        // Literal value
-        } else if let Some(literal) = Value::parse(&token) {
-            Some(Expr::Literal(literal))
-        // Formatted string (f-string)
-        } else if token.starts_with("f\"") && token.ends_with('"') {
-            let str = str_format(token.get(2..token.len() - 1)?)?;
-            let mut result = None;
-            for elm in str {
-                if elm.starts_with("{") && elm.ends_with("}") {
-                    let elm = elm.get(1..elm.len() - 1)?.trim();
-                    let block = Expr::Operator(Box::new(Op::Cast(
-                        Expr::Block(Block::parse(elm)?),
-                        Type::String,
-                    )));
-                    result = Some(if let Some(result) = result {
-                        Expr::Operator(Box::new(Op::Add(result, block)))
-                    } else {
-                        block
-                    });
-                } else {
-                    let str = Expr::Literal(Value::String(elm));
-                    result = Some(if let Some(result) = result {
-                        Expr::Operator(Box::new(Op::Add(result, str)))
-                    } else {
-                        str
-                    })
-                }
-            }
-            dbg!(&result);
-            result
-        // Prioritize expression `(expr)`
-        } else if token.starts_with("(") && token.ends_with(")") {
-            let token = token.get(1..token.len() - 1)?.trim();
-            Some(Expr::parse(token)?)
-        // Code block `{ stmt; ... }`
-        } else if token.starts_with("{") && token.ends_with("}") {
-            let token = token.get(1..token.len() - 1)?.trim();
-            Some(Expr::Block(Block::parse(token)?))
-        // Index access `array[index]`
-        } else if token.contains("[") && token.ends_with("]") {
-            let token = token.get(..token.len() - 1)?.trim();
-            let (array, index) = token.rsplit_once("[")?;
-            let (array, index) = (Expr::parse(array)?, Expr::parse(index)?);
-            Some(Expr::Index(Box::new(array), Box::new(index)))
-        // Function call `name(args, ...)`
-        } else if token.contains("(") && token.ends_with(")") {
-            let token = tokenize(token, &["("], false, true, true)?;
-            let name = token.get(0..token.len() - 1)?.concat();
-            let args = token.last()?.get(1..token.last()?.len() - 1)?;
-            let args = tokenize(args, &[","], false, true, false)?;
-            let args = args.iter().map(|i| Expr::parse(&i));
-            let args = args.collect::<Option<Vec<_>>>()?;
-            match Expr::parse(&name)? {
-                Expr::Variable(name) if name == "memcpy" => {
-                    Some(Expr::Clone(Box::new(args.first()?.clone())))
-                }
-                Expr::Variable(name) => Some(Expr::Call(name, args)),
-                Expr::Field(obj, name) if name == "memcpy" => Some(Expr::Clone(obj)),
-                Expr::Field(obj, name) => Some(Expr::Call(name, [vec![*obj], args].concat())),
-                _ => None,
-            }
-        // Dictionary access `dict.field`
-        } else if token.contains(".") {
-            let (dict, field) = token.rsplit_once(".")?;
-            let field = field.trim();
-            if !is_identifier(field) {
-                return None;
-            };
-            Some(Expr::Field(Box::new(Expr::parse(dict)?), field.to_owned()))
-        // Enumerate access `( a | b )#a`
-        } else if source.contains("#") {
-            let (typ, enum_) = source.rsplit_once("#")?;
-            let enum_ = Value::Enum(Type::parse(typ)?, enum_.to_owned());
-            Some(Expr::Literal(enum_))
-        // Variable reference
-        } else if is_identifier(token) {
-            Some(Expr::Variable(token.to_string()))
-        } else {
-            None
-        }
+    fn parse(parser: &mut Parser) -> Option<Expr> {
+        Expr::parse_binary(parser, 1)
     }
 
     fn compile(&self, ctx: &mut Compiler) -> Option<String> {
         Some(match self {
+            Expr::Spanned(expr, span) => {
+                let outer_span = ctx.span.replace(*span);
+                let code = expr.compile(ctx);
+                ctx.span = outer_span;
+                code?
+            }
             Expr::Operator(oper) => oper.compile(ctx)?,
             Expr::Variable(name) if ctx.global_type.contains_key(name) => {
                 format!("(global.get ${name})")
             }
+            // Function as value is the static closure record that has only the index in the table
+            Expr::Variable(name) if Expr::is_function_value(name, ctx) => {
+                if let Some(Some(address)) = ctx.table.get(name) {
+                    return Some(format!("(i32.const {address})"));
+                }
+                let address = ctx.allocator;
+                let index = (ctx.table.len() as i32).to_le_bytes();
+                let data = index.iter().map(|byte| format!("\\{byte:02x}"));
+                let code = format!(
+                    r#"(data (i32.const {address}) "{}")"#,
+                    data.collect::<String>()
+                );
+                ctx.static_data.push(code);
+                ctx.allocator += BYTES;
+                ctx.table.insert(name.clone(), Some(address));
+                format!("(i32.const {address})")
+            }
             Expr::Variable(name) => format!("(local.get ${name})"),
             Expr::Literal(literal) => literal.compile(ctx)?,
             Expr::Call(name, args) => {
-                if ctx.function_type.contains_key(name) || ctx.export_type.contains_key(name) {
+                if let Some(Type::Function(params, ret)) = Expr::callee(name, ctx) {
+                    let params = params
+                        .iter()
+                        .map(|typ| typ.compile(ctx))
+                        .collect::<Option<Vec<_>>>()?;
+                    let params = if params.is_empty() {
+                        String::new()
+                    } else {
+                        format!("(param {})", join!(params))
+                    };
                     let args = args
                         .iter()
                         .map(|x| x.compile(ctx))
                         .collect::<Option<Vec<_>>>()?;
-                    format!("(call ${name} {})", join!(args))
-                } else if let Some((params, expr)) = ctx.macro_code.get(name).cloned() {
-                    for (param, arg) in params.iter().zip(args) {
-                        let typ = arg.type_infer(ctx)?;
-                        ctx.variable_type.insert(param.to_owned(), typ);
-                    }
-                    let mut body = expr.compile(ctx)?;
-                    for (param, arg) in params.iter().zip(args) {
-                        let var = Expr::Variable(param.to_owned()).compile(ctx)?;
-                        body = body.replace(&var, &arg.compile(ctx)?);
-                    }
-                    body
+                    // Closure record is passed after the arguments are evaluated
+                    format!(
+                        "(call_indirect {params} {ret} {} (global.set $closure {}) (i32.load (global.get $closure)))",
+                        join!(args),
+                        Expr::Variable(name.clone()).compile(ctx)?,
+                        ret = compile_return!(ret, ctx)
+                    )
+                } else if ctx.function_type.contains_key(name) || ctx.export_type.contains_key(name)
+                {
+                    let args = args
+                        .iter()
+                        .map(|x| x.compile(ctx))
+                        .collect::<Option<Vec<_>>>()?;
+                    format!("(call ${} {})", mangle(name), join!(args))
+                } else if ctx.generic_code.contains_key(name) {
+                    let instance = Stmt::instantiate(name, args, ctx)?;
+                    Expr::Call(instance, args.clone()).compile(ctx)?
+                } else if name == "drop" {
+                    // Objects that the freed one refers aren't freed with it
+                    format!("(call $free {})", args[0].compile(ctx)?)
+                } else if let Some((builtin, _, _)) = Builtin::function(name) {
+                    let args = args
+                        .iter()
+                        .map(|x| x.compile(ctx))
+                        .collect::<Option<Vec<_>>>()?;
+                    ctx.call_builtin(builtin, &args)
                 } else {
                     return None;
                 }
@@ -136,8 +108,8 @@ impl Node for Expr {
                 let Type::Array(typ) = array.type_infer(ctx)?.type_infer(ctx)? else {
                     return None;
                 };
-                let addr = Box::new(address_calc!(array, index, typ));
-                Expr::Peek(addr, *typ).compile(ctx)?
+                let addr = Expr::element(array, index, ctx)?;
+                format!("({}.load {addr})", typ.compile(ctx)?)
             }
             Expr::Field(expr, key) => {
                 let typ = expr.type_infer(ctx)?.type_infer(ctx)?;
@@ -148,13 +120,29 @@ impl Node for Expr {
                 let addr = offset_calc!(expr, offset);
                 Expr::Peek(Box::new(addr), typ).compile(ctx)?
             }
+            Expr::OptionalField(dict, key) => {
+                self.type_infer(ctx)?;
+                let Type::Nullable(inner) = dict.type_infer(ctx)?.type_infer(ctx)? else {
+                    return Expr::Field(dict.clone(), key.clone()).compile(ctx);
+                };
+                // Dictionary is evaluated once into a temporary local variable
+                let temp = format!("optional.{}", ctx.variable_type.len());
+                ctx.variable_type.insert(temp.clone(), *inner);
+                let field = Expr::Field(Box::new(Expr::Variable(temp.clone())), key.clone());
+                let dict = dict.compile(ctx)?;
+                format!(
+                    "(block (result i32) {} (if (result i32) (i32.eq (local.get ${temp}) (i32.const -1)) (then (i32.const -1)) (else {})))",
+                    ctx.set_local(&temp, dict)?,
+                    field.compile(ctx)?
+                )
+            }
             Expr::Block(block) => block.compile(ctx)?,
             Expr::Clone(from) => {
+                let typ = from.type_infer(ctx)?;
+                let layout = ctx.object_layout(&typ)?;
                 let size = from.object_size(ctx)?.compile(ctx)?;
-                format!(
-                    "(memory.copy (global.get $allocator) {object} {size}) (call $malloc {size})",
-                    object = from.compile(ctx)?,
-                )
+                let args = [from.compile(ctx)?, size, format!("(i32.const {layout})")];
+                ctx.call_builtin(Builtin::Copy, &args)
             }
             Expr::Peek(expr, typ) => {
                 format!("({}.load {})", typ.compile(ctx)?, expr.compile(ctx)?)
@@ -167,21 +155,203 @@ impl Node for Expr {
                     expr.compile(ctx)?
                 )
             }
+            Expr::Match(subject, arms) => {
+                let outer = ctx.narrowed.clone();
+                let typ = self.type_infer(ctx)?;
+                let after = std::mem::replace(&mut ctx.narrowed, outer.clone());
+                let subject_type = subject.type_infer(ctx)?.type_infer(ctx)?;
+                // Subject is evaluated once into a temporary local variable
+                let temp = format!("match.{}", ctx.variable_type.len());
+                ctx.variable_type.insert(temp.clone(), subject_type.clone());
+                let subject = subject.compile(ctx)?;
+                let mut code = ctx.set_local(&temp, subject)?;
+
+                // Arms are nested blocks, and branch of depth N jumps to the arm N
+                let wildcard = arms.iter().position(|(patterns, _)| {
+                    patterns.iter().any(|x| matches!(x, Pattern::Wildcard))
+                });
+                if let Type::Enum(variants) = &subject_type {
+                    // Dense enumerator values are dispatched by jump table
+                    let mut targets = vec![];
+                    for variant in variants.keys() {
+                        let arm = arms.iter().position(|(patterns, _)| {
+                            patterns.iter().any(|pattern| {
+                                matches!(pattern, Pattern::Wildcard)
+                                    || pattern.variant() == Some(variant)
+                            })
+                        });
+                        targets.push(arm?.to_string());
+                    }
+                    let default = targets.last()?.clone();
+                    let targets = targets.join(" ");
+                    let mut tag = format!("(local.get ${temp})");
+                    if subject_type.is_tagged() {
+                        tag = format!("(i32.load {tag})");
+                    }
+                    code += &format!(" (br_table {targets} {default} {tag})");
+                } else {
+                    for (index, (patterns, _)) in arms.iter().enumerate() {
+                        for pattern in patterns {
+                            let Pattern::Literal(value) = pattern else {
+                                break;
+                            };
+                            let cond =
+                                Op::Eql(Expr::Variable(temp.clone()), Expr::Literal(value.clone()));
+                            code += &format!(" (br_if {index} {})", cond.compile(ctx)?);
+                        }
+                        if Some(index) == wildcard {
+                            break;
+                        }
+                    }
+                    // Unmatched value skips all the arms, that's allowed only in void
+                    let default = wildcard.unwrap_or(arms.len());
+                    code += &format!(" (br {default})");
+                }
+                for (index, (patterns, body)) in arms.iter().enumerate() {
+                    let depth = arms.len() - index - 1;
+                    let bind = join!(
+                        patterns
+                            .iter()
+                            .map(|x| x.bind(&temp, ctx))
+                            .collect::<Option<Vec<_>>>()?
+                    );
+                    ctx.narrowed = outer.clone();
+                    let body = format!("{bind} {}", body.compile(ctx)?);
+                    code = if let Type::Void = typ {
+                        format!("(block {code}) {body} (br {depth})")
+                    } else {
+                        format!("(block {code}) (br {depth} {body})")
+                    };
+                }
+                ctx.narrowed = after;
+                format!("(block {} {code})", compile_return!(typ, ctx))
+            }
+            Expr::Lambda(params, body) => {
+                let Type::Function(_, ret) = self.type_infer(ctx)? else {
+                    return None;
+                };
+                let name = format!("lambda.{}", ctx.table.len());
+                ctx.table.insert(name.clone(), None);
+
+                // Local variables and arguments in the scope that the body refers are captured,
+                // and the ones it assigns are its own locals
+                let (mut refer, mut assign) = (IndexSet::new(), IndexSet::new());
+                body.variables(&mut refer, &mut assign);
+                let var_ctx = ctx.variable_type.clone();
+                let arg_ctx = ctx.argument_type.clone();
+                let mut scope = arg_ctx.clone();
+                scope.extend(var_ctx.clone());
+                scope.retain(|name, _| {
+                    refer.contains(name)
+                        && !assign.contains(name)
+                        && !name.contains('.')
+                        && !params.iter().any(|(param, _)| param == name)
+                });
+                ctx.variable_type = scope.clone();
+                ctx.argument_type = params.iter().cloned().collect();
+                let flow = ctx.open_flow();
+                let frame = ctx.open_frame();
+                let code = body.compile(ctx);
+                let func = code.and_then(|code| {
+                    let captures: Vec<_> = scope.iter().collect();
+                    // Captured values are copied from the record when it's called
+                    let mut prologue = vec![];
+                    for (index, (name, typ)) in captures.iter().enumerate() {
+                        let value = format!(
+                            "({typ}.load offset={offset} (global.get $closure))",
+                            typ = typ.compile(ctx)?,
+                            offset = (index as i32 + 1) * BYTES
+                        );
+                        prologue.push(ctx.set_local(name, value)?);
+                    }
+                    let body = format!("{} {code}", join!(prologue));
+                    let body = ctx.close_frame(frame, body, &ret, false)?;
+                    let args = params
+                        .iter()
+                        .map(|(name, typ)| Some(format!("(param ${name} {})", typ.compile(ctx)?)))
+                        .collect::<Option<Vec<_>>>()?;
+                    let func = format!(
+                        "(func ${id} {args} {ret} {locals} {body})",
+                        id = mangle(&name),
+                        args = join!(args),
+                        ret = compile_return!(ret, ctx),
+                        locals = expand_local(ctx)?,
+                    );
+                    ctx.declare_code.push(func);
+                    Some(
+                        captures
+                            .into_iter()
+                            .map(|(name, _)| name.clone())
+                            .collect::<Vec<_>>(),
+                    )
+                });
+                ctx.variable_type = var_ctx;
+                ctx.argument_type = arg_ctx;
+                ctx.close_flow(flow);
+
+                // Record of the index in the table followed by the captured values
+                let captures = func?;
+                let pointer = format!("closure.{}", ctx.variable_type.len());
+                ctx.variable_type.insert(pointer.clone(), Type::Integer);
+                let size = (captures.len() as i32 + 1) * BYTES;
+                let index = ctx.table.get_index_of(&name)?;
+                let mut words = vec![Type::Integer];
+                for name in &captures {
+                    words.push(Expr::Variable(name.clone()).type_infer(ctx)?);
+                }
+                let layout = ctx.layout(&words)?;
+                let mut result = vec![
+                    format!("(local.set ${pointer} {})", ctx.alloc(size, layout)),
+                    format!("(i32.store (local.get ${pointer}) (i32.const {index}))"),
+                ];
+                for (index, name) in captures.iter().enumerate() {
+                    let value = Expr::Variable(name.clone());
+                    result.push(format!(
+                        "({typ}.store offset={offset} (local.get ${pointer}) {value})",
+                        typ = value.type_infer(ctx)?.compile(ctx)?,
+                        offset = (index as i32 + 1) * BYTES,
+                        value = value.compile(ctx)?
+                    ));
+                }
+                result.push(format!("(local.get ${pointer})"));
+                join!(result)
+            }
+            Expr::Spread(_) => {
+                self.type_infer(ctx)?;
+                return None;
+            }
         })
     }
 
     fn type_infer(&self, ctx: &mut Compiler) -> Option<Type> {
         Some(match self {
+            Expr::Spanned(expr, span) => {
+                let outer_span = ctx.span.replace(*span);
+                let typ = expr.type_infer(ctx);
+                ctx.span = outer_span;
+                typ?
+            }
             Expr::Operator(oper) => oper.type_infer(ctx)?,
             Expr::Variable(name) => {
+                let local = ctx.variable_type.get(name).or(ctx.argument_type.get(name));
                 if let Some(global) = ctx.global_type.get(name) {
                     global.clone()
-                } else if let Some(local) = ctx.variable_type.get(name) {
-                    local.clone()
-                } else if let Some(arg) = ctx.argument_type.get(name) {
-                    arg.clone()
+                } else if let Some(local) = local.cloned() {
+                    // Null-checked variable is non-null until a value that may be null is assigned
+                    if !ctx.narrowed.contains(name) {
+                        local
+                    } else if let Type::Nullable(inner) = local.type_infer(ctx)? {
+                        *inner
+                    } else {
+                        local
+                    }
+                } else if let Some(function) =
+                    ctx.function_type.get(name).or(ctx.export_type.get(name))
+                {
+                    let args = function.arguments.values().cloned().collect();
+                    Type::Function(args, Box::new(function.returns.clone()))
                 } else {
-                    ctx.occurred_error = Some(format!("undefined variable `{name}`"));
+                    ctx.report(Diagnostic::error(format!("undefined variable `{name}`")));
                     return None;
                 }
             }
@@ -192,71 +362,132 @@ impl Node for Expr {
                         if args.len() != $params.len() {
                             let (typ, paramlen, arglen) = ($typ, $params.len(), args.len());
                             let errmsg = format!("arguments of {typ} `{name}` length should be {paramlen}, but passed {arglen} values");
-                            ctx.occurred_error = Some(errmsg);
+                            ctx.report(Diagnostic::error(errmsg));
                             return None;
                         }
                     };
                 }
-                if let Some(function) = ctx
+                if let Some(Type::Function(params, ret)) = Expr::callee(name, ctx) {
+                    arglen_check!(params, "function");
+                    let func = |(arg, typ): (&Expr, &Type)| value_check!(arg, typ, ctx);
+                    args.iter()
+                        .zip(params.iter())
+                        .map(func)
+                        .collect::<Option<Vec<_>>>()?;
+                    ret.type_infer(ctx)?
+                } else if let Some(Type::Error) = Expr::callee(name, ctx) {
+                    // Variable whose definition failed is called without reporting it again
+                    Type::Error
+                } else if let Some(function) = ctx
                     .function_type
                     .get(name)
                     .or(ctx.export_type.get(name))
                     .cloned()
                 {
                     arglen_check!(function.arguments, "function");
-                    let func = |(arg, typ): (&Expr, &Type)| type_check!(arg, typ, ctx);
+                    let func = |(arg, typ): (&Expr, &Type)| value_check!(arg, typ, ctx);
                     let ziped = args.iter().zip(function.arguments.values());
                     ziped.map(func).collect::<Option<Vec<_>>>()?;
                     function.returns.type_infer(ctx)?
-                } else if let Some((params, expr)) = ctx.macro_code.get(name).cloned() {
-                    arglen_check!(params, "macro");
-                    let var_ctx = ctx.variable_type.clone();
-                    for (params, arg) in params.iter().zip(args) {
-                        let typ = arg.type_infer(ctx)?;
-                        ctx.variable_type.insert(params.to_owned(), typ);
+                } else if ctx.generic_code.contains_key(name) {
+                    let instance = Stmt::instantiate(name, args, ctx)?;
+                    Expr::Call(instance, args.clone()).type_infer(ctx)?
+                } else if name == "drop" {
+                    arglen_check!([()], "function");
+                    let typ = args[0].type_infer(ctx)?.type_infer(ctx)?;
+                    let is_object = is_ptr!(typ, ctx) || typ.is_tagged();
+                    let is_ref = matches!(typ, Type::Function(_, _) | Type::Nullable(_));
+                    if !(is_object || is_ref || typ == Type::Error) {
+                        let errmsg = format!("can't drop {} typed value", typ.format());
+                        let note = "only objects allocated in the heap can be freed";
+                        ctx.report(Diagnostic::error(errmsg).with_note(note));
+                        return None;
                     }
-                    let typ = expr.type_infer(ctx)?;
-                    ctx.variable_type = var_ctx;
-                    typ
+                    Type::Void
+                } else if let Some((_, params, ret)) = Builtin::function(name) {
+                    arglen_check!(params, "function");
+                    let func = |(arg, typ): (&Expr, &Type)| value_check!(arg, typ, ctx);
+                    args.iter()
+                        .zip(params.iter())
+                        .map(func)
+                        .collect::<Option<Vec<_>>>()?;
+                    ret
                 } else {
-                    ctx.occurred_error = Some(format!(
-                        "function or macro `{name}` you want to call is not defined"
-                    ));
+                    let errmsg =
+                        format!("function or macro `{name}` you want to call is not defined");
+                    let note = "external functions have to be imported by `load` statement";
+                    ctx.report(Diagnostic::error(errmsg).with_note(note));
                     return None;
                 }
             }
             Expr::Index(arr, _) => {
                 let infered = arr.type_infer(ctx)?;
-                let Some(Type::Array(typ)) = infered.type_infer(ctx) else {
-                    let error_message = format!("can't index access to {}", infered.format());
-                    ctx.occurred_error = Some(error_message);
-                    return None;
-                };
-                typ.type_infer(ctx)?
+                match infered.type_infer(ctx) {
+                    Some(Type::Array(typ)) => typ.type_infer(ctx)?,
+                    Some(Type::Error) => Type::Error,
+                    Some(Type::Nullable(_)) => {
+                        let errmsg = format!(
+                            "can't index access to {} that may be null",
+                            infered.format()
+                        );
+                        let note =
+                            "check it by `if x? then ...` first, or give the default value by `??`";
+                        ctx.report(Diagnostic::error(errmsg).with_note(note));
+                        return None;
+                    }
+                    _ => {
+                        let error_message = format!("can't index access to {}", infered.format());
+                        ctx.report(Diagnostic::error(error_message));
+                        return None;
+                    }
+                }
             }
             Expr::Field(dict, key) => {
                 let infered = dict.type_infer(ctx)?.type_infer(ctx)?;
-                if let Type::Dict(dict) = infered.clone() {
-                    let Some((_offset, typ)) = dict.get(key) else {
-                        let error_message = format!("{} haven't field `{key}`", infered.format());
-                        ctx.occurred_error = Some(error_message);
-                        return None;
-                    };
-                    typ.type_infer(ctx)?
-                } else {
-                    let error_message = format!("can't field access to {}", infered.format());
-                    ctx.occurred_error = Some(error_message);
+                if let Type::Nullable(_) = infered {
+                    let errmsg = format!(
+                        "can't field access to {} that may be null",
+                        infered.format()
+                    );
+                    let note = "check it by `if x? then ...` first, or access it by `?.` instead";
+                    ctx.report(Diagnostic::error(errmsg).with_note(note));
                     return None;
                 }
+                Expr::field_type(&infered, key, ctx)?
+            }
+            Expr::OptionalField(dict, key) => {
+                let infered = dict.type_infer(ctx)?.type_infer(ctx)?;
+                let Type::Nullable(inner) = infered else {
+                    return Expr::field_type(&infered, key, ctx);
+                };
+                // Field of the null dictionary is null too
+                match Expr::field_type(&inner, key, ctx)? {
+                    typ @ (Type::Nullable(_) | Type::Error) => typ,
+                    typ if is_ptr!(typ, ctx) => Type::Nullable(Box::new(typ)),
+                    typ => {
+                        let errmsg = format!(
+                            "can't optional access to {} typed field `{key}`, that can't be null",
+                            typ.format()
+                        );
+                        let note = "check the dictionary by `if x? then ...` and access it by `.`";
+                        ctx.report(Diagnostic::error(errmsg).with_note(note));
+                        return None;
+                    }
+                }
             }
             Expr::Block(block) => block.type_infer(ctx)?,
             Expr::Clone(from) => {
                 let typ = from.type_infer(ctx)?;
-                if is_ptr!(typ, ctx) {
+                if is_ptr!(typ, ctx) || typ == Type::Error {
                     typ
+                } else if let Type::Nullable(_) = typ.type_infer(ctx)? {
+                    let errmsg = format!("can't memory copy {} that may be null", typ.format());
+                    let note = "check it by `if x? then ...` first";
+                    ctx.report(Diagnostic::error(errmsg).with_note(note));
+                    return None;
                 } else {
                     let errmsg = "can't memory copy primitive typed value";
-                    ctx.occurred_error = Some(errmsg.to_string());
+                    ctx.report(Diagnostic::error(errmsg));
                     return None;
                 }
             }
@@ -269,11 +500,489 @@ impl Node for Expr {
                 expr.type_infer(ctx)?;
                 Type::Void
             }
+            Expr::Match(subject, arms) => {
+                let typ = subject.type_infer(ctx)?.type_infer(ctx)?;
+                if !matches!(
+                    typ,
+                    Type::Integer | Type::String | Type::Enum(_) | Type::Error
+                ) {
+                    let errmsg = format!("can't match {} typed value", typ.format());
+                    let note = "only integers, strings and enumerators can be matched";
+                    ctx.report(Diagnostic::error(errmsg).with_note(note));
+                    return None;
+                }
+                let mut result = Type::Error;
+                let outer = ctx.narrowed.clone();
+                let mut after: Option<IndexSet<String>> = None;
+                for (patterns, body) in arms {
+                    for pattern in patterns {
+                        pattern.type_check(&typ, ctx)?;
+                    }
+                    ctx.narrowed = outer.clone();
+                    let arm = body.type_infer(ctx);
+                    // Null-checks hold after the match if all the arms reaching there keep them
+                    let narrowed = std::mem::replace(&mut ctx.narrowed, outer.clone());
+                    if !Stmt::Expr(body.clone()).diverges() {
+                        after = Some(match after {
+                            Some(after) => after.intersection(&narrowed).cloned().collect(),
+                            None => narrowed,
+                        });
+                    }
+                    let arm = arm?;
+                    result = branch_check!(result, arm, ctx)?;
+                }
+                ctx.narrowed = after.unwrap_or(outer);
+
+                let is_wildcard = arms
+                    .iter()
+                    .any(|(patterns, _)| patterns.iter().any(|x| matches!(x, Pattern::Wildcard)));
+                if let (Type::Enum(variants), false) = (&typ, is_wildcard) {
+                    let missing: Vec<_> = variants
+                        .keys()
+                        .filter(|variant| {
+                            !arms.iter().any(|(patterns, _)| {
+                                patterns.iter().any(|x| x.variant() == Some(variant))
+                            })
+                        })
+                        .cloned()
+                        .collect();
+                    if !missing.is_empty() {
+                        let typ = typ.compress_alias(ctx).format();
+                        let errmsg = format!("match on {typ} isn't exhaustive");
+                        let note = format!("missing variants are {}", missing.join(", "));
+                        ctx.report(Diagnostic::error(errmsg).with_note(note));
+                        return None;
+                    }
+                } else if !is_wildcard && result != Type::Void && typ != Type::Error {
+                    let errmsg = format!("match on {} isn't exhaustive", typ.format());
+                    let note = "add the wildcard pattern `_` to handle the rest of values";
+                    ctx.report(Diagnostic::error(errmsg).with_note(note));
+                    return None;
+                }
+                result
+            }
+            Expr::Lambda(params, body) => {
+                // Captured values are copied, so the assignment wouldn't reach the original
+                let (mut refer, mut assign) = (IndexSet::new(), IndexSet::new());
+                body.variables(&mut refer, &mut assign);
+                for name in assign {
+                    let is_outer = ctx.variable_type.contains_key(&name)
+                        || ctx.argument_type.contains_key(&name);
+                    if is_outer && !params.iter().any(|(param, _)| *param == name) {
+                        let errmsg = format!("can't assign value to captured variable `{name}`");
+                        let note = "lambda has the copy of it, bind the value to other name";
+                        ctx.report(Diagnostic::error(errmsg).with_note(note));
+                        return None;
+                    }
+                }
+                let var_ctx = ctx.variable_type.clone();
+                for (name, typ) in params {
+                    let typ = typ.type_infer(ctx)?;
+                    ctx.variable_type.insert(name.clone(), typ);
+                }
+                let flow = ctx.open_flow();
+                let ret = body.type_infer(ctx);
+                ctx.close_flow(flow);
+                ctx.variable_type = var_ctx;
+                let params = params.iter().map(|(_, typ)| typ.clone()).collect();
+                Type::Function(params, Box::new(ret?))
+            }
+            Expr::Spread(name) => {
+                let errmsg = format!("`...{name}` isn't the rest parameter of macro");
+                ctx.report(Diagnostic::error(errmsg));
+                return None;
+            }
         })
     }
 }
 
 impl Expr {
+    /// Precedence climbing over binary operators binding at least as tight as the power
+    fn parse_binary(parser: &mut Parser, power: u8) -> Option<Expr> {
+        let start = parser.span();
+        let mut lhs = Expr::parse_cast(parser)?;
+        let mut last = None;
+        while let Some(TokenKind::Operator(op)) = parser.peek().cloned() {
+            let Some((prec, assoc)) = Op::precedence(op).filter(|(prec, _)| *prec >= power) else {
+                break;
+            };
+            if assoc == Assoc::NonAssoc && last == Some(prec) {
+                let errmsg = format!("comparison operators can't be chained, found `{op}`");
+                let note = "use parentheses or `&&` to combine comparisons";
+                let diagnostic = Diagnostic::error(errmsg).with_span(Some(parser.span()));
+                parser.diagnostics.push(diagnostic.with_note(note));
+                return None;
+            }
+            let lhs_span = parser.span_from(start);
+            parser.advance();
+            let rhs_start = parser.span();
+            let rhs = Expr::parse_binary(parser, prec + 1)?;
+            // Operands keep their spans that type errors between them are reported at
+            let rhs = Expr::Spanned(Box::new(rhs), parser.span_from(rhs_start));
+            let lhs_spanned = Expr::Spanned(Box::new(lhs), lhs_span);
+            lhs = Expr::Operator(Box::new(Op::binary(op, lhs_spanned, rhs)?));
+            last = Some(prec);
+        }
+        Some(lhs)
+    }
+
+    /// Type cast `expr: type`, binding tighter than any binary operator
+    fn parse_cast(parser: &mut Parser) -> Option<Expr> {
+        let mut expr = Expr::parse_unary(parser)?;
+        while parser.eat(&TokenKind::Operator(":")) {
+            expr = Expr::Operator(Box::new(Op::Cast(expr, Type::parse(parser)?)));
+        }
+        Some(expr)
+    }
+
+    /// Prefix operators `-`, `!`, `~` applied to the operand with its postfix `?`
+    pub(crate) fn parse_unary(parser: &mut Parser) -> Option<Expr> {
+        use TokenKind::{Number, Operator, Symbol};
+        let mut expr = match (
+            parser.peek().cloned(),
+            parser.peek_nth(1),
+            parser.peek_nth(2),
+        ) {
+            // Fold negative literal unless it's accessed, so that `-2147483648` fits in integer
+            (Some(Operator("-")), Some(Number(n)), next)
+                if !matches!(next, Some(Symbol('.' | '['))) =>
+            {
+                let literal = Value::number(&format!("-{n}"))?;
+                parser.advance();
+                parser.advance();
+                Expr::Literal(literal)
+            }
+            (Some(Operator(op @ ("-" | "!" | "~"))), _, _) => {
+                parser.advance();
+                let expr = Expr::parse_unary(parser)?;
+                return Some(Expr::Operator(Box::new(Op::unary(op, expr)?)));
+            }
+            _ => Expr::parse_operand(parser)?,
+        };
+        while parser.eat(&TokenKind::Operator("?")) {
+            expr = Expr::Operator(Box::new(Op::NullCheck(expr)));
+        }
+        Some(expr)
+    }
+
+    fn parse_operand(parser: &mut Parser) -> Option<Expr> {
+        let typed = parser.speculate(|parser| {
+            let typ = Type::parse(parser)?;
+            match parser.peek() {
+                // Nullable `T!`
+                Some(TokenKind::Operator("!")) => {
+                    parser.advance();
+                    Some(Expr::Operator(Box::new(Op::Nullable(typ))))
+                }
+                // Enumerate access `( a | b )#a` with the fields `T#a(expr, ...)`
+                Some(TokenKind::Symbol('#')) => {
+                    parser.advance();
+                    let variant = parser.identifier()?;
+                    let mut args = vec![];
+                    if parser.is(&TokenKind::Symbol('(')) {
+                        args = Expr::parse_args(parser)?;
+                    }
+                    Some(Expr::Literal(Value::Enum(typ, variant, args)))
+                }
+                _ => None,
+            }
+        });
+        if typed.is_some() {
+            return typed;
+        }
+        Expr::parse_primary(parser)
+    }
+
+    /// Atom followed by index accesses, field accesses and function calls
+    fn parse_primary(parser: &mut Parser) -> Option<Expr> {
+        use TokenKind::{FString, Identifier, Keyword, Operator, Symbol};
+        let start = parser.span();
+        let mut expr = match parser.peek().cloned() {
+            // Formatted string (f-string)
+            Some(FString(body)) => {
+                parser.advance();
+                Expr::parse_fstring(parser, &body, start.start + 2)?
+            }
+            // Pattern matching `match expr with pattern | ... => expr, ...`
+            Some(Keyword("match")) => {
+                parser.advance();
+                let subject = Expr::parse(parser)?;
+                parser.expect(&Keyword("with"))?;
+                let mut arms = vec![];
+                loop {
+                    let patterns = Pattern::parse(parser)?;
+                    parser.expect(&TokenKind::Operator("=>"))?;
+                    arms.push((patterns, Expr::parse(parser)?));
+                    if !parser.eat(&Symbol(',')) {
+                        break;
+                    }
+                }
+                Expr::Match(Box::new(subject), arms)
+            }
+            // Lambda `|name: type, ...| expr` that captures the local variables it refers
+            Some(Operator(bar @ ("|" | "||"))) => {
+                parser.advance();
+                let mut params = vec![];
+                if bar == "|" {
+                    while !parser.is(&Operator("|")) {
+                        let name = parser.identifier()?;
+                        parser.expect(&Operator(":"))?;
+                        params.push((name, Type::parse(parser)?));
+                        if !parser.eat(&Symbol(',')) {
+                            break;
+                        }
+                    }
+                    parser.expect(&Operator("|"))?;
+                }
+                Expr::Lambda(params, Box::new(Expr::parse(parser)?))
+            }
+            // Prioritize expression `(expr)`
+            Some(Symbol('(')) => {
+                parser.advance();
+                let expr = Expr::parse(parser)?;
+                parser.expect(&Symbol(')'))?;
+                expr
+            }
+            // Code block `{ stmt; ... }`
+            Some(Symbol('{')) => {
+                parser.advance();
+                let block = Block::parse(parser);
+                parser.expect(&Symbol('}'))?;
+                Expr::Block(block?)
+            }
+            // Variable reference
+            Some(Identifier(name)) if name != "true" && name != "false" => {
+                parser.advance();
+                Expr::Variable(name)
+            }
+            // Literal value
+            _ => Expr::Literal(Value::parse(parser)?),
+        };
+        loop {
+            expr = if parser.eat(&Symbol('[')) {
+                // Index access `array[index]`
+                let index = Expr::parse(parser)?;
+                parser.expect(&Symbol(']'))?;
+                Expr::Index(Box::new(expr), Box::new(index))
+            } else if parser.eat(&Symbol('.')) {
+                // Dictionary access `dict.field` or method call `obj.name(args, ...)`
+                let name = parser.identifier()?;
+                if parser.is(&Symbol('(')) {
+                    let args = Expr::parse_args(parser)?;
+                    if name == "memcpy" {
+                        Expr::Clone(Box::new(expr))
+                    } else {
+                        Expr::Call(name, [vec![expr], args].concat())
+                    }
+                } else {
+                    Expr::Field(Box::new(expr), name)
+                }
+            } else if parser.eat(&Operator("?.")) {
+                // Optional field access `dict?.field`
+                Expr::OptionalField(Box::new(expr), parser.identifier()?)
+            } else if let (Expr::Variable(name), true) = (&expr, parser.is(&Symbol('('))) {
+                // Function call `name(args, ...)`
+                let args = Expr::parse_args(parser)?;
+                if name == "memcpy" {
+                    let Some(object) = args.first() else {
+                        return parser.error_at(parser.span_from(start), "memcpy needs an object");
+                    };
+                    Expr::Clone(Box::new(object.clone()))
+                } else {
+                    Expr::Call(name.clone(), args)
+                }
+            } else {
+                break;
+            };
+        }
+        Some(expr)
+    }
+
+    /// Whether the name refers the function itself, not the variable
+    fn is_function_value(name: &str, ctx: &Compiler) -> bool {
+        let is_variable = ctx.global_type.contains_key(name)
+            || ctx.variable_type.contains_key(name)
+            || ctx.argument_type.contains_key(name);
+        !is_variable && (ctx.function_type.contains_key(name) || ctx.export_type.contains_key(name))
+    }
+
+    /// Type of the variable that's called, whose function is looked up in the table,
+    /// or the error type if its definition failed
+    fn callee(name: &str, ctx: &mut Compiler) -> Option<Type> {
+        let typ = ctx
+            .global_type
+            .get(name)
+            .or(ctx.variable_type.get(name))
+            .or(ctx.argument_type.get(name))?;
+        let typ = typ.clone().type_infer(ctx)?;
+        matches!(typ, Type::Function(_, _) | Type::Error).then_some(typ)
+    }
+
+    /// Arguments of function call `(expr, ...)`
+    pub(crate) fn parse_args(parser: &mut Parser) -> Option<Vec<Expr>> {
+        parser.expect(&TokenKind::Symbol('('))?;
+        let mut args = vec![];
+        while !parser.is(&TokenKind::Symbol(')')) {
+            if parser.eat(&TokenKind::Operator("...")) {
+                args.push(Expr::Spread(parser.identifier()?));
+            } else {
+                args.push(Expr::parse(parser)?);
+            }
+            if !parser.eat(&TokenKind::Symbol(',')) {
+                break;
+            }
+        }
+        parser.expect(&TokenKind::Symbol(')'))?;
+        Some(args)
+    }
+
+    /// Desugar f-string into concatenation of strings, whose body starts at the offset
+    fn parse_fstring(parser: &mut Parser, body: &str, offset: usize) -> Option<Expr> {
+        let Some(elms) = str_format(body) else {
+            return parser.error_at(
+                Span::new(offset - 2, offset + body.len() + 1),
+                "unbalanced braces in f-string",
+            );
+        };
+        let mut result = None;
+        let mut position = offset;
+        for elm in elms {
+            let part = if elm.starts_with("{") && elm.ends_with("}") {
+                let mut inner = Parser::at(&elm[1..elm.len() - 1], position + 1);
+                let block = Block::parse(&mut inner);
+                if !inner.is_eof() {
+                    inner.error::<()>(format!("unexpected {}", inner.found()));
+                }
+                parser.diagnostics.append(&mut inner.diagnostics);
+                Expr::Operator(Box::new(Op::Cast(Expr::Block(block?), Type::String)))
+            } else {
+                match unescape(&elm) {
+                    Ok(str) => Expr::Literal(Value::String(str)),
+                    Err((at, errmsg)) => {
+                        let at = Span::new(position + at.start, position + at.end);
+                        return parser.error_at(at, errmsg);
+                    }
+                }
+            };
+            position += elm.len();
+            result = Some(if let Some(result) = result {
+                Expr::Operator(Box::new(Op::Add(result, part)))
+            } else {
+                part
+            });
+        }
+        Some(result.unwrap_or(Expr::Literal(Value::String(String::new()))))
+    }
+
+    /// Address of the element in the array, that aborts out of the bounds if they're checked,
+    /// or whose index wraps around the length. Negative index counts from the end either way
+    pub fn element(array: &Expr, index: &Expr, ctx: &mut Compiler) -> Option<String> {
+        if ctx.bounds_check {
+            let args = [array.compile(ctx)?, index.compile(ctx)?];
+            return Some(ctx.call_builtin(Builtin::Index, &args));
+        }
+        let (array, index) = (Box::new(array.clone()), Box::new(index.clone()));
+        address_calc!(array, index).compile(ctx)
+    }
+
+    /// Type of the field that the dictionary typed value has
+    fn field_type(infered: &Type, key: &str, ctx: &mut Compiler) -> Option<Type> {
+        if let Type::Dict(dict) = infered {
+            let Some((_offset, typ)) = dict.get(key) else {
+                let error_message = format!("{} haven't field `{key}`", infered.format());
+                let fields = dict.keys().cloned().collect::<Vec<_>>().join(", ");
+                let note = format!("available fields are {fields}");
+                ctx.report(Diagnostic::error(error_message).with_note(note));
+                return None;
+            };
+            typ.type_infer(ctx)
+        } else if let Type::Error = infered {
+            Some(Type::Error)
+        } else {
+            let error_message = format!("can't field access to {}", infered.format());
+            ctx.report(Diagnostic::error(error_message));
+            None
+        }
+    }
+
+    /// Names of the variables that the expression refers and assigns,
+    /// that are found on the syntax tree to know what the lambda captures
+    pub fn variables(&self, refer: &mut IndexSet<String>, assign: &mut IndexSet<String>) {
+        match self {
+            Expr::Variable(name) => {
+                refer.insert(name.clone());
+            }
+            Expr::Call(name, args) => {
+                refer.insert(name.clone());
+                args.iter().for_each(|arg| arg.variables(refer, assign));
+            }
+            Expr::Literal(Value::Array(exprs) | Value::Enum(_, _, exprs)) => {
+                exprs.iter().for_each(|expr| expr.variables(refer, assign));
+            }
+            Expr::Literal(Value::Dict(dict)) => {
+                dict.values().for_each(|expr| expr.variables(refer, assign));
+            }
+            Expr::Literal(_) | Expr::Spread(_) => {}
+            Expr::Spanned(expr, _) => expr.variables(refer, assign),
+            Expr::Operator(oper) => match (oper.binop_term(), &**oper) {
+                (Some((lhs, rhs)), _) => {
+                    lhs.variables(refer, assign);
+                    rhs.variables(refer, assign);
+                }
+                (
+                    None,
+                    Op::Neg(expr)
+                    | Op::BNot(expr)
+                    | Op::LNot(expr)
+                    | Op::NullCheck(expr)
+                    | Op::Cast(expr, _)
+                    | Op::Transmute(expr, _),
+                ) => expr.variables(refer, assign),
+                (None, _) => {}
+            },
+            Expr::Index(array, index) | Expr::Poke(array, index) => {
+                array.variables(refer, assign);
+                index.variables(refer, assign);
+            }
+            Expr::Field(expr, _)
+            | Expr::OptionalField(expr, _)
+            | Expr::Clone(expr)
+            | Expr::Peek(expr, _) => expr.variables(refer, assign),
+            Expr::Block(Block(block)) => {
+                block
+                    .iter()
+                    .for_each(|(stmt, _)| stmt.variables(refer, assign));
+            }
+            Expr::Match(subject, arms) => {
+                subject.variables(refer, assign);
+                for (patterns, body) in arms {
+                    for pattern in patterns {
+                        if let Pattern::Variant(_, _, names) = pattern {
+                            assign.extend(names.iter().filter(|name| *name != "_").cloned());
+                        }
+                    }
+                    body.variables(refer, assign);
+                }
+            }
+            // Parameters of the nested lambda aren't the variables of the enclosing scope
+            Expr::Lambda(params, body) => {
+                let (mut inner_refer, mut inner_assign) = (IndexSet::new(), IndexSet::new());
+                body.variables(&mut inner_refer, &mut inner_assign);
+                let is_param = |name: &String| params.iter().any(|(param, _)| param == name);
+                refer.extend(inner_refer.into_iter().filter(|name| !is_param(name)));
+                assign.extend(inner_assign.into_iter().filter(|name| !is_param(name)));
+            }
+        }
+    }
+
+    /// Expression without the span that the parser attached to the operand
+    pub fn unspanned(&self) -> &Expr {
+        match self {
+            Expr::Spanned(expr, _) => expr.unspanned(),
+            expr => expr,
+        }
+    }
+
     pub fn object_size(&self, ctx: &mut Compiler) -> Option<Expr> {
         match self.type_infer(ctx)? {
             Type::Dict(dict) => Some(Expr::Literal(Value::Integer(dict.len() as i32 * BYTES))),
@@ -288,3 +997,52 @@ impl Expr {
         }
     }
 }
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Operand of postfix access that binds looser than it is enclosed in parentheses
+        let base = |expr: &Expr| match expr.unspanned() {
+            Expr::Operator(_)
+            | Expr::Literal(Value::Enum(_, _, _) | Value::Integer(..0))
+            | Expr::Peek(_, _)
+            | Expr::Poke(_, _) => format!("({expr})"),
+            Expr::Literal(Value::Number(n)) if n.is_sign_negative() => format!("({expr})"),
+            _ => expr.to_string(),
+        };
+        let join = |args: &[Expr]| {
+            args.iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            Expr::Literal(value) => write!(f, "{value}"),
+            Expr::Variable(name) => write!(f, "{name}"),
+            Expr::Operator(oper) => write!(f, "{oper}"),
+            Expr::Call(name, args) => write!(f, "{name}({})", join(args)),
+            Expr::Index(array, index) => write!(f, "{}[{index}]", base(array)),
+            Expr::Field(dict, name) => write!(f, "{}.{name}", base(dict)),
+            Expr::OptionalField(dict, name) => write!(f, "{}?.{name}", base(dict)),
+            Expr::Block(block) => write!(f, "{{ {block} }}"),
+            Expr::Clone(from) => write!(f, "memcpy({from})"),
+            // Internal expressions that have no syntax are printed as pseudo function calls
+            Expr::Peek(addr, typ) => write!(f, "peek({addr}): {typ}"),
+            Expr::Poke(addr, value) => write!(f, "poke({addr}, {value})"),
+            // Parenthesized not to take the following arms or operators in
+            Expr::Match(subject, arms) => {
+                let arms = arms.iter().map(|(patterns, body)| {
+                    let patterns = patterns.iter().map(|x| x.to_string());
+                    format!("{} => {body}", patterns.collect::<Vec<_>>().join(" | "))
+                });
+                let arms = arms.collect::<Vec<_>>().join(", ");
+                write!(f, "(match {subject} with {arms})")
+            }
+            Expr::Lambda(params, body) => {
+                let params = params.iter().map(|(name, typ)| format!("{name}: {typ}"));
+                write!(f, "(|{}| {body})", params.collect::<Vec<_>>().join(", "))
+            }
+            Expr::Spread(name) => write!(f, "...{name}"),
+            Expr::Spanned(expr, _) => write!(f, "{expr}"),
+        }
+    }
+}
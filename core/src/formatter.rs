@@ -0,0 +1,346 @@
+use crate::*;
+
+/// Indentation of a nesting level
+const INDENT: &str = "    ";
+
+/// Format the source code in the canonical style.
+/// Comments and line breaks are preserved, and blank lines are collapsed into one
+pub fn format_source(source: &str) -> Result<String, Vec<Diagnostic>> {
+    parse_source(source)?;
+    let cst = Cst::parse(source)?;
+    let mut formatter = Formatter::default();
+    let mut frame = Frame::new(Nest::Top, 0);
+    formatter.items(&cst.items, &mut frame);
+    formatter.trivia(&cst.trailing, &frame);
+    let output = format!("{}\n", formatter.output.trim_end());
+
+    // Formatting must change nothing but whitespaces
+    let kinds = |code: &str| tokenize(code, 0).0.into_iter().map(|x| x.kind);
+    if !kinds(source).eq(kinds(&output)) {
+        let errmsg = "formatting failed because it changes tokens of the code";
+        return Err(vec![Diagnostic::error(errmsg)]);
+    }
+    Ok(output)
+}
+
+/// Kind of nesting that decides indentation of the lines in it
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Nest {
+    Top,
+    Block,
+    Dict,
+    /// Enumerate type `( a | b )` that's padded with spaces
+    Enum,
+    Bracket,
+}
+
+/// Nesting that the tokens being formatted are in
+#[derive(Debug)]
+struct Frame {
+    nest: Nest,
+    /// Indentation of the line where the nesting opens
+    indent: usize,
+    /// Whether the next token starts a statement
+    is_stmt_start: bool,
+    /// First token of the statement
+    head: Option<TokenKind>,
+    /// Indentation of the lines having `then` that `else` hasn't matched yet
+    thens: Vec<usize>,
+    /// Whether the parameters of lambda `|x: T, ...|` are open
+    is_params: bool,
+}
+
+/// Token that is written last, to decide spacing
+#[derive(Debug)]
+struct Prev {
+    kind: TokenKind,
+    is_operand_end: bool,
+    is_prefix: bool,
+}
+
+#[derive(Debug, Default)]
+struct Formatter {
+    output: String,
+    /// Indentation of the line being written
+    indent: usize,
+    prev: Option<Prev>,
+    /// Line breaks in the source code before the next token
+    breaks: usize,
+    /// Line breaks required by the structure before the next token
+    forced: usize,
+    /// Whether to put a space before the next token anyway
+    padded: bool,
+    /// Type arguments `Name<T, ...>` that are open
+    angles: usize,
+    /// Whether the next token is the angle bracket of type arguments
+    is_angle: bool,
+    /// Whether the next token is the bar around lambda parameters
+    is_bar: bool,
+}
+
+impl Frame {
+    fn new(nest: Nest, indent: usize) -> Self {
+        Frame {
+            nest,
+            indent,
+            is_stmt_start: true,
+            head: None,
+            thens: vec![],
+            is_params: false,
+        }
+    }
+
+    /// Indentation of the lines inside of the nesting
+    fn inner(&self) -> usize {
+        match self.nest {
+            Nest::Top => 0,
+            _ => self.indent + 1,
+        }
+    }
+}
+
+impl Formatter {
+    fn items(&mut self, items: &[Syntax], frame: &mut Frame) {
+        use TokenKind::{Identifier, Operator};
+        for (i, item) in items.iter().enumerate() {
+            match item {
+                Syntax::Leaf(leaf) => {
+                    let is_after_name = i > 0
+                        && matches!(&items[i - 1], Syntax::Leaf(x) if matches!(x.token.kind, Identifier(_)));
+                    match leaf.token.kind {
+                        Operator("<")
+                            if is_after_name
+                                && leaf.trivia.is_empty()
+                                && is_type_args(&items[i..]) =>
+                        {
+                            self.angles += 1;
+                            self.is_angle = true;
+                        }
+                        Operator(">") if self.angles > 0 => {
+                            self.angles -= 1;
+                            self.is_angle = true;
+                        }
+                        Operator(">>") if self.angles > 0 => {
+                            self.angles = self.angles.saturating_sub(2);
+                            self.is_angle = true;
+                        }
+                        // Bar in the place of operand opens lambda parameters
+                        Operator("|") if frame.is_params || !self.is_operand_end() => {
+                            frame.is_params = !frame.is_params;
+                            self.is_bar = true;
+                        }
+                        _ => {}
+                    }
+                    self.leaf(leaf, frame)
+                }
+                Syntax::Group(open, items, close) => self.group(open, items, close, frame),
+            }
+        }
+    }
+
+    fn leaf(&mut self, leaf: &Leaf, frame: &mut Frame) {
+        use TokenKind::{Keyword, Symbol};
+        self.trivia(&leaf.trivia, frame);
+        let is_statement = matches!(frame.nest, Nest::Top | Nest::Block);
+        if frame.is_stmt_start {
+            frame.head = Some(leaf.token.kind.clone());
+        }
+        let indent = match leaf.token.kind {
+            Keyword("else") => frame.thens.pop(),
+            _ => None,
+        };
+        // Continuation line of the statement is indented more
+        let indent = indent.unwrap_or_else(|| {
+            if frame.is_stmt_start || !is_statement {
+                frame.inner()
+            } else {
+                frame.inner() + 1
+            }
+        });
+        self.token(leaf, indent);
+        match leaf.token.kind {
+            Symbol(';') if is_statement => {
+                frame.is_stmt_start = true;
+                frame.thens.clear();
+                self.forced = 1;
+            }
+            Keyword("then") => {
+                frame.thens.push(self.indent);
+                frame.is_stmt_start = false;
+            }
+            _ => frame.is_stmt_start = false,
+        }
+    }
+
+    fn group(&mut self, open: &Leaf, items: &[Syntax], close: &Option<Leaf>, frame: &mut Frame) {
+        use TokenKind::{Keyword, Operator, Symbol};
+        let is_type = self.is_prev(&Operator(":"))
+            || (self.is_prev(&Operator("=")) && frame.head == Some(Keyword("type")));
+        let nest = match open.token.kind {
+            Symbol('{') if self.is_prev(&Symbol('@')) => Nest::Dict,
+            Symbol('{') => Nest::Block,
+            Symbol('(') if is_type => Nest::Enum,
+            _ => Nest::Bracket,
+        };
+        self.leaf(open, frame);
+        let mut inner = Frame::new(nest, self.indent);
+        self.padded = nest == Nest::Enum;
+
+        // Code block having multiple statements or line breaks is written in lines
+        let is_separator = |x: &Syntax| matches!(x, Syntax::Leaf(x) if x.token.kind == Symbol(';'));
+        let text = items.iter().map(|x| x.to_string()).collect::<String>();
+        let is_multiline = nest == Nest::Block
+            && (items.iter().any(is_separator)
+                || text.contains('\n')
+                || close
+                    .iter()
+                    .any(|x| x.trivia.iter().any(|x| x.to_string().contains('\n'))));
+        if is_multiline {
+            self.forced = 1;
+        }
+        self.items(items, &mut inner);
+        if let Some(close) = close {
+            self.trivia(&close.trivia, &inner);
+            if is_multiline {
+                self.forced = 1;
+            }
+            self.padded = nest == Nest::Enum;
+            self.token(close, inner.indent);
+        }
+    }
+
+    /// Write comments keeping whether they're on own lines, and count line breaks
+    fn trivia(&mut self, trivia: &[Trivia], frame: &Frame) {
+        for trivia in trivia {
+            match trivia {
+                Trivia::Whitespace(space) => {
+                    let breaks = space.matches('\n').count().min(2);
+                    self.breaks = self.breaks.max(breaks);
+                }
+                Trivia::Comment(comment) => {
+                    if self.output.is_empty() || self.breaks > 0 {
+                        self.newline(frame.inner());
+                    } else {
+                        self.output.push(' ');
+                    }
+                    self.output.push_str(comment);
+                }
+            }
+        }
+    }
+
+    fn token(&mut self, leaf: &Leaf, indent: usize) {
+        use TokenKind::{Identifier, Number, Operator, String, Symbol};
+        let kind = &leaf.token.kind;
+        let is_angle = std::mem::take(&mut self.is_angle);
+        let is_bar = std::mem::take(&mut self.is_bar);
+        // Type arguments are attached to the name like `Option<int>`,
+        // and lambda parameters to the bars like `|x: int|`
+        let is_prefix = (matches!(kind, Operator("-" | "!" | "~")) && !self.is_operand_end())
+            || kind == &Operator("...")
+            || (is_angle && kind == &Operator("<"))
+            || (is_bar && !self.is_operand_end());
+        let is_postfix = match kind {
+            Operator("?") => true,
+            Operator("!") => self.is_operand_end(),
+            Operator("|") if is_bar => self.is_operand_end(),
+            _ => false,
+        };
+
+        if self.output.is_empty() {
+            self.indent = indent;
+        } else if self.breaks.max(self.forced) > 0 {
+            self.newline(indent);
+        } else if !is_angle && (self.padded || self.is_spaced(kind, is_postfix)) {
+            self.output.push(' ');
+        }
+        self.output.push_str(&leaf.text);
+
+        let is_operand_end = (is_postfix && !is_bar)
+            || (is_angle && kind != &Operator("<"))
+            || matches!(
+                kind,
+                Identifier(_)
+                    | Number(_)
+                    | String(_)
+                    | TokenKind::FString(_)
+                    | Symbol(')' | ']' | '}')
+            );
+        self.prev = Some(Prev {
+            kind: kind.clone(),
+            is_operand_end,
+            is_prefix,
+        });
+        self.forced = 0;
+        self.padded = false;
+    }
+
+    fn newline(&mut self, indent: usize) {
+        let breaks = self.breaks.max(self.forced).max(1);
+        if !self.output.is_empty() {
+            self.output.truncate(self.output.trim_end().len());
+            self.output.push_str(&"\n".repeat(breaks));
+        }
+        self.output.push_str(&INDENT.repeat(indent));
+        self.indent = indent;
+        self.breaks = 0;
+    }
+
+    fn is_operand_end(&self) -> bool {
+        self.prev.as_ref().is_some_and(|x| x.is_operand_end)
+    }
+
+    fn is_prev(&self, kind: &TokenKind) -> bool {
+        self.prev.as_ref().is_some_and(|x| &x.kind == kind)
+    }
+
+    /// Whether to put a space between the previous token and the next one on the same line
+    fn is_spaced(&self, kind: &TokenKind, is_postfix: bool) -> bool {
+        use TokenKind::{Operator, Symbol};
+        let Some(prev) = &self.prev else {
+            return false;
+        };
+        match (&prev.kind, kind) {
+            (Symbol('(' | '[' | '@' | '#' | '.'), _) => false,
+            (_, Symbol(')' | ']' | ',' | ';' | '#' | '.')) => false,
+            (Symbol('{'), Symbol('}')) => false,
+            // `~~` is the start of a comment
+            (Operator("~"), Operator("~")) => true,
+            // `??` is the null-coalescing operator
+            (Operator("?"), Operator("?")) => true,
+            _ if prev.is_prefix => false,
+            // Function call and index access
+            (_, Symbol('(' | '[')) => !prev.is_operand_end,
+            // Type annotation and cast `expr: type`
+            (_, Operator(":")) => false,
+            // Range `start..end`
+            (Operator(".." | "..="), _) | (_, Operator(".." | "..=")) => false,
+            // Optional field access `dict?.field`
+            (Operator("?."), _) | (_, Operator("?.")) => false,
+            _ => !is_postfix,
+        }
+    }
+}
+
+/// Whether the items starting with `<` are type arguments closed by `>`, not comparison
+fn is_type_args(items: &[Syntax]) -> bool {
+    use TokenKind::{Identifier, Operator, Symbol};
+    let mut depth = 0;
+    for item in items {
+        let Syntax::Leaf(leaf) = item else {
+            continue;
+        };
+        match leaf.token.kind {
+            Operator("<") => depth += 1,
+            Operator(">") => depth -= 1,
+            Operator(">>") => depth -= 2,
+            Identifier(_) | Symbol(',' | '@') | Operator("|") => {}
+            _ => return false,
+        }
+        // `>>` may close the outer type arguments together
+        if depth <= 0 {
+            return true;
+        }
+    }
+    false
+}
@@ -1,115 +1,195 @@
 use crate::*;
 
-pub fn tokenize(
-    input: &str,
-    delimiter: &[&str],
-    is_expr: bool,
-    is_trim: bool,
-    is_split: bool,
-) -> Option<Vec<String>> {
-    let mut tokens: Vec<String> = Vec::new();
-    let mut current_token = String::new();
-    let mut in_parentheses: usize = 0;
-    let mut in_quote = false;
-    let mut is_escape = false;
-    let mut is_comment = false;
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Identifier(String),
+    Keyword(&'static str),
+    Number(String),
+    String(String),
+    FString(String),
+    Operator(&'static str),
+    /// Brackets and punctuation `( ) [ ] { } , ; . # @`
+    Symbol(char),
+    Comment(String),
+}
 
-    let chars: Vec<String> = input.chars().map(String::from).collect();
-    let mut index = 0;
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
 
-    fn include_letter(query: &str, chars: &Vec<String>, idx: usize) -> bool {
-        chars
-            .clone()
-            .get(idx..idx + query.chars().count())
-            .map(|i| query == i.concat())
-            .unwrap_or(false)
-    }
+/// Split the source code into tokens, whose spans are shifted by the offset.
+/// Lexical errors are reported along with the tokens recovered from them
+pub fn tokenize(source: &str, offset: usize) -> (Vec<Token>, Vec<Diagnostic>) {
+    let mut tokens = vec![];
+    let mut errors = vec![];
+    let mut index = 0;
+    let span = |start: usize, end: usize| Span::new(offset + start, offset + end);
 
-    while index < chars.len() {
-        let c = chars.get(index)?.to_owned();
-        if include_letter("~~", &chars, index) && !in_quote {
-            is_comment = !is_comment;
-            index += 2;
+    while let Some(c) = source[index..].chars().next() {
+        let start = index;
+        let rest = &source[index..];
+        let kind = if c.is_whitespace() {
+            index += c.len_utf8();
             continue;
-        }
-        if is_comment {
+        } else if let Some(body) = rest.strip_prefix("~~") {
+            let end = body.find("~~").unwrap_or_else(|| {
+                let errmsg = "unterminated comment";
+                errors.push(Diagnostic::error(errmsg).with_span(Some(span(start, start + 2))));
+                body.len()
+            });
+            index = (index + end + 4).min(source.len());
+            TokenKind::Comment(body[..end].to_string())
+        } else if c == '"' || rest.starts_with("f\"") {
+            let prefix = if c == '"' { 1 } else { 2 };
+            let end = string_end(&rest[prefix..]).unwrap_or_else(|| {
+                let errmsg = "unterminated string literal";
+                errors.push(Diagnostic::error(errmsg).with_span(Some(span(start, source.len()))));
+                rest.len() - prefix
+            });
+            let body = rest[prefix..prefix + end].to_string();
+            index = (index + prefix + end + 1).min(source.len());
+            if prefix == 1 {
+                let body_start = start + prefix;
+                TokenKind::String(unescape(&body).unwrap_or_else(|(at, errmsg)| {
+                    let at = span(body_start + at.start, body_start + at.end);
+                    errors.push(Diagnostic::error(errmsg).with_span(Some(at)));
+                    body
+                }))
+            } else {
+                TokenKind::FString(body)
+            }
+        } else if c.is_ascii_digit() {
+            let mut end = digits_end(rest);
+            if let Some(fraction) = rest[end..].strip_prefix('.')
+                && fraction.starts_with(|c: char| c.is_ascii_digit())
+            {
+                end += 1 + digits_end(fraction);
+            }
+            index += end;
+            TokenKind::Number(rest[..end].to_string())
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            index += end;
+            match RESERVED.iter().find(|keyword| **keyword == word) {
+                Some(keyword) => TokenKind::Keyword(keyword),
+                None => TokenKind::Identifier(word.to_string()),
+            }
+        } else if let Some(op) = OPERATOR
+            .iter()
+            // `x?..y` is the range from the null-check, not the optional field access
+            .filter(|op| rest.starts_with(**op) && !(**op == "?." && rest.starts_with("?..")))
+            .max_by_key(|op| op.len())
+        {
+            index += op.len();
+            TokenKind::Operator(op)
+        } else if "()[]{},;.#@".contains(c) {
             index += 1;
+            TokenKind::Symbol(c)
+        } else {
+            let errmsg = format!("unexpected character `{c}`");
+            let span = span(start, start + c.len_utf8());
+            errors.push(Diagnostic::error(errmsg).with_span(Some(span)));
+            index += c.len_utf8();
             continue;
-        }
+        };
+        tokens.push(Token {
+            kind,
+            span: span(start, index),
+        });
+    }
+    (tokens, errors)
+}
+
+/// Byte length of the string literal body, until the closing quote
+fn string_end(source: &str) -> Option<usize> {
+    let mut is_escape = false;
+    for (index, c) in source.char_indices() {
         if is_escape {
-            current_token.push_str(&c);
             is_escape = false;
-            index += 1;
-        } else if ["(", "[", "{"].contains(&c.as_str()) && !in_quote {
-            if is_split && in_parentheses == 0 {
-                tokens.push(current_token.clone());
-                current_token.clear();
-            }
-            current_token.push_str(c.as_str());
-            in_parentheses += 1;
-            index += 1;
-        } else if [")", "]", "}"].contains(&c.as_str()) && !in_quote {
-            current_token.push_str(c.as_str());
-            in_parentheses = in_parentheses.checked_sub(1)?;
-            index += 1;
-        } else if c == "\"" {
-            in_quote = !in_quote;
-            current_token.push_str(c.as_str());
-            index += 1;
-        } else if c == "\\" {
-            current_token.push_str(&c);
+        } else if c == '\\' {
             is_escape = true;
-            index += 1;
-        } else {
-            let mut is_opr = false;
-            if is_expr {
-                for op in OPERATOR {
-                    if include_letter(op, &chars, index) && in_parentheses == 0 && !in_quote {
-                        if current_token.is_empty() {
-                            index += op.chars().count();
-                            tokens.push(op.to_string());
-                        } else {
-                            tokens.push(current_token.to_string());
-                            index += op.chars().count();
-                            tokens.push(op.to_string());
-                            current_token.clear();
-                        }
-                        is_opr = true;
-                        break;
-                    }
-                }
-            }
-            let mut is_delimit = false;
-            if !is_opr {
-                for delimit in delimiter {
-                    if include_letter(delimit, &chars, index) && in_parentheses == 0 && !in_quote {
-                        if current_token.is_empty() {
-                            index += delimit.chars().count();
-                        } else {
-                            tokens.push(current_token.clone());
-                            index += delimit.chars().count();
-                            current_token.clear();
-                        }
-                        is_delimit = true;
-                        break;
-                    }
-                }
-                if !is_delimit {
-                    current_token.push_str(c.as_str());
-                    index += 1;
-                }
-            }
+        } else if c == '"' {
+            return Some(index);
         }
     }
+    None
+}
 
-    // Syntax error check
-    if is_escape || in_quote || in_parentheses != 0 {
-        return None;
+/// Decode escape sequences `\n \t \" \\ \u{...}` in the string literal body.
+/// Braces `\{ \}` can be escaped too, so that f-strings can contain them.
+/// Error has the span of the invalid escape sequence in the body
+pub fn unescape(body: &str) -> Result<String, (Span, String)> {
+    let mut result = String::new();
+    let mut chars = body.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let Some((index, c)) = chars.next() else {
+            let span = Span::new(start, body.len());
+            return Err((span, "incomplete escape sequence".to_string()));
+        };
+        let end = index + c.len_utf8();
+        result.push(match c {
+            'n' => '\n',
+            't' => '\t',
+            '"' | '\\' | '{' | '}' => c,
+            'u' => {
+                let code = body[end..]
+                    .strip_prefix('{')
+                    .and_then(|rest| Some(&rest[..rest.find('}')?]));
+                let Some(code) = code else {
+                    let errmsg = "unicode escape must be in the form `\\u{...}`";
+                    return Err((Span::new(start, end), errmsg.to_string()));
+                };
+                let end = end + code.len() + 2;
+                while chars.next_if(|(index, _)| *index < end).is_some() {}
+                let is_hex =
+                    (1..=6).contains(&code.len()) && code.chars().all(|c| c.is_ascii_hexdigit());
+                let decoded = u32::from_str_radix(code, 16).ok().filter(|_| is_hex);
+                let Some(decoded) = decoded.and_then(char::from_u32) else {
+                    let errmsg = format!("invalid unicode escape `\\u{{{code}}}`");
+                    return Err((Span::new(start, end), errmsg));
+                };
+                decoded
+            }
+            _ => {
+                let errmsg = format!("unknown escape sequence `\\{c}`");
+                return Err((Span::new(start, end), errmsg));
+            }
+        });
     }
-    if !is_trim || (is_trim && !current_token.is_empty()) {
-        tokens.push(current_token.clone());
+    Ok(result)
+}
+
+/// Encode the string as a literal body that `unescape` decodes back into it
+pub fn escape(str: &str) -> String {
+    let mut result = String::new();
+    for c in str.chars() {
+        match c {
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '"' | '\\' => {
+                result.push('\\');
+                result.push(c);
+            }
+            _ if c.is_control() => result.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            _ => result.push(c),
+        }
     }
-    Some(tokens)
+    result
+}
+
+fn digits_end(source: &str) -> usize {
+    source
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(source.len())
 }
 
 pub fn is_identifier(name: &str) -> bool {
@@ -118,7 +198,7 @@ pub fn is_identifier(name: &str) -> bool {
     }
     let mut chars = name.chars();
     let first_char = chars.next().unwrap();
-    if !UnicodeXID::is_xid_start(first_char) {
+    if !(UnicodeXID::is_xid_start(first_char) || first_char == '_') {
         return false;
     }
     if !chars.all(UnicodeXID::is_xid_continue) {
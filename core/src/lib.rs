@@ -1,31 +1,49 @@
+mod allocator;
 mod block;
+mod builtin;
+mod cst;
+mod diagnostic;
+mod expand;
 mod expr;
+mod formatter;
 mod lexer;
+mod module;
 mod op;
+mod parser;
+mod pattern;
 mod stmt;
 mod r#type;
 mod utils;
 mod value;
 
 use crate::utils::expand_global;
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 use unicode_xid::UnicodeXID;
 
 pub use {
+    allocator::Allocator,
     block::Block,
+    builtin::Builtin,
+    cst::{Cst, Leaf, Syntax, Trivia},
+    diagnostic::{Diagnostic, Label, Locate, Severity, Span},
+    expand::MacroParam,
     expr::Expr,
-    lexer::{is_identifier, str_format, tokenize},
-    op::Op,
-    stmt::Stmt,
+    formatter::format_source,
+    lexer::{Token, TokenKind, escape, is_identifier, str_format, tokenize, unescape},
+    module::Module,
+    op::{Assoc, Op},
+    parser::{Parser, parse_source},
+    pattern::{Arm, Pattern},
+    stmt::{Iter, Scope, Stmt},
     r#type::{Dict, Enum, Type},
-    utils::{BYTES, OPERATOR, RESERVED, SPACE, expand_local},
+    utils::{BYTES, OPERATOR, RESERVED, SPACE, expand_local, mangle},
     value::Value,
 };
 
 pub trait Node {
     fn compile(&self, ctx: &mut Compiler) -> Option<String>;
     fn type_infer(&self, ctx: &mut Compiler) -> Option<Type>;
-    fn parse(source: &str) -> Option<Self>
+    fn parse(parser: &mut Parser) -> Option<Self>
     where
         Self: Node + Sized;
 }
@@ -38,6 +56,13 @@ pub struct Function {
     pub returns: Type,
 }
 
+/// Output of the successful compilation
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    /// Generated WebAssembly text format
+    pub wat: String,
+}
+
 /// Context in compiling
 #[derive(Debug, Clone)]
 pub struct Compiler {
@@ -49,20 +74,78 @@ pub struct Compiler {
     pub static_data: Vec<String>,
     /// Set of function declare code
     pub declare_code: Vec<String>,
-    /// Macro code that's processing in compile time
-    pub macro_code: IndexMap<String, (Vec<String>, Expr)>,
+    /// Builtin functions that the program uses
+    pub builtins: IndexSet<Builtin>,
+    /// Whether the program allocates objects in the heap, other than by builtin functions
+    pub allocates: bool,
+    /// Functions referred as values by the indexes in the table,
+    /// with the address of the static closure record if it's named one
+    pub table: IndexMap<String, Option<i32>>,
+    /// Macro code that's expanded before type inference
+    pub macro_code: IndexMap<String, (Vec<MacroParam>, Expr)>,
+    /// Variables in the macro body that's expanding, replaced with the arguments or renamed ones
+    pub macro_scope: IndexMap<String, Expr>,
+    /// Macros that are expanding, to reject recursive ones
+    pub macro_stack: Vec<String>,
+    /// Names used in the program, that local variables renamed in macro bodies must avoid
+    pub macro_names: IndexSet<String>,
+    /// Local variables in scope where the code is expanding, that `let` in the macro bodies
+    /// assigns as the caller's ones instead of binding the renamed ones
+    pub macro_visible: IndexSet<String>,
+    /// Path of the source file that's expanding, that the imported paths are relative to
+    pub file: String,
+    /// Source files of the program by the paths, the main one first and the imported ones
+    pub modules: IndexMap<String, Module>,
+    /// Names defined in the file that's expanding, replaced with the ones qualified by it
+    pub namespace: IndexMap<String, String>,
+    /// Paths of the modules that the file that's expanding imports by the namespace names
+    pub aliases: IndexMap<String, String>,
+    /// Entry files of the dependencies, that are imported by the names like `import "name"`
+    pub packages: IndexMap<String, String>,
+    /// Initial size of the linear memory in pages of 64 KiB
+    pub memory: usize,
+    /// Size in pages that the memory can grow to as the heap needs, or unlimited
+    pub max_memory: Option<usize>,
+    /// Allocator of the heap that objects are created in runtime
+    pub heap: Allocator,
+    /// Whether indexing aborts out of the bounds of the array. Without the checks, the index
+    /// wraps around the length by `%` and reading out of the range returns another element
+    /// silently, so the builds for release turn them off explicitly
+    pub bounds_check: bool,
+    /// Local variables referring objects in the function that's compiling,
+    /// kept in the slots of its frame on the shadow stack in GC mode
+    pub stack_slots: IndexSet<String>,
     /// Operator overload code that's processing in compile time
     pub overload: IndexMap<(usize, (String, String)), String>,
     /// Type alias that's defined by user
     pub type_alias: IndexMap<String, Type>,
-    /// Errors that occurred during compilation
-    pub occurred_error: Option<String>,
+    /// Generic type alias with the type parameters, that's instantiated on use
+    pub generic_type: IndexMap<String, (Vec<String>, Type)>,
+    /// Generic function definition with the type parameters, that's instantiated on call
+    pub generic_code: IndexMap<String, (Vec<String>, Stmt)>,
+    /// Type arguments bound to the parameters of the generic instance that's processing
+    pub type_param: IndexMap<String, Type>,
+    /// Instances of generic types and functions by the mangled names, like `Option<int>`,
+    /// with the generic name and the type arguments
+    pub instance: IndexMap<String, (String, Vec<Type>)>,
+    /// Types of the statements that blocks have inferred by their spans, reused in compiling,
+    /// or none if the copies of the statement by macros or generics have different ones
+    pub stmt_type: IndexMap<Span, Option<Type>>,
+    /// Diagnostics that occurred during compilation
+    pub diagnostics: Vec<Diagnostic>,
+    /// Span of the statement or the operand that's processing
+    pub span: Option<Span>,
     /// Type environment for variable
     pub variable_type: IndexMap<String, Type>,
     /// Type environment for global varibale
     pub global_type: IndexMap<String, Type>,
     /// Type environment for argument
     pub argument_type: IndexMap<String, Type>,
+    /// Nullable local variables and arguments that are known not to be null at the point
+    pub narrowed: IndexSet<String>,
+    /// Nullable local variables that are assigned values may be null, to find the ones
+    /// that the loop body nulls
+    pub nullable_assigned: IndexSet<String>,
     /// Type environment for function
     pub function_type: IndexMap<String, Function>,
     /// Type environment for exported function
@@ -71,6 +154,12 @@ pub struct Compiler {
     pub program_return: Type,
 }
 
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
 impl Compiler {
     pub fn new() -> Self {
         Compiler {
@@ -78,40 +167,118 @@ impl Compiler {
             import_code: vec![],
             static_data: vec![],
             declare_code: vec![],
-            occurred_error: None,
+            builtins: IndexSet::new(),
+            allocates: false,
+            table: IndexMap::new(),
+            diagnostics: vec![],
+            span: None,
             macro_code: IndexMap::new(),
+            macro_scope: IndexMap::new(),
+            macro_stack: vec![],
+            macro_names: IndexSet::new(),
+            macro_visible: IndexSet::new(),
+            file: String::new(),
+            modules: IndexMap::new(),
+            namespace: IndexMap::new(),
+            aliases: IndexMap::new(),
+            packages: IndexMap::new(),
+            memory: 64,
+            max_memory: None,
+            heap: Allocator::default(),
+            bounds_check: true,
+            stack_slots: IndexSet::new(),
             overload: IndexMap::new(),
             type_alias: IndexMap::new(),
+            generic_type: IndexMap::new(),
+            generic_code: IndexMap::new(),
+            type_param: IndexMap::new(),
+            instance: IndexMap::new(),
+            stmt_type: IndexMap::new(),
             variable_type: IndexMap::new(),
             global_type: IndexMap::new(),
             argument_type: IndexMap::new(),
+            narrowed: IndexSet::new(),
+            nullable_assigned: IndexSet::new(),
             function_type: IndexMap::new(),
             export_type: IndexMap::new(),
             program_return: Type::Void,
         }
     }
 
-    pub fn build(&mut self, source: &str) -> Option<String> {
-        let ast = Block::parse(source)?;
+    pub fn build(&mut self, source: &str) -> Result<Artifact, Vec<Diagnostic>> {
+        let ast = self.expand(source)?;
+        match self.generate(&ast) {
+            Some(wat) => Ok(Artifact { wat }),
+            None => {
+                if self.diagnostics.is_empty() {
+                    let msg = "failed to compile or check type consistency";
+                    self.report(Diagnostic::error(msg));
+                }
+                Err(self.diagnostics.clone())
+            }
+        }
+    }
+
+    fn generate(&mut self, ast: &Block) -> Option<String> {
         self.program_return = ast.type_infer(self)?;
+        // Null-checked variables are tracked again along the code that's generating
+        self.open_flow();
+        let frame = self.open_frame();
+        let code = ast.compile(self)?;
+        let code = self.close_frame(frame, code, &self.program_return.clone(), true)?;
+        let roots = self.gc_roots()?;
+        // Host is told why the program traps when the memory can't grow any more,
+        // or an index is out of the bounds. Programs doing neither import nothing from it
+        let is_aborting = self.allocates
+            || self.heap == Allocator::Gc
+            || self.builtins.iter().any(Builtin::allocates);
+        let abort = if is_aborting {
+            Some(Value::String("out of memory".to_string()).compile(self)?)
+        } else {
+            None
+        };
+        let elem = if self.table.is_empty() {
+            String::new()
+        } else {
+            let funcs = self.table.keys().map(|name| format!("${}", mangle(name)));
+            format!("(elem (i32.const 0) {})", join!(funcs.collect::<Vec<_>>()))
+        };
         Some(format!(
-            "(module {import} {memory} {memcpy} {strings} {declare} {global} (func (export \"_start\") {ret} {locals} {code}))",
-            code = ast.compile(self)?,
+            "(module {abort} {import} {memory} {allocator} {builtin} {strings} {declare} {table} {global} (func (export \"_start\") {ret} {locals} {code}))",
+            table = format_args!(
+                "(table $table (export \"table\") {} funcref) {elem} (global $closure (export \"closure\") (mut i32) (i32.const 0))",
+                self.table.len()
+            ),
             ret = compile_return!(self.program_return.clone(), self),
             import = join!(self.import_code),
             strings = join!(self.static_data),
             declare = join!(self.declare_code),
+            builtin = join!(self.builtins.iter().map(Builtin::code).collect::<Vec<_>>()),
             global = expand_global(self)?,
             locals = expand_local(self)?,
-            memory = "(memory $mem (export \"mem\") 64)",
-            memcpy = &format!(
-                "(global $allocator (export \"allocator\") (mut i32) (i32.const {allocator})) {}",
-                format!(
-                    "(func $malloc (export \"malloc\") (param $size i32) (result i32) (global.get $allocator) {}",
-                    "(global.set $allocator (i32.add (global.get $allocator) (local.get $size))))"
-                ),
-                allocator = self.allocator
+            memory = format_args!(
+                "(memory $mem (export \"mem\") {}{}) {}",
+                self.memory,
+                self.max_memory.map(|x| format!(" {x}")).unwrap_or_default(),
+                Allocator::reserve(abort.as_deref())
             ),
+            abort = if is_aborting {
+                "(import \"env\" \"abort\" (func $mem/abort (param i32)))"
+            } else {
+                ""
+            },
+            allocator = self.heap.code(self.allocator, &roots),
         ))
     }
+
+    /// Start checking the function body, returning the null-checked variables of the outer one
+    pub(crate) fn open_flow(&mut self) -> (IndexSet<String>, IndexSet<String>) {
+        let narrowed = std::mem::take(&mut self.narrowed);
+        (narrowed, std::mem::take(&mut self.nullable_assigned))
+    }
+
+    /// Finish checking the function body, restoring the null-checked variables of the outer one
+    pub(crate) fn close_flow(&mut self, outer: (IndexSet<String>, IndexSet<String>)) {
+        (self.narrowed, self.nullable_assigned) = outer;
+    }
 }
@@ -0,0 +1,324 @@
+use crate::*;
+use std::{
+    fs::read_to_string,
+    path::{Component, Path, PathBuf},
+};
+
+/// Parameters and body of the macro
+type MacroDef = (Vec<MacroParam>, Expr);
+
+/// Names that the file refers, switched while expanding the code written in other file:
+/// the path, the namespace, the aliases and the macros
+type FileScope = (
+    String,
+    IndexMap<String, String>,
+    IndexMap<String, String>,
+    IndexMap<String, MacroDef>,
+);
+
+/// Source file of the program, whose definitions are qualified by the prefix
+/// if it's imported from the other one
+#[derive(Clone, Debug)]
+pub struct Module {
+    pub source: String,
+    /// Position where the spans in the file start, not to overlap with the other files
+    pub offset: usize,
+    /// Prefix of the names that the file defines, like `geometry.`
+    pub prefix: String,
+    /// Names of the definitions, and whether they're public
+    pub names: IndexMap<String, bool>,
+    /// Paths of the modules that the file imports by the namespace names
+    pub aliases: IndexMap<String, String>,
+    /// Macros defined in the file by the qualified names
+    pub macros: IndexMap<String, MacroDef>,
+    /// Whether the file is expanding, to detect circular imports
+    pub is_expanding: bool,
+}
+
+impl Module {
+    pub fn new(source: &str, offset: usize, prefix: &str) -> Self {
+        Module {
+            source: source.to_string(),
+            offset,
+            prefix: prefix.to_string(),
+            names: IndexMap::new(),
+            aliases: IndexMap::new(),
+            macros: IndexMap::new(),
+            is_expanding: true,
+        }
+    }
+}
+
+impl Compiler {
+    /// Import the source file in the namespace, returning its definitions
+    /// that are expanded unless it's already imported by other file
+    pub(crate) fn import(
+        &mut self,
+        path: &str,
+        alias: &Option<String>,
+    ) -> Option<Vec<(Stmt, Span)>> {
+        // Dependency of the project is imported by the name
+        let (key, stem) = match self.packages.get(path) {
+            Some(entry) => (resolve("", entry), path.to_string()),
+            None => {
+                let key = resolve(&self.file, path);
+                let stem = Path::new(&key).file_stem().unwrap_or_default();
+                let stem = stem.to_string_lossy().to_string();
+                (key, stem)
+            }
+        };
+        let alias = match alias {
+            Some(alias) => alias.clone(),
+            None if is_identifier(&stem) => stem.clone(),
+            None => {
+                let errmsg = format!("can't name module `{path}` by the file name");
+                let note = format!("give it the name by `import \"{path}\" as name`");
+                self.report(Diagnostic::error(errmsg).with_note(note));
+                return None;
+            }
+        };
+        if self.aliases.get(&alias).is_some_and(|x| *x != key) {
+            let errmsg = format!("namespace `{alias}` is already used by other module");
+            let note = format!("give it other name by `import \"{path}\" as name`");
+            self.report(Diagnostic::error(errmsg).with_note(note));
+            return None;
+        }
+        if let Some(module) = self.modules.get(&key) {
+            if module.is_expanding {
+                // Paths are shown relative to the directory of the main file
+                let main = self.modules.keys().next().map(Path::new);
+                let dir = main.and_then(Path::parent).unwrap_or(Path::new(""));
+                let chain = self.modules.iter().filter(|(_, x)| x.is_expanding);
+                let chain = chain.map(|(path, _)| path).chain([&key]);
+                let chain = chain.filter(|x| !x.is_empty()).map(|x| {
+                    let path = Path::new(x).strip_prefix(dir).unwrap_or(Path::new(x));
+                    path.to_string_lossy().to_string()
+                });
+                let chain = chain.collect::<Vec<_>>();
+                let errmsg = format!("circular import of `{path}`");
+                let note = format!("files import each other: {}", chain.join(" -> "));
+                self.report(Diagnostic::error(errmsg).with_note(note));
+                return None;
+            }
+            self.aliases.insert(alias, key);
+            return Some(vec![]);
+        }
+
+        let source = match read_to_string(&key) {
+            Ok(source) => source,
+            Err(err) => {
+                let errmsg = format!("can't read module `{path}`");
+                self.report(Diagnostic::error(errmsg).with_note(err));
+                return None;
+            }
+        };
+        let offset = self.modules.values();
+        let offset = offset.map(|x| x.offset + x.source.len() + 1).max();
+        let mut prefix = format!("{stem}.");
+        for n in 2.. {
+            if !self.modules.values().any(|x| x.prefix == prefix) {
+                break;
+            }
+            prefix = format!("{stem}{n}.");
+        }
+        let module = Module::new(&source, offset.unwrap_or_default(), &prefix);
+        self.modules.insert(key.clone(), module.clone());
+        for token in tokenize(&source, 0).0 {
+            if let TokenKind::Identifier(name) = token.kind {
+                self.macro_names.insert(name);
+            }
+        }
+        let ast = match Parser::at(&source, module.offset).parse_program() {
+            Ok(ast) => ast,
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    let diagnostic = self.locate(diagnostic);
+                    self.diagnostics.push(diagnostic);
+                }
+                return None;
+            }
+        };
+
+        // Definitions are collected ahead, so that they can be referred before defined
+        let mut names = IndexMap::new();
+        let mut is_failed = false;
+        for (stmt, span) in &ast.0 {
+            let define = match stmt {
+                Stmt::Generic(_, define) => define,
+                stmt => stmt,
+            };
+            let (name, scope) = match define {
+                Stmt::Let(scope, sig, _) => match Stmt::signature(sig) {
+                    Some((Expr::Call(name, _), _)) => (name, scope),
+                    _ => (String::new(), scope),
+                },
+                Stmt::Type(scope, name, _) | Stmt::Macro(scope, name, _, _) => {
+                    (name.clone(), scope)
+                }
+                Stmt::Overload(_, _, _) | Stmt::Import(_, _) | Stmt::Module(_, _) => continue,
+                _ => (String::new(), &Scope::Local),
+            };
+            if name.is_empty() {
+                self.span = Some(*span);
+                let errmsg = "module can only have definitions at the top level";
+                let note = "code is run only at the top level of the main file";
+                self.report(Diagnostic::error(errmsg).with_note(note));
+                is_failed = true;
+                continue;
+            }
+            names.insert(name, matches!(scope, Scope::Global));
+        }
+        if is_failed {
+            return None;
+        }
+
+        self.modules.get_mut(&key)?.names = names;
+        let outer = self.enter(&key);
+        let ast = ast.expand_file(self);
+        let module = self.modules.get_mut(&key)?;
+        module.aliases = self.aliases.clone();
+        module.macros = self.macro_code.clone();
+        module.is_expanding = false;
+        self.leave(outer);
+        self.aliases.insert(alias, key);
+
+        // Public functions of the module are visible from other files, not exported
+        let unexport = |define: Stmt| match define {
+            Stmt::Let(Scope::Global, sig, body) => Stmt::Let(Scope::Local, sig, body),
+            define => define,
+        };
+        let stmts = ast?.0.into_iter().map(|(stmt, span)| match stmt {
+            Stmt::Generic(params, define) => {
+                (Stmt::Generic(params, Box::new(unexport(*define))), span)
+            }
+            stmt => (unexport(stmt), span),
+        });
+        Some(stmts.collect())
+    }
+
+    /// Switch to the names that the file refers, returning ones of the file that's expanding
+    pub(crate) fn enter(&mut self, path: &str) -> FileScope {
+        let module = &self.modules[path];
+        let namespace = module.names.keys();
+        let namespace = namespace.map(|name| (name.clone(), format!("{}{name}", module.prefix)));
+        let namespace = namespace.collect();
+        let aliases = module.aliases.clone();
+        let macros = module.macros.clone();
+        (
+            std::mem::replace(&mut self.file, path.to_string()),
+            std::mem::replace(&mut self.namespace, namespace),
+            std::mem::replace(&mut self.aliases, aliases),
+            std::mem::replace(&mut self.macro_code, macros),
+        )
+    }
+
+    /// Switch back to the names of the file that was expanding
+    pub(crate) fn leave(&mut self, scope: FileScope) {
+        (self.file, self.namespace, self.aliases, self.macro_code) = scope;
+    }
+
+    /// Name qualified by the module that `alias.name` refers, that should be public
+    pub(crate) fn qualify(&mut self, alias: &str, name: &str) -> Option<String> {
+        let module = self.aliases.get(alias).and_then(|x| self.modules.get(x))?;
+        match module.names.get(name) {
+            Some(true) => Some(format!("{}{name}", module.prefix)),
+            Some(false) => {
+                let errmsg = format!("`{name}` of module `{alias}` is private");
+                let note = "mark the definition by `pub` to use it from other files";
+                self.report(Diagnostic::error(errmsg).with_note(note));
+                None
+            }
+            None => {
+                let errmsg = format!("module `{alias}` doesn't define `{name}`");
+                self.report(Diagnostic::error(errmsg));
+                None
+            }
+        }
+    }
+
+    /// Name qualified by the module that the name written in the file refers,
+    /// like `geo.Point` of the imported one or `Point` defined in the file itself
+    pub(crate) fn resolve_name(&mut self, name: &str) -> Option<String> {
+        if let Some((alias, member)) = name.split_once('.')
+            && self.aliases.contains_key(alias)
+        {
+            return self.qualify(alias, member);
+        }
+        Some(
+            self.namespace
+                .get(name)
+                .cloned()
+                .unwrap_or(name.to_string()),
+        )
+    }
+
+    /// Macro definition by the qualified name, with the path of the imported file defining it
+    pub(crate) fn macro_def(&self, name: &str) -> Option<(MacroDef, Option<String>)> {
+        if let Some(define) = self.macro_code.get(name) {
+            return Some((define.clone(), None));
+        }
+        let mut modules = self.modules.iter();
+        modules
+            .find_map(|(path, module)| Some((module.macros.get(name)?.clone(), Some(path.clone()))))
+    }
+
+    /// Attach the path of the imported file that the spans of diagnostic point,
+    /// shifting them to be relative to the file
+    pub(crate) fn locate(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        let Some(span) = diagnostic.span.filter(|_| diagnostic.file.is_none()) else {
+            return diagnostic;
+        };
+        // First one is the main file
+        let mut modules = self.modules.iter().skip(1);
+        let module =
+            modules.find(|(_, x)| (x.offset..=x.offset + x.source.len()).contains(&span.start));
+        let Some((path, module)) = module else {
+            return diagnostic;
+        };
+        let shift = |span: Span| Span::new(span.start - module.offset, span.end - module.offset);
+        diagnostic.span = Some(shift(span));
+        for label in &mut diagnostic.labels {
+            label.span = shift(label.span);
+        }
+        diagnostic.file = Some(path.clone());
+        diagnostic
+    }
+}
+
+impl Block {
+    /// Expand the top level of the file, where the definitions of imported modules are placed
+    pub(crate) fn expand_file(&self, ctx: &mut Compiler) -> Option<Block> {
+        let outer_span = ctx.span;
+        let mut result = vec![];
+        let mut is_failed = false;
+        for (stmt, span) in &self.0 {
+            ctx.span = Some(*span);
+            let stmts = match stmt {
+                Stmt::Module(path, alias) => ctx.import(path, alias),
+                stmt => stmt.expand(ctx).map(|stmt| vec![(stmt, *span)]),
+            };
+            match stmts {
+                Some(stmts) => result.extend(stmts),
+                None => is_failed = true,
+            }
+        }
+        ctx.span = outer_span;
+        if is_failed { None } else { Some(Block(result)) }
+    }
+}
+
+/// Path of the file imported from the other one, whose `.` and `..` are resolved
+fn resolve(from: &str, path: &str) -> String {
+    let dir = Path::new(from).parent().unwrap_or(Path::new(""));
+    let mut result = PathBuf::new();
+    for component in dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if result.file_name().is_some() => {
+                result.pop();
+            }
+            component => result.push(component),
+        }
+    }
+    result.to_string_lossy().to_string()
+}
@@ -1,4 +1,5 @@
 use crate::*;
+use std::fmt::{self, Display};
 
 #[derive(Debug, Clone)]
 pub enum Op {
@@ -7,6 +8,7 @@ pub enum Op {
     Mul(Expr, Expr),
     Div(Expr, Expr),
     Mod(Expr, Expr),
+    Neg(Expr),
     Shr(Expr, Expr),
     Shl(Expr, Expr),
     Eql(Expr, Expr),
@@ -25,91 +27,22 @@ pub enum Op {
     Cast(Expr, Type),
     NullCheck(Expr),
     Nullable(Type),
+    /// Left value unless it's null, otherwise the right one `lhs ?? rhs`
+    Coalesce(Expr, Expr),
     Transmute(Expr, Type),
 }
 
 impl Node for Op {
-    fn parse(source: &str) -> Option<Self> {
-        let tokens: Vec<String> = tokenize(source, SPACE.as_ref(), true, true, false)?;
-        // Parsing is from right to left because operator is left-associative
-        let binopergen = |n: usize| {
-            let operator = tokens.get(n)?;
-            let lhs = &join!(tokens.get(..n)?);
-            let rhs = &join!(tokens.get(n + 1..)?);
-            Some(match operator.as_str() {
-                "+" => Op::Add(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "-" => Op::Sub(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "*" => Op::Mul(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "/" => Op::Div(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "%" => Op::Mod(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                ">>" => Op::Shr(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "<<" => Op::Shl(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "==" => Op::Eql(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "!=" => Op::Neq(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "<" => Op::Lt(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                ">" => Op::Gt(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                ">=" => Op::GtEq(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "<=" => Op::LtEq(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "&" => Op::BAnd(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "|" => Op::BOr(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "^" => Op::XOr(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "&&" => Op::LAnd(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                "||" => Op::LOr(Expr::parse(lhs)?, Expr::parse(rhs)?),
-                ":" => Op::Cast(Expr::parse(lhs)?, Type::parse(rhs)?),
-                _ => return None,
-            })
-        };
-        let unaryopergen = || {
-            let op = tokens.first()?.trim();
-            let token = &join!(tokens.get(1..)?);
-            Some(match op {
-                "~" => Op::BNot(Expr::parse(token)?),
-                "!" => Op::LNot(Expr::parse(token)?),
-                "-" => Op::Sub(
-                    Expr::Operator(Box::new(Op::Sub(Expr::parse(token)?, Expr::parse(token)?))),
-                    Expr::parse(token)?,
-                ),
-                _ => return None,
-            })
-        };
-        let suffixopergen = || {
-            let op = tokens.last()?.trim();
-            let token = &join!(tokens.get(..tokens.len() - 1)?);
-            Some(match op {
-                "?" => Op::NullCheck(Expr::parse(token)?),
-                "!" => Op::Nullable(Type::parse(token)?),
-                _ => return None,
-            })
-        };
-        if let Some(op) = unaryopergen() {
-            return Some(op);
-        }
-        if let Some(op) = suffixopergen() {
-            return Some(op);
-        }
-        for i in 2..tokens.len() {
-            if let Some(op) = binopergen(tokens.len().checked_sub(i)?) {
-                return Some(op);
-            }
+    fn parse(parser: &mut Parser) -> Option<Self> {
+        let start = parser.span();
+        match Expr::parse(parser)? {
+            Expr::Operator(oper) => Some(*oper),
+            _ => parser.error_at(parser.span_from(start), "expected operator expression"),
         }
-        None
     }
 
     fn compile(&self, ctx: &mut Compiler) -> Option<String> {
-        let mut overload = || {
-            let terms = self.binop_term()?;
-            let terms_typ = (
-                terms.0.type_infer(ctx)?.format(),
-                terms.1.type_infer(ctx)?.format(),
-            );
-            let key = (self.overload_id()?, terms_typ);
-            if let Some(func) = ctx.overload.get(&key) {
-                return Expr::Call(func.to_string(), vec![terms.0, terms.1]).compile(ctx);
-            } else {
-                None
-            }
-        };
-        if let Some(overloaded) = overload() {
+        if let Some(overloaded) = self.overload(ctx).and_then(|call| call.compile(ctx)) {
             return Some(overloaded);
         }
         Some(match self {
@@ -122,7 +55,6 @@ impl Node for Op {
             Op::BOr(lhs, rhs) => compile_arithmetic!("or", self, ctx, lhs, rhs),
             Op::XOr(lhs, rhs) => compile_arithmetic!("xor", self, ctx, lhs, rhs),
             Op::LNot(lhs) => compile_compare!("eqz", ctx, lhs),
-            Op::Neq(lhs, rhs) => compile_arithmetic!("ne", self, ctx, lhs, rhs),
             Op::Lt(lhs, rhs) => compile_compare!("lt", ctx, lhs, rhs),
             Op::Gt(lhs, rhs) => compile_compare!("gt", ctx, lhs, rhs),
             Op::LtEq(lhs, rhs) => compile_compare!("le", ctx, lhs, rhs),
@@ -132,20 +64,22 @@ impl Node for Op {
             Op::Add(lhs, rhs) => {
                 let typ = self.type_infer(ctx)?;
                 if let Type::String = typ {
-                    Expr::Call(String::from("concat"), vec![lhs.clone(), rhs.clone()])
-                        .compile(ctx)?
+                    let args = [lhs.compile(ctx)?, rhs.compile(ctx)?];
+                    ctx.call_builtin(Builtin::Concat, &args)
                 } else if let Type::Number | Type::Integer = typ {
                     compile_arithmetic!("add", self, ctx, lhs, rhs)
                 } else {
                     return None;
                 }
             }
-            Op::Eql(lhs, rhs) => {
+            Op::Eql(lhs, rhs) | Op::Neq(lhs, rhs) => {
+                let oper = if let Op::Eql(_, _) = self { "eq" } else { "ne" };
                 if let (Type::String, Type::String) = (lhs.type_infer(ctx)?, rhs.type_infer(ctx)?) {
-                    Expr::Call(String::from("strcmp"), vec![lhs.clone(), rhs.clone()])
-                        .compile(ctx)?
+                    let args = [lhs.compile(ctx)?, rhs.compile(ctx)?];
+                    let order = ctx.call_builtin(Builtin::Compare, &args);
+                    format!("(i32.{oper} {order} (i32.const 0))")
                 } else {
-                    compile_arithmetic!("eq", self, ctx, lhs, rhs)
+                    compile_arithmetic!(oper, self, ctx, lhs, rhs)
                 }
             }
             Op::Mod(lhs, rhs) => {
@@ -157,25 +91,39 @@ impl Node for Op {
                     format!("(f32.sub {lhs} (f32.mul (f32.floor (f32.div {lhs} {rhs})) {rhs}))")
                 }
             }
+            Op::Neg(lhs) => {
+                if let Type::Number = lhs.type_infer(ctx)? {
+                    format!("(f32.neg {})", lhs.compile(ctx)?)
+                } else {
+                    format!("(i32.sub (i32.const 0) {})", lhs.compile(ctx)?)
+                }
+            }
             Op::BNot(lhs) => {
                 let minus_one = Expr::Literal(Value::Integer(-1));
                 compile_arithmetic!("xor", self, ctx, lhs, minus_one)
             }
             Op::Cast(lhs, rhs) => {
                 let rhs = rhs.type_infer(ctx)?;
-                if let (Type::Number | Type::Integer, Type::String) = (lhs.type_infer(ctx)?, &rhs) {
-                    let numized = Expr::Operator(Box::new(Op::Cast(lhs.clone(), Type::Number)));
-                    Expr::Call(String::from("to_str"), vec![numized]).compile(ctx)?
-                } else if let (Type::String, Type::Number | Type::Integer) =
-                    (lhs.type_infer(ctx)?, &rhs)
-                {
-                    Op::Cast(Expr::Call(String::from("to_num"), vec![lhs.clone()]), rhs)
-                        .compile(ctx)?
+                if let (Type::Integer, Type::String) = (lhs.type_infer(ctx)?, &rhs) {
+                    let arg = format!("(i64.extend_i32_s {})", lhs.compile(ctx)?);
+                    ctx.call_builtin(Builtin::FormatInt, &[arg])
+                } else if let (Type::Number, Type::String) = (lhs.type_infer(ctx)?, &rhs) {
+                    let arg = lhs.compile(ctx)?;
+                    ctx.call_builtin(Builtin::FormatNum, &[arg])
+                } else if let (Type::String, Type::Integer) = (lhs.type_infer(ctx)?, &rhs) {
+                    let arg = lhs.compile(ctx)?;
+                    ctx.call_builtin(Builtin::ParseInt, &[arg])
+                } else if let (Type::String, Type::Number) = (lhs.type_infer(ctx)?, &rhs) {
+                    let arg = lhs.compile(ctx)?;
+                    ctx.call_builtin(Builtin::ParseNum, &[arg])
                 } else if let (Type::Integer, Type::Number) = (lhs.type_infer(ctx)?, &rhs) {
                     format!("(f32.convert_i32_s {})", lhs.compile(ctx)?,)
                 } else if let (Type::Number, Type::Integer) = (lhs.type_infer(ctx)?, &rhs) {
                     format!("(i32.trunc_f32_s {})", lhs.compile(ctx)?,)
-                } else if lhs.type_infer(ctx)?.type_infer(ctx)? == rhs {
+                } else if let lhs_type = lhs.type_infer(ctx)?.type_infer(ctx)?
+                    && rhs.accepts(&lhs_type, ctx)
+                {
+                    // Non-null value is converted to the nullable one as is
                     lhs.compile(ctx)?
                 } else {
                     return None;
@@ -188,24 +136,24 @@ impl Node for Op {
             )
             .compile(ctx)?,
             Op::Nullable(_) => Value::Integer(-1).compile(ctx)?,
+            Op::Coalesce(lhs, rhs) => {
+                self.type_infer(ctx)?;
+                // Left value is evaluated once into a temporary local variable
+                let temp = format!("coalesce.{}", ctx.variable_type.len());
+                let typ = lhs.type_infer(ctx)?.type_infer(ctx)?;
+                ctx.variable_type.insert(temp.clone(), typ);
+                let lhs = lhs.compile(ctx)?;
+                format!(
+                    "(block (result i32) {} (if (result i32) (i32.eq (local.get ${temp}) (i32.const -1)) (then {}) (else (local.get ${temp}))))",
+                    ctx.set_local(&temp, lhs)?,
+                    rhs.compile(ctx)?
+                )
+            }
         })
     }
 
     fn type_infer(&self, ctx: &mut Compiler) -> Option<Type> {
-        let mut overload = || {
-            let terms = self.binop_term()?;
-            let terms_typ = (
-                terms.0.type_infer(ctx)?.format(),
-                terms.1.type_infer(ctx)?.format(),
-            );
-            let key = (self.overload_id()?, terms_typ);
-            if let Some(func) = ctx.overload.get(&key) {
-                return Expr::Call(func.to_string(), vec![terms.0, terms.1]).type_infer(ctx);
-            } else {
-                None
-            }
-        };
-        if let Some(overloaded) = overload() {
+        if let Some(overloaded) = self.overload(ctx).and_then(|call| call.type_infer(ctx)) {
             return Some(overloaded);
         }
         match self {
@@ -222,12 +170,18 @@ impl Node for Op {
             | Op::BOr(lhs, rhs)
             | Op::XOr(lhs, rhs) => correct!(lhs, rhs, ctx, Type::Number | Type::Integer),
             Op::Eql(lhs, rhs) | Op::Neq(lhs, rhs) => {
-                correct!(
+                let typ = correct!(
                     lhs,
                     rhs,
                     ctx,
                     Type::Number | Type::Integer | Type::String | Type::Enum(_)
                 )?;
+                if typ.is_tagged() {
+                    let msg = "can't compare enumerators having fields";
+                    let note = "use `match` to check the variant instead";
+                    ctx.report(Diagnostic::error(msg).with_note(note));
+                    return None;
+                }
                 Some(Type::Bool)
             }
             Op::Lt(lhs, rhs) | Op::Gt(lhs, rhs) | Op::LtEq(lhs, rhs) | Op::GtEq(lhs, rhs) => {
@@ -243,6 +197,16 @@ impl Node for Op {
                 type_check!(lhs, Type::Bool, ctx)?;
                 Some(Type::Bool)
             }
+            Op::Neg(lhs) => {
+                let typ = lhs.type_infer(ctx)?.type_infer(ctx)?;
+                if let Type::Number | Type::Integer | Type::Error = typ {
+                    Some(typ)
+                } else {
+                    let msg = format!("can't negate {} typed value", typ.format());
+                    ctx.report(Diagnostic::error(msg));
+                    None
+                }
+            }
             Op::Cast(lhs, rhs) => {
                 let lhs = lhs.type_infer(ctx)?;
                 let rhs = rhs.type_infer(ctx)?;
@@ -251,12 +215,13 @@ impl Node for Op {
                     (Type::Integer, Type::Number) => Some(Type::Number),
                     (Type::String, Type::Integer | Type::Number) => Some(rhs),
                     (Type::Integer | Type::Number, Type::String) => Some(Type::String),
-                    (lhs, rhs) if lhs == rhs => Some(lhs),
+                    (Type::Error, rhs) => Some(rhs),
+                    (lhs, rhs) if rhs.accepts(&lhs, ctx) => Some(rhs),
                     _ => {
                         let [lhs, rhs] = [lhs.format(), rhs.format()];
                         let msg = format!("type {lhs} can't convert to {rhs}");
-                        ctx.occurred_error = Some(msg);
-                        return None;
+                        ctx.report(Diagnostic::error(msg));
+                        None
                     }
                 }
             }
@@ -269,28 +234,148 @@ impl Node for Op {
                 rhs.type_infer(ctx)
             }
             Op::NullCheck(expr) => {
-                if is_ptr!(expr.type_infer(ctx)?, ctx) {
+                let typ = expr.type_infer(ctx)?.type_infer(ctx)?;
+                if is_ptr!(typ, ctx) || matches!(typ, Type::Nullable(_) | Type::Error) {
                     Some(Type::Bool)
                 } else {
-                    let errmsg = format!("can't null-check primitive typed value");
-                    ctx.occurred_error = Some(errmsg);
-                    return None;
+                    let errmsg = "can't null-check primitive typed value";
+                    ctx.report(Diagnostic::error(errmsg));
+                    None
                 }
             }
-            Op::Nullable(typ) => {
-                if is_ptr!(typ, ctx) {
-                    Some(typ.clone())
-                } else {
-                    let errmsg = format!("primitive types are not nullable");
-                    ctx.occurred_error = Some(errmsg);
-                    return None;
+            Op::Nullable(typ) => Type::Nullable(Box::new(typ.clone())).type_infer(ctx),
+            Op::Coalesce(lhs, rhs) => {
+                let typ = lhs.type_infer(ctx)?.type_infer(ctx)?;
+                match typ {
+                    // Default value fills in the null, so it's not null unless the default is
+                    Type::Nullable(inner) => {
+                        if let Type::Nullable(_) = rhs.type_infer(ctx)?.type_infer(ctx)? {
+                            type_check!(Type::Nullable(inner), rhs, ctx)
+                        } else {
+                            type_check!(inner, rhs, ctx)
+                        }
+                    }
+                    typ if is_ptr!(typ, ctx) || typ == Type::Error => type_check!(typ, rhs, ctx),
+                    typ => {
+                        let errmsg = format!("can't null-coalesce {} typed value", typ.format());
+                        let note = "only strings, arrays and dictionaries are nullable";
+                        ctx.report(Diagnostic::error(errmsg).with_note(note));
+                        None
+                    }
                 }
             }
         }
     }
 }
 
+/// Associativity of the binary operator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assoc {
+    /// `a - b - c` is `(a - b) - c`
+    Left,
+    /// `a < b < c` is rejected as syntax error
+    NonAssoc,
+}
+
 impl Op {
+    /// Binding power and associativity of the binary operator.
+    ///
+    /// | Power | Operators                           | Associativity |
+    /// |-------|-------------------------------------|---------------|
+    /// | -     | postfix `?`, `?.`, type `T!`, `T#V` | -             |
+    /// | -     | prefix `-`, `!`, `~`                | -             |
+    /// | -     | cast `expr: type`                   | left          |
+    /// | 10    | `*` `/` `%`                         | left          |
+    /// | 9     | `+` `-`                             | left          |
+    /// | 8     | `<<` `>>`                           | left          |
+    /// | 7     | `&`                                 | left          |
+    /// | 6     | `^`                                 | left          |
+    /// | 5     | `\|`                                | left          |
+    /// | 4     | `??`                                | left          |
+    /// | 3     | `==` `!=` `<` `>` `<=` `>=`         | none          |
+    /// | 2     | `&&`                                | left          |
+    /// | 1     | `\|\|`                              | left          |
+    ///
+    /// Operators without power are applied to an operand before any binary operator.
+    /// `=` is not an expression operator, it's only used in `let` statements
+    pub fn precedence(op: &str) -> Option<(u8, Assoc)> {
+        Some(match op {
+            "*" | "/" | "%" => (10, Assoc::Left),
+            "+" | "-" => (9, Assoc::Left),
+            "<<" | ">>" => (8, Assoc::Left),
+            "&" => (7, Assoc::Left),
+            "^" => (6, Assoc::Left),
+            "|" => (5, Assoc::Left),
+            "??" => (4, Assoc::Left),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => (3, Assoc::NonAssoc),
+            "&&" => (2, Assoc::Left),
+            "||" => (1, Assoc::Left),
+            _ => return None,
+        })
+    }
+
+    /// Build binary operator from its token
+    pub fn binary(op: &str, lhs: Expr, rhs: Expr) -> Option<Op> {
+        Some(match op {
+            "+" => Op::Add(lhs, rhs),
+            "-" => Op::Sub(lhs, rhs),
+            "*" => Op::Mul(lhs, rhs),
+            "/" => Op::Div(lhs, rhs),
+            "%" => Op::Mod(lhs, rhs),
+            ">>" => Op::Shr(lhs, rhs),
+            "<<" => Op::Shl(lhs, rhs),
+            "==" => Op::Eql(lhs, rhs),
+            "!=" => Op::Neq(lhs, rhs),
+            "<" => Op::Lt(lhs, rhs),
+            ">" => Op::Gt(lhs, rhs),
+            ">=" => Op::GtEq(lhs, rhs),
+            "<=" => Op::LtEq(lhs, rhs),
+            "&" => Op::BAnd(lhs, rhs),
+            "|" => Op::BOr(lhs, rhs),
+            "^" => Op::XOr(lhs, rhs),
+            "&&" => Op::LAnd(lhs, rhs),
+            "||" => Op::LOr(lhs, rhs),
+            "??" => Op::Coalesce(lhs, rhs),
+            _ => return None,
+        })
+    }
+
+    /// Token of the binary operator
+    pub fn token(&self) -> Option<&'static str> {
+        Some(match self {
+            Op::Add(_, _) => "+",
+            Op::Sub(_, _) => "-",
+            Op::Mul(_, _) => "*",
+            Op::Div(_, _) => "/",
+            Op::Mod(_, _) => "%",
+            Op::Shr(_, _) => ">>",
+            Op::Shl(_, _) => "<<",
+            Op::Eql(_, _) => "==",
+            Op::Neq(_, _) => "!=",
+            Op::Lt(_, _) => "<",
+            Op::Gt(_, _) => ">",
+            Op::GtEq(_, _) => ">=",
+            Op::LtEq(_, _) => "<=",
+            Op::BAnd(_, _) => "&",
+            Op::BOr(_, _) => "|",
+            Op::XOr(_, _) => "^",
+            Op::LAnd(_, _) => "&&",
+            Op::LOr(_, _) => "||",
+            Op::Coalesce(_, _) => "??",
+            _ => return None,
+        })
+    }
+
+    /// Build prefix operator from its token
+    pub fn unary(op: &str, expr: Expr) -> Option<Op> {
+        Some(match op {
+            "~" => Op::BNot(expr),
+            "!" => Op::LNot(expr),
+            "-" => Op::Neg(expr),
+            _ => return None,
+        })
+    }
+
     pub fn overload_id(&self) -> Option<usize> {
         Some(match self {
             Op::Add(_, _) => 1,
@@ -311,10 +396,62 @@ impl Op {
             Op::XOr(_, _) => 17,
             Op::LAnd(_, _) => 18,
             Op::LOr(_, _) => 19,
+            Op::Neg(_) => 20,
             _ => return None,
         })
     }
 
+    /// Call of the function that overloads the operator for the operand types.
+    /// Operand of unary operator is paired with `void` in the overload key
+    pub fn overload(&self, ctx: &mut Compiler) -> Option<Expr> {
+        let terms = match self {
+            Op::Neg(expr) => vec![expr.clone()],
+            _ => {
+                let (lhs, rhs) = self.binop_term()?;
+                vec![lhs, rhs]
+            }
+        };
+        let mut terms_typ = vec![];
+        for term in &terms {
+            terms_typ.push(term.type_infer(ctx)?.format());
+        }
+        terms_typ.resize(2, Type::Void.format());
+        let key = (
+            self.overload_id()?,
+            (terms_typ[0].clone(), terms_typ[1].clone()),
+        );
+        let func = ctx.overload.get(&key)?.clone();
+        Some(Expr::Call(func, terms))
+    }
+
+    /// Nullable local variables and arguments that aren't null if the condition is the truth
+    pub fn narrowing(cond: &Expr, truth: bool, ctx: &Compiler) -> Vec<String> {
+        let Expr::Operator(oper) = cond.unspanned() else {
+            return vec![];
+        };
+        match (&**oper, truth) {
+            (Op::NullCheck(expr), true) => {
+                let Expr::Variable(name) = expr.unspanned() else {
+                    return vec![];
+                };
+                let is_local =
+                    ctx.variable_type.contains_key(name) || ctx.argument_type.contains_key(name);
+                if is_local && !ctx.global_type.contains_key(name) {
+                    vec![name.clone()]
+                } else {
+                    vec![]
+                }
+            }
+            (Op::LNot(cond), truth) => Op::narrowing(cond, !truth, ctx),
+            (Op::LAnd(lhs, rhs), true) | (Op::LOr(lhs, rhs), false) => [
+                Op::narrowing(lhs, truth, ctx),
+                Op::narrowing(rhs, truth, ctx),
+            ]
+            .concat(),
+            _ => vec![],
+        }
+    }
+
     pub fn binop_term(&self) -> Option<(Expr, Expr)> {
         Some(match self.clone() {
             Op::Add(lhs, rhs) => (lhs, rhs),
@@ -335,7 +472,67 @@ impl Op {
             Op::XOr(lhs, rhs) => (lhs, rhs),
             Op::LAnd(lhs, rhs) => (lhs, rhs),
             Op::LOr(lhs, rhs) => (lhs, rhs),
+            Op::Coalesce(lhs, rhs) => (lhs, rhs),
             _ => return None,
         })
     }
 }
+
+/// Binding power of the expression if it's a binary operator
+fn power(expr: &Expr) -> Option<(u8, Assoc)> {
+    match expr.unspanned() {
+        Expr::Operator(oper) => Op::precedence(oper.token()?),
+        _ => None,
+    }
+}
+
+impl Display for Op {
+    /// Print as source code, enclosing operands in parentheses only if needed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let is_compound = |expr: &Expr| matches!(expr.unspanned(), Expr::Operator(oper) if oper.token().is_some() || matches!(**oper, Op::Cast(_, _)));
+        let prefix = |f: &mut fmt::Formatter<'_>, op: &str, expr: &Expr| {
+            // Leading number is enclosed so as not to be folded into negative literal
+            let is_number = expr.to_string().starts_with(|c: char| c.is_ascii_digit());
+            if is_compound(expr) || (op == "-" && is_number) {
+                write!(f, "{op}({expr})")
+            } else if op == "~" && expr.to_string().starts_with('~') {
+                // `~~` is the start of a comment
+                write!(f, "{op} {expr}")
+            } else {
+                write!(f, "{op}{expr}")
+            }
+        };
+        match self {
+            Op::Neg(expr) => prefix(f, "-", expr),
+            Op::LNot(expr) => prefix(f, "!", expr),
+            Op::BNot(expr) => prefix(f, "~", expr),
+            Op::Cast(expr, typ) if power(expr).is_some() => write!(f, "({expr}): {typ}"),
+            Op::Cast(expr, typ) => write!(f, "{expr}: {typ}"),
+            Op::NullCheck(expr @ Expr::Operator(oper)) if !matches!(**oper, Op::Nullable(_)) => {
+                write!(f, "({expr})?")
+            }
+            Op::NullCheck(expr) => write!(f, "{expr}?"),
+            Op::Nullable(typ) => write!(f, "{typ}!"),
+            // Internal operator that has no syntax is printed as pseudo function call
+            Op::Transmute(expr, typ) => write!(f, "transmute({expr}): {typ}"),
+            _ => {
+                let (Some(op), Some((lhs, rhs))) = (self.token(), self.binop_term()) else {
+                    return Err(fmt::Error);
+                };
+                let (prec, assoc) = Op::precedence(op).ok_or(fmt::Error)?;
+                match power(&lhs) {
+                    Some((lhs_prec, _))
+                        if lhs_prec < prec || (lhs_prec == prec && assoc == Assoc::NonAssoc) =>
+                    {
+                        write!(f, "({lhs})")?
+                    }
+                    _ => write!(f, "{lhs}")?,
+                }
+                match power(&rhs) {
+                    Some((rhs_prec, _)) if rhs_prec <= prec => write!(f, " {op} ({rhs})"),
+                    _ => write!(f, " {op} {rhs}"),
+                }
+            }
+        }
+    }
+}
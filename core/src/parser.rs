@@ -0,0 +1,203 @@
+use crate::*;
+use std::fmt::{self, Display};
+
+/// Cursor over the tokens, collecting syntax errors
+#[derive(Clone, Debug)]
+pub struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+    /// Position where the last consumed token ends
+    last_end: usize,
+    /// Position of the end of the source code
+    eof: Span,
+    /// Tokens `>>` split to close nested type arguments, with their positions to restore
+    splits: Vec<(usize, Token)>,
+    /// Syntax errors that occurred during parsing
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Parser {
+    pub fn new(source: &str) -> Self {
+        Parser::at(source, 0)
+    }
+
+    /// Parser of the source code embedded at the offset of other source code
+    pub fn at(source: &str, offset: usize) -> Self {
+        let end = offset + source.len();
+        let (tokens, diagnostics) = tokenize(source, offset);
+        let is_code = |token: &Token| !matches!(token.kind, TokenKind::Comment(_));
+        Parser {
+            tokens: tokens.into_iter().filter(is_code).collect(),
+            index: 0,
+            last_end: offset,
+            eof: Span::new(end, end),
+            splits: vec![],
+            diagnostics,
+        }
+    }
+
+    pub fn peek(&self) -> Option<&TokenKind> {
+        self.peek_nth(0)
+    }
+
+    pub fn peek_nth(&self, n: usize) -> Option<&TokenKind> {
+        self.tokens.get(self.index + n).map(|token| &token.kind)
+    }
+
+    pub fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index)?.clone();
+        self.index += 1;
+        self.last_end = token.span.end;
+        Some(token)
+    }
+
+    pub fn is(&self, kind: &TokenKind) -> bool {
+        self.peek() == Some(kind)
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.index >= self.tokens.len()
+    }
+
+    /// Consume the token if it's the expected one
+    pub fn eat(&mut self, kind: &TokenKind) -> bool {
+        let is_match = self.is(kind);
+        if is_match {
+            self.advance();
+        }
+        is_match
+    }
+
+    pub fn expect(&mut self, kind: &TokenKind) -> Option<Span> {
+        if self.is(kind) {
+            Some(self.advance()?.span)
+        } else {
+            self.error(format!("expected `{kind}`, found {}", self.found()))
+        }
+    }
+
+    /// Consume the closing angle bracket of type arguments,
+    /// taking `>` out of `>>` that closes nested ones like `Option<Option<int>>`
+    pub fn close_angle(&mut self) -> bool {
+        match self.peek() {
+            Some(TokenKind::Operator(">")) => {
+                self.advance();
+                true
+            }
+            Some(TokenKind::Operator(">>")) => {
+                let token = &mut self.tokens[self.index];
+                self.splits.push((self.index, token.clone()));
+                token.kind = TokenKind::Operator(">");
+                token.span = Span::new(token.span.start + 1, token.span.end);
+                self.last_end = token.span.start;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn identifier(&mut self) -> Option<String> {
+        if let Some(TokenKind::Identifier(name)) = self.peek().cloned() {
+            self.advance();
+            Some(name)
+        } else {
+            self.error(format!("expected identifier, found {}", self.found()))
+        }
+    }
+
+    /// Span of the current token
+    pub fn span(&self) -> Span {
+        self.tokens
+            .get(self.index)
+            .map(|x| x.span)
+            .unwrap_or(self.eof)
+    }
+
+    /// Span from the start position to the end of the last consumed token
+    pub fn span_from(&self, start: Span) -> Span {
+        Span::new(start.start, self.last_end.max(start.start))
+    }
+
+    /// Description of the current token for error messages
+    pub fn found(&self) -> String {
+        match self.peek() {
+            Some(kind) => format!("`{kind}`"),
+            None => String::from("end of input"),
+        }
+    }
+
+    /// Report syntax error at the current token
+    pub fn error<T>(&mut self, message: impl ToString) -> Option<T> {
+        self.error_at(self.span(), message)
+    }
+
+    pub fn error_at<T>(&mut self, span: Span, message: impl ToString) -> Option<T> {
+        let diagnostic = Diagnostic::error(message).with_span(Some(span));
+        if !self.diagnostics.contains(&diagnostic) {
+            self.diagnostics.push(diagnostic);
+        }
+        None
+    }
+
+    /// Try the parser, rewinding the position and discarding errors if it fails
+    pub fn speculate<T>(&mut self, parser: impl FnOnce(&mut Parser) -> Option<T>) -> Option<T> {
+        let (index, last_end) = (self.index, self.last_end);
+        let (checkpoint, splits) = (self.diagnostics.len(), self.splits.len());
+        let result = parser(self);
+        if result.is_none() {
+            (self.index, self.last_end) = (index, last_end);
+            self.diagnostics.truncate(checkpoint);
+            for (index, token) in self.splits.drain(splits..).rev() {
+                self.tokens[index] = token;
+            }
+        }
+        result
+    }
+
+    /// Skip tokens until the end of the statement to recover from syntax error
+    pub fn skip_statement(&mut self) {
+        let mut depth: usize = 0;
+        while let Some(kind) = self.peek() {
+            match kind {
+                TokenKind::Symbol(';') if depth == 0 => break,
+                TokenKind::Symbol(')' | ']' | '}') if depth == 0 => break,
+                TokenKind::Symbol('(' | '[' | '{') => depth += 1,
+                TokenKind::Symbol(')' | ']' | '}') => depth -= 1,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    /// Parse the whole tokens as a program
+    pub fn parse_program(mut self) -> Result<Block, Vec<Diagnostic>> {
+        let ast = Block::parse(&mut self);
+        if !self.is_eof() || (ast.is_none() && self.diagnostics.is_empty()) {
+            self.error::<()>(format!("unexpected {}", self.found()));
+        }
+        match ast {
+            Some(ast) if self.diagnostics.is_empty() => Ok(ast),
+            _ => Err(self.diagnostics),
+        }
+    }
+}
+
+/// Parse the whole source code as a program
+pub fn parse_source(source: &str) -> Result<Block, Vec<Diagnostic>> {
+    Parser::new(source).parse_program()
+}
+
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Identifier(name) => write!(f, "{name}"),
+            TokenKind::Keyword(keyword) => write!(f, "{keyword}"),
+            TokenKind::Number(number) => write!(f, "{number}"),
+            TokenKind::String(string) => write!(f, "\"{}\"", escape(string)),
+            TokenKind::FString(string) => write!(f, "f\"{string}\""),
+            TokenKind::Operator(op) => write!(f, "{op}"),
+            TokenKind::Symbol(symbol) => write!(f, "{symbol}"),
+            TokenKind::Comment(comment) => write!(f, "~~{comment}~~"),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::*;
+use std::fmt::{self, Display};
+
+/// Arm of the match expression: alternative patterns and the result
+pub type Arm = (Vec<Pattern>, Expr);
+
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// Wildcard `_` that matches any value
+    Wildcard,
+    /// Integer, string or enumerator literal
+    Literal(Value),
+    /// Enumerator whose fields are bound to the variables `Type#Variant(name, ...)`
+    Variant(Type, String, Vec<String>),
+}
+
+impl Pattern {
+    /// Patterns separated by `|`, any of which matches the arm
+    pub fn parse(parser: &mut Parser) -> Option<Vec<Pattern>> {
+        let start = parser.span();
+        let mut result = vec![Pattern::parse_single(parser)?];
+        while parser.eat(&TokenKind::Operator("|")) {
+            result.push(Pattern::parse_single(parser)?);
+        }
+        if result.len() > 1 && result.iter().any(|x| matches!(x, Pattern::Variant(..))) {
+            let span = Span::new(start.start, parser.span().start);
+            return parser.error_at(span, "variables can't be bound in or-patterns");
+        }
+        Some(result)
+    }
+
+    fn parse_single(parser: &mut Parser) -> Option<Pattern> {
+        let start = parser.span();
+        Some(match Expr::parse_unary(parser)? {
+            Expr::Variable(name) if name == "_" => Pattern::Wildcard,
+            Expr::Literal(Value::Enum(typ, variant, args)) if !args.is_empty() => {
+                let mut names = vec![];
+                for arg in args {
+                    let Expr::Variable(name) = arg else {
+                        let errmsg = format!("expected variable name to bind, found `{arg}`");
+                        let span = Span::new(start.start, parser.span().start);
+                        return parser.error_at(span, errmsg);
+                    };
+                    names.push(name);
+                }
+                Pattern::Variant(typ, variant, names)
+            }
+            Expr::Literal(
+                value @ (Value::Integer(_) | Value::String(_) | Value::Enum(_, _, _)),
+            ) => Pattern::Literal(value),
+            expr => {
+                let errmsg = format!("expected pattern, found `{expr}`");
+                let note = "patterns are integers, strings, enumerators or `_`";
+                let span = Span::new(start.start, parser.span().start);
+                parser.diagnostics.push(
+                    Diagnostic::error(errmsg)
+                        .with_span(Some(span))
+                        .with_note(note),
+                );
+                return None;
+            }
+        })
+    }
+
+    /// Enumerator that the pattern matches
+    pub fn variant(&self) -> Option<&str> {
+        match self {
+            Pattern::Literal(Value::Enum(_, key, _)) | Pattern::Variant(_, key, _) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Check that the pattern can match the value of the type,
+    /// and declare the variables that it binds
+    pub fn type_check(&self, typ: &Type, ctx: &mut Compiler) -> Option<()> {
+        let pattern = match self {
+            Pattern::Wildcard => return Some(()),
+            // Fields of the enumerator don't have to be matched
+            Pattern::Literal(Value::Enum(enum_type, key, _)) => {
+                Value::variant(enum_type, key, ctx)?;
+                enum_type.type_infer(ctx)?
+            }
+            Pattern::Literal(value) => value.type_infer(ctx)?.type_infer(ctx)?,
+            Pattern::Variant(enum_type, key, names) => {
+                let (_, fields) = Value::variant(enum_type, key, ctx)?;
+                if names.len() != fields.len() {
+                    let errmsg = format!(
+                        "variant `{key}` has {} fields, but {} variables are bound",
+                        fields.len(),
+                        names.len()
+                    );
+                    ctx.report(Diagnostic::error(errmsg));
+                    return None;
+                }
+                for (name, field) in names.iter().zip(fields) {
+                    if name == "_" {
+                        continue;
+                    } else if ctx.argument_type.contains_key(name) {
+                        let msg = format!("can't bind value to argument `{name}`");
+                        let note = "arguments are immutable, bind the value to other name";
+                        ctx.report(Diagnostic::error(msg).with_note(note));
+                        return None;
+                    } else if let Some(exist) = ctx.variable_type.get(name).cloned() {
+                        type_check!(exist, field, ctx)?;
+                    } else {
+                        ctx.variable_type.insert(name.clone(), field);
+                    }
+                }
+                enum_type.type_infer(ctx)?
+            }
+        };
+        if pattern != *typ && *typ != Type::Error {
+            let errmsg = format!(
+                "pattern `{self}` doesn't match {} typed value",
+                typ.compress_alias(ctx).format()
+            );
+            ctx.report(Diagnostic::error(errmsg));
+            return None;
+        }
+        Some(())
+    }
+
+    /// Code that assigns the fields of the enumerator at the pointer to the variables
+    pub fn bind(&self, pointer: &str, ctx: &mut Compiler) -> Option<String> {
+        let Pattern::Variant(enum_type, key, names) = self else {
+            return Some(String::new());
+        };
+        let (_, fields) = Value::variant(enum_type, key, ctx)?;
+        let mut result = vec![];
+        for (index, (name, typ)) in names.iter().zip(fields).enumerate() {
+            if name != "_" {
+                let field = format!(
+                    "({typ}.load offset={offset} (local.get ${pointer}))",
+                    typ = typ.compile(ctx)?,
+                    offset = (index as i32 + 1) * BYTES,
+                );
+                result.push(ctx.set_local(name, field)?);
+            }
+        }
+        Some(join!(result))
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Literal(value) => write!(f, "{value}"),
+            Pattern::Variant(typ, variant, names) => {
+                write!(f, "{typ}#{variant}({})", names.join(", "))
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::*;
+use std::fmt::{self, Display};
 
 /// Import function signature: name, arguments, return, alias
 type Signature = (String, Vec<(String, Type)>, Type);
@@ -8,16 +9,32 @@ pub enum Stmt {
     Let(Scope, Expr, Expr),
     If(Expr, Expr, Option<Box<Stmt>>),
     While(Expr, Expr),
-    Type(String, Type),
+    For(String, Iter, Expr),
+    Type(Scope, String, Type),
+    /// Type alias or function definition having the type parameters `<T, ...>`
+    Generic(Vec<String>, Box<Stmt>),
     Try(Expr, Box<Stmt>),
-    Macro(String, Vec<String>, Expr),
+    Macro(Scope, String, Vec<MacroParam>, Expr),
     Overload(usize, (Type, Type), String),
     Import(Option<String>, Signature),
+    /// Source file whose public definitions are imported in the namespace `import "path" as name`
+    Module(String, Option<String>),
     Return(Option<Expr>),
     Break,
     Next,
 }
 
+/// Values that `for` statement binds to the loop variable in order
+#[derive(Clone, Debug)]
+pub enum Iter {
+    /// Elements of the array
+    Array(Expr),
+    /// Integers from the start to before the end `start..end`
+    Range(Expr, Expr),
+    /// Integers from the start to the end `start..=end`
+    RangeInclusive(Expr, Expr),
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum Scope {
     Global,
@@ -25,44 +42,80 @@ pub enum Scope {
 }
 
 impl Node for Stmt {
-    fn parse(source: &str) -> Option<Self> {
-        let source = source.trim();
-        if let Some(source) = source.strip_prefix("if ") {
-            let tokens = tokenize(source, SPACE.as_ref(), false, true, false)?;
-            let then = tokens.iter().position(|i| i == "then")?;
-            if let Some(r#else) = tokens.iter().position(|i| i == "else") {
-                let cond = Expr::parse(&join!(tokens.get(0..then)?))?;
-                let then = Expr::parse(&join!(tokens.get(then + 1..r#else)?))?;
-                let r#else = Stmt::parse(&join!(tokens.get(r#else + 1..)?))?;
-                Some(Stmt::If(cond, then, Some(Box::new(r#else))))
-            } else {
-                let cond = Expr::parse(&join!(tokens.get(0..then)?))?;
-                let then = Expr::parse(&join!(tokens.get(then + 1..)?))?;
-                Some(Stmt::If(cond, then, None))
-            }
-        } else if let Some(source) = source.strip_prefix("while ") {
-            let tokens = tokenize(source, SPACE.as_ref(), false, true, false)?;
-            let r#loop = tokens.iter().position(|i| i == "loop")?;
-            let cond = Expr::parse(&join!(tokens.get(0..r#loop)?))?;
-            let body = Expr::parse(&join!(tokens.get(r#loop + 1..)?))?;
-            Some(Stmt::While(cond, body))
-        } else if let Some(source) = source.strip_prefix("try ") {
-            let tokens = tokenize(source, SPACE.as_ref(), false, true, false)?;
-            let r#catch = tokens.iter().position(|i| i == "catch")?;
-            let expr = Expr::parse(&join!(tokens.get(0..r#catch)?))?;
-            let r#catch = Stmt::parse(&join!(tokens.get(r#catch + 1..)?))?;
-            Some(Stmt::Try(expr, Box::new(r#catch)))
-        } else if let Some(token) = source.strip_prefix("let ") {
-            if let Some((name, value)) = token.split_once("=") {
-                let (name, value) = (Expr::parse(name)?, Expr::parse(value)?);
-                Some(Stmt::Let(Scope::Local, name, value))
-            } else {
-                let source = Op::parse(token)?;
+    fn parse(parser: &mut Parser) -> Option<Self> {
+        use TokenKind::{Identifier, Keyword, Operator, Symbol};
+        let is_definition = matches!(parser.peek_nth(1), Some(Identifier(_)));
+        Some(match parser.peek().cloned() {
+            Some(Keyword("if")) => {
+                parser.advance();
+                let cond = Expr::parse(parser)?;
+                parser.expect(&Keyword("then"))?;
+                let then = Expr::parse(parser)?;
+                if parser.eat(&Keyword("else")) {
+                    let r#else = Stmt::parse(parser)?;
+                    Stmt::If(cond, then, Some(Box::new(r#else)))
+                } else {
+                    Stmt::If(cond, then, None)
+                }
+            }
+            Some(Keyword("while")) => {
+                parser.advance();
+                let cond = Expr::parse(parser)?;
+                parser.expect(&Keyword("loop"))?;
+                let body = Expr::parse(parser)?;
+                Stmt::While(cond, body)
+            }
+            Some(Keyword("for")) => {
+                parser.advance();
+                let name = parser.identifier()?;
+                parser.expect(&Keyword("in"))?;
+                let collection = Expr::parse(parser)?;
+                let iter = if parser.eat(&Operator("..")) {
+                    Iter::Range(collection, Expr::parse(parser)?)
+                } else if parser.eat(&Operator("..=")) {
+                    Iter::RangeInclusive(collection, Expr::parse(parser)?)
+                } else {
+                    Iter::Array(collection)
+                };
+                parser.expect(&Keyword("loop"))?;
+                let body = Expr::parse(parser)?;
+                Stmt::For(name, iter, body)
+            }
+            Some(Keyword("try")) => {
+                parser.advance();
+                let expr = Expr::parse(parser)?;
+                parser.expect(&Keyword("catch"))?;
+                let r#catch = Stmt::parse(parser)?;
+                Stmt::Try(expr, Box::new(r#catch))
+            }
+            Some(Keyword("let")) => {
+                parser.advance();
+                // Generic function `let name<T, ...>(arg: type, ...) = expr`
+                if let (Some(Identifier(name)), Some(Operator("<"))) =
+                    (parser.peek().cloned(), parser.peek_nth(1))
+                {
+                    parser.advance();
+                    let params = Stmt::parse_params(parser)?;
+                    let mut sig = Expr::Call(name, Expr::parse_args(parser)?);
+                    if parser.eat(&Operator(":")) {
+                        sig = Expr::Operator(Box::new(Op::Cast(sig, Type::parse(parser)?)));
+                    }
+                    parser.expect(&Operator("="))?;
+                    let define = Stmt::Let(Scope::Local, sig, Expr::parse(parser)?);
+                    return Some(Stmt::Generic(params, Box::new(define)));
+                }
+                let name = Expr::parse(parser)?;
+                if parser.eat(&Operator("=")) {
+                    return Some(Stmt::Let(Scope::Local, name, Expr::parse(parser)?));
+                }
                 macro_rules! assign_with {
                     ($op: ident) => {
-                        if let Op::$op(name, value) = source {
-                            let value = Expr::Operator(Box::new(Op::$op(name.clone(), value)));
-                            return Some(Stmt::Let(Scope::Local, name, value));
+                        if let Expr::Operator(oper) = &name {
+                            if let Op::$op(name, value) = *oper.clone() {
+                                let value = Expr::Operator(Box::new(Op::$op(name.clone(), value)));
+                                let name = name.unspanned().clone();
+                                return Some(Stmt::Let(Scope::Local, name, value));
+                            }
                         }
                     };
                 }
@@ -71,96 +124,293 @@ impl Node for Stmt {
                 assign_with!(Mul);
                 assign_with!(Div);
                 assign_with!(Mod);
-                None
+                return parser.error(format!("expected `=`, found {}", parser.found()));
             }
-        } else if let Some(token) = source.strip_prefix("pub ") {
-            let Stmt::Let(Scope::Local, name, value) = Stmt::parse(token)? else {
-                return None;
-            };
-            Some(Stmt::Let(Scope::Global, name, value))
-        } else if let Some(source) = source.strip_prefix("type ") {
-            let (name, value) = source.split_once("=")?;
-            let Some(Expr::Variable(name)) = Expr::parse(name) else {
-                return None;
-            };
-            Some(Stmt::Type(name, Type::parse(value)?))
-        } else if let Some(source) = source.strip_prefix("macro ") {
-            let (head, value) = source.split_once("=")?;
-            let Expr::Call(name, args) = Expr::parse(head)? else {
-                return None;
-            };
-            let func = |x: &Expr| {
-                let Expr::Variable(x) = x else { return None };
-                Some(x.clone())
-            };
-            let args = args.iter().map(func).collect::<Option<Vec<_>>>()?;
-            Some(Stmt::Macro(name, args, Expr::parse(value)?))
-        } else if let Some(source) = source.strip_prefix("overload ") {
-            let (name, value) = source.split_once("=")?;
-            let tokens = tokenize(value, SPACE.as_ref(), true, true, false)?;
-            let [lhs, op, rhs] = tokens.as_slice() else {
-                return None;
-            };
-            Some(Stmt::Overload(
-                Op::parse(&format!("0 {op} 0"))?.overload_id()?,
-                (Type::parse(lhs)?, Type::parse(rhs)?),
-                name.trim().to_owned(),
-            ))
-        } else if let Some(after) = source.strip_prefix("load ") {
-            let rest = after.trim_start();
-            if let Some((module, sigs)) = rest.split_once(".") {
-                let module = module.trim().to_string();
-                Some(Stmt::Import(Some(module), import_args!(sigs)))
-            } else {
-                Some(Stmt::Import(None, import_args!(after)))
-            }
-        } else if let Some(source) = source.strip_prefix("return ") {
-            Some(Stmt::Return(Some(Expr::parse(source)?)))
-        } else if source == "return" {
-            Some(Stmt::Return(None))
-        } else if source == "next" {
-            Some(Stmt::Next)
-        } else if source == "break" {
-            Some(Stmt::Break)
-        } else {
-            Some(Stmt::Expr(Expr::parse(source)?))
-        }
+            Some(Keyword("pub")) => {
+                parser.advance();
+                let is_macro = matches!(parser.peek(), Some(Identifier(word)) if word == "macro");
+                if !parser.is(&Keyword("let")) && !parser.is(&Keyword("type")) && !is_macro {
+                    return parser.error(format!(
+                        "expected `let`, `type` or `macro` after `pub`, found {}",
+                        parser.found()
+                    ));
+                }
+                // Public definitions are exported, or visible from the files importing it
+                let export = |define: Stmt| match define {
+                    Stmt::Let(Scope::Local, name, value) => {
+                        Some(Stmt::Let(Scope::Global, name, value))
+                    }
+                    Stmt::Type(Scope::Local, name, typ) => {
+                        Some(Stmt::Type(Scope::Global, name, typ))
+                    }
+                    Stmt::Macro(Scope::Local, name, params, body) => {
+                        Some(Stmt::Macro(Scope::Global, name, params, body))
+                    }
+                    _ => None,
+                };
+                match Stmt::parse(parser)? {
+                    Stmt::Generic(params, define) => {
+                        Stmt::Generic(params, Box::new(export(*define)?))
+                    }
+                    define => export(define)?,
+                }
+            }
+            Some(Keyword("type")) => {
+                parser.advance();
+                let name = parser.identifier()?;
+                let params = if parser.is(&Operator("<")) {
+                    Some(Stmt::parse_params(parser)?)
+                } else {
+                    None
+                };
+                parser.expect(&Operator("="))?;
+                let define = Stmt::Type(Scope::Local, name, Type::parse(parser)?);
+                match params {
+                    Some(params) => Stmt::Generic(params, Box::new(define)),
+                    None => define,
+                }
+            }
+            Some(Identifier(word)) if word == "macro" && is_definition => {
+                parser.advance();
+                let name = parser.identifier()?;
+                parser.expect(&Symbol('('))?;
+                let mut params: Vec<MacroParam> = vec![];
+                while !parser.is(&Symbol(')')) {
+                    let start = parser.span();
+                    if let Some(rest) = params.last().filter(|x| x.is_rest) {
+                        let errmsg = format!("rest parameter `...{}` must be the last", rest.name);
+                        return parser.error_at(start, errmsg);
+                    }
+                    // Parameter `let name` binds the argument evaluated once,
+                    // `...name` takes the rest of arguments, and `name = expr` has the default
+                    let is_bound = parser.eat(&Keyword("let"));
+                    let is_rest = parser.eat(&Operator("..."));
+                    let name = parser.identifier()?;
+                    let default = if !is_rest && parser.eat(&Operator("=")) {
+                        Some(Expr::parse(parser)?)
+                    } else {
+                        None
+                    };
+                    let is_defaulted = params.last().is_some_and(|x| x.default.is_some());
+                    if is_defaulted && default.is_none() && !is_rest {
+                        let errmsg = format!("parameter `{name}` must have the default value");
+                        let note = "parameters following the one having default value need it too";
+                        let span = parser.span_from(start);
+                        let diagnostic = Diagnostic::error(errmsg).with_span(Some(span));
+                        parser.diagnostics.push(diagnostic.with_note(note));
+                        return None;
+                    }
+                    params.push(MacroParam {
+                        name,
+                        is_bound,
+                        is_rest,
+                        default,
+                    });
+                    if !parser.eat(&Symbol(',')) {
+                        break;
+                    }
+                }
+                parser.expect(&Symbol(')'))?;
+                parser.expect(&Operator("="))?;
+                Stmt::Macro(Scope::Local, name, params, Expr::parse(parser)?)
+            }
+            Some(Identifier(word))
+                if word == "import" && matches!(parser.peek_nth(1), Some(TokenKind::String(_))) =>
+            {
+                parser.advance();
+                let Some(TokenKind::String(path)) = parser.advance().map(|x| x.kind) else {
+                    return None;
+                };
+                let alias = if parser.eat(&Keyword("as")) {
+                    Some(parser.identifier()?)
+                } else {
+                    None
+                };
+                Stmt::Module(path, alias)
+            }
+            Some(Identifier(word)) if word == "overload" && is_definition => {
+                parser.advance();
+                let name = parser.identifier()?;
+                parser.expect(&Operator("="))?;
+                // Unary operator `-T` is overloaded with `void` as the second operand
+                let dummy = Expr::Literal(Value::Integer(0));
+                if parser.eat(&Operator("-")) {
+                    let id = Op::unary("-", dummy).and_then(|op| op.overload_id());
+                    return Some(Stmt::Overload(
+                        id?,
+                        (Type::parse(parser)?, Type::Void),
+                        name,
+                    ));
+                }
+                let lhs = Type::parse(parser)?;
+                let dummy = Expr::Literal(Value::Integer(0));
+                let id = match parser.peek() {
+                    Some(Operator(op)) => {
+                        Op::binary(op, dummy.clone(), dummy).and_then(|op| op.overload_id())
+                    }
+                    _ => None,
+                };
+                let Some(id) = id else {
+                    let found = parser.found();
+                    return parser.error(format!("expected overloadable operator, found {found}"));
+                };
+                parser.advance();
+                Stmt::Overload(id, (lhs, Type::parse(parser)?), name)
+            }
+            Some(Keyword("load")) => {
+                parser.advance();
+                let mut module = None;
+                if let (Some(Identifier(name)), Some(Symbol('.'))) =
+                    (parser.peek().cloned(), parser.peek_nth(1))
+                {
+                    module = Some(name);
+                    parser.advance();
+                    parser.advance();
+                }
+                let start = parser.span();
+                let sigs = Expr::parse(parser)?;
+                Stmt::Import(module, import_args!(sigs, parser, start))
+            }
+            Some(Keyword("return")) => {
+                parser.advance();
+                let is_end = parser.is_eof()
+                    || parser.is(&Symbol(';'))
+                    || parser.is(&Symbol('}'))
+                    || parser.is(&Keyword("else"));
+                Stmt::Return(if is_end {
+                    None
+                } else {
+                    Some(Expr::parse(parser)?)
+                })
+            }
+            Some(Keyword("next")) => {
+                parser.advance();
+                Stmt::Next
+            }
+            Some(Keyword("break")) => {
+                parser.advance();
+                Stmt::Break
+            }
+            _ => Stmt::Expr(Expr::parse(parser)?),
+        })
     }
 
     fn compile(&self, ctx: &mut Compiler) -> Option<String> {
         Some(match self {
             Stmt::Expr(expr) => expr.compile(ctx)?,
             Stmt::If(cond, then, r#else) => {
+                let outer = ctx.narrowed.clone();
+                let typ = self.type_infer(ctx)?;
+                let after = std::mem::replace(&mut ctx.narrowed, outer.clone());
+                let cond_code = cond.compile(ctx)?;
+                // Branches are compiled knowing the variables that the condition null-checks
+                ctx.narrowed.extend(Op::narrowing(cond, true, ctx));
+                let then_code = then.compile(ctx)?;
+                ctx.narrowed = outer;
+                ctx.narrowed.extend(Op::narrowing(cond, false, ctx));
+                let else_code = if let Some(r#else) = r#else {
+                    format!("(else {})", r#else.compile(ctx)?)
+                } else {
+                    String::new()
+                };
+                ctx.narrowed = after;
                 format!(
-                    "(if {} {} (then {}) {})",
-                    compile_return!(self.type_infer(ctx)?, ctx),
-                    cond.compile(ctx)?,
-                    then.compile(ctx)?,
-                    if let Some(r#else) = r#else {
-                        format!("(else {})", r#else.compile(ctx)?)
-                    } else {
-                        String::new()
-                    },
+                    "(if {} {cond_code} (then {then_code}) {else_code})",
+                    compile_return!(typ, ctx),
                 )
             }
             Stmt::While(cond, body) => {
+                self.loop_narrowed(ctx);
+                let entry = ctx.narrowed.clone();
+                // Objects that the last iteration allocated are dropped at the start of next one
+                let (save, restore) = ctx.stack_mark();
+                let cond_code = cond.compile(ctx)?;
+                ctx.narrowed.extend(Op::narrowing(cond, true, ctx));
+                let body_code = body.compile(ctx)?;
+                ctx.narrowed = entry;
                 format!(
-                    "(block $outer (loop $while_start (br_if $outer (i32.eqz {})) {} {}))",
-                    cond.compile(ctx)?,
-                    body.compile(ctx)?,
+                    "{save} (block $outer (loop $while_start {restore} (br_if $outer (i32.eqz {cond_code})) {body_code} {}))",
                     Stmt::Next.compile(ctx)?
                 )
             }
+            Stmt::For(name, iter, body) => {
+                self.type_infer(ctx)?;
+                let entry = ctx.narrowed.clone();
+                let mut temp = || {
+                    let name = format!("for.{}", ctx.variable_type.len());
+                    ctx.variable_type.insert(name.clone(), Type::Integer);
+                    name
+                };
+                // Index counter and the collection or the end that's evaluated once
+                let (counter, held) = (temp(), temp());
+                let (init, is_end, value) = match iter {
+                    Iter::Range(start, end) | Iter::RangeInclusive(start, end) => (
+                        format!(
+                            "(local.set ${counter} {}) (local.set ${held} {})",
+                            start.compile(ctx)?,
+                            end.compile(ctx)?
+                        ),
+                        format!(
+                            "(i32.{} (local.get ${counter}) (local.get ${held}))",
+                            if let Iter::Range(..) = iter {
+                                "ge_s"
+                            } else {
+                                "gt_s"
+                            }
+                        ),
+                        format!("(local.get ${counter})"),
+                    ),
+                    Iter::Array(array) => {
+                        let Type::Array(typ) = array.type_infer(ctx)?.type_infer(ctx)? else {
+                            return None;
+                        };
+                        (
+                            format!(
+                                "(local.set ${counter} (i32.const 0)) (local.set ${held} {})",
+                                array.compile(ctx)?
+                            ),
+                            format!(
+                                "(i32.ge_s (local.get ${counter}) (i32.load (local.get ${held})))"
+                            ),
+                            format!(
+                                "({typ}.load offset={BYTES} (i32.add (local.get ${held}) (i32.mul (local.get ${counter}) (i32.const {BYTES}))))",
+                                typ = typ.compile(ctx)?
+                            ),
+                        )
+                    }
+                };
+                let value = ctx.set_local(name, value)?;
+                let (save, restore) = ctx.stack_mark();
+                let body = body.compile(ctx)?;
+                ctx.narrowed = entry;
+                // Counter is advanced before the body, so that `next` doesn't skip it
+                format!(
+                    "{init} {save} (block $outer (loop $while_start {restore} (br_if $outer {is_end}) {value} (local.set ${counter} (i32.add (local.get ${counter}) (i32.const 1))) {body} (br $while_start)))"
+                )
+            }
             Stmt::Next => "(br $while_start)".to_string(),
             Stmt::Break => "(br $outer)".to_string(),
             Stmt::Let(scope, name, value) => match name {
                 Expr::Variable(name) => match scope {
                     Scope::Local => {
+                        let narrowed = ctx.narrowed.clone();
                         let typ = value.type_infer(ctx)?;
+                        ctx.narrowed = narrowed;
                         if !ctx.argument_type.contains_key(name) {
-                            ctx.variable_type.insert(name.to_string(), typ);
+                            // Variable declared nullable stays so when non-null value is assigned
+                            let declared = ctx.variable_type.get(name).cloned();
+                            let is_widened =
+                                matches!(
+                                    declared.and_then(|x| x.type_infer(ctx)),
+                                    Some(Type::Nullable(_))
+                                ) && !matches!(typ.type_infer(ctx)?, Type::Nullable(_));
+                            if !is_widened {
+                                ctx.variable_type.insert(name.to_string(), typ.clone());
+                            }
                         }
-                        format!("(local.set ${name} {})", value.compile(ctx)?)
+                        let value = value.compile(ctx)?;
+                        Stmt::assign_narrowed(name, &typ, ctx)?;
+                        ctx.set_local(name, value)?
                     }
                     Scope::Global => {
                         let typ = value.type_infer(ctx)?;
@@ -174,6 +424,7 @@ impl Node for Stmt {
                     self.type_infer(ctx);
                     let var_ctx = ctx.variable_type.clone();
                     let arg_ctx = ctx.argument_type.clone();
+                    let flow = ctx.open_flow();
                     let function = ctx
                         .function_type
                         .get(name)
@@ -181,8 +432,17 @@ impl Node for Stmt {
                         .clone();
                     ctx.variable_type = function.variables.clone();
                     ctx.argument_type = function.arguments.clone();
+                    let frame = ctx.open_frame();
+                    let body = value.compile(ctx)?;
+                    let body = ctx.close_frame(
+                        frame,
+                        body,
+                        &function.returns,
+                        matches!(scope, Scope::Global),
+                    )?;
                     let code = format!(
-                        "(func ${name} {pub} {args} {ret} {locals} {body})",
+                        "(func ${id} {pub} {args} {ret} {locals} {body})",
+                        id = mangle(name),
                         args = join!(
                             &function
                                 .arguments
@@ -195,11 +455,12 @@ impl Node for Stmt {
                         ),
                         ret = compile_return!(function.returns, ctx),
                         pub = if let Scope::Global = scope { format!("(export \"{name}\")") } else { String::new() },
-                        body = value.compile(ctx)?, locals = expand_local(ctx)?
+                        locals = expand_local(ctx)?
                     );
                     ctx.declare_code.push(code);
                     ctx.variable_type = var_ctx;
                     ctx.argument_type = arg_ctx;
+                    ctx.close_flow(flow);
                     String::new()
                 }
                 Expr::Operator(oper) => {
@@ -214,8 +475,12 @@ impl Node for Stmt {
                         return None;
                     };
                     type_check!(typ, value.type_infer(ctx)?, ctx)?;
-                    let addr = Box::new(address_calc!(array, index, typ));
-                    Expr::Poke(addr, Box::new(value.clone())).compile(ctx)?
+                    let addr = Expr::element(array, index, ctx)?;
+                    format!(
+                        "({}.store {addr} {})",
+                        typ.compile(ctx)?,
+                        value.compile(ctx)?
+                    )
                 }
                 Expr::Field(expr, key) => {
                     let Type::Dict(dict) = expr.type_infer(ctx)? else {
@@ -228,8 +493,17 @@ impl Node for Stmt {
                 }
                 _ => return None,
             },
-            Stmt::Try(expr, catch) => expr.compile(ctx).or(catch.compile(ctx))?,
+            Stmt::Try(expr, catch) => {
+                let checkpoint = ctx.diagnostics.len();
+                if let Some(code) = expr.compile(ctx) {
+                    code
+                } else {
+                    ctx.diagnostics.truncate(checkpoint);
+                    catch.compile(ctx)?
+                }
+            }
             Stmt::Import(module, funcs) => {
+                self.type_infer(ctx)?;
                 let (name, args, ret_typ) = funcs.clone();
                 let mut export = name.clone();
                 if let Some(module) = module {
@@ -242,16 +516,33 @@ impl Node for Stmt {
                 };
                 let sig = compile_args_type!(function, ctx);
                 let ret = compile_return!(ret_typ, ctx);
-                ctx.import_code.push(format!(
-                    "(import \"env\" \"{export}\" (func ${name} {sig} {ret}))"
-                ));
+                let code = format!(
+                    "(import \"env\" \"{export}\" (func ${} {sig} {ret}))",
+                    mangle(&name)
+                );
+                // Same function may be loaded in the imported files too
+                if !ctx.import_code.contains(&code) {
+                    ctx.import_code.push(code);
+                }
                 String::new()
             }
+            // Function body is the block in GC mode, that frees the frame after it
+            Stmt::Return(Some(expr)) if ctx.heap == Allocator::Gc => {
+                format!("(br $gc/body {})", expr.compile(ctx)?)
+            }
+            Stmt::Return(_) if ctx.heap == Allocator::Gc => "(br $gc/body)".to_string(),
             Stmt::Return(Some(expr)) => {
                 format!("(return {})", expr.compile(ctx)?)
             }
             Stmt::Return(_) => "(return)".to_string(),
-            Stmt::Type(_, _) | Stmt::Macro(_, _, _) | Stmt::Overload(_, (_, _), _) => String::new(),
+            Stmt::Generic(_, _) => {
+                self.type_infer(ctx)?;
+                String::new()
+            }
+            Stmt::Type(_, _, _)
+            | Stmt::Macro(_, _, _, _)
+            | Stmt::Module(_, _)
+            | Stmt::Overload(_, (_, _), _) => String::new(),
         })
     }
 
@@ -260,15 +551,76 @@ impl Node for Stmt {
             Stmt::Expr(expr) => expr.type_infer(ctx)?,
             Stmt::If(cond, then, r#else) => {
                 type_check!(cond, Type::Bool, ctx)?;
-                if let Some(r#else) = r#else {
-                    type_check!(then, r#else, ctx)?
+                let outer = ctx.narrowed.clone();
+                ctx.narrowed.extend(Op::narrowing(cond, true, ctx));
+                let then_type = then.type_infer(ctx);
+                let then_narrowed = std::mem::replace(&mut ctx.narrowed, outer);
+                ctx.narrowed.extend(Op::narrowing(cond, false, ctx));
+                let else_type = r#else.as_ref().map(|r#else| r#else.type_infer(ctx));
+                let else_narrowed = std::mem::take(&mut ctx.narrowed);
+                // Null-checks hold after the branches, unless the branch doesn't reach there
+                let is_diverged = (
+                    Stmt::Expr(then.clone()).diverges(),
+                    r#else.as_ref().is_some_and(|r#else| r#else.diverges()),
+                );
+                ctx.narrowed = match is_diverged {
+                    (true, false) => else_narrowed,
+                    (false, true) => then_narrowed,
+                    _ => then_narrowed
+                        .intersection(&else_narrowed)
+                        .cloned()
+                        .collect(),
+                };
+                if let Some(else_type) = else_type {
+                    let (then_type, else_type) = (then_type?, else_type?);
+                    branch_check!(then_type, else_type, ctx)?
                 } else {
-                    then.type_infer(ctx)?
+                    then_type?
                 }
             }
             Stmt::While(cond, body) => {
+                self.loop_narrowed(ctx);
+                let entry = ctx.narrowed.clone();
                 type_check!(cond, Type::Bool, ctx)?;
-                body.type_infer(ctx)?;
+                ctx.narrowed.extend(Op::narrowing(cond, true, ctx));
+                let body = body.type_infer(ctx);
+                ctx.narrowed = entry;
+                body?;
+                Type::Void
+            }
+            Stmt::For(name, iter, body) => {
+                let typ = match iter {
+                    Iter::Range(start, end) | Iter::RangeInclusive(start, end) => {
+                        type_check!(start, Type::Integer, ctx)?;
+                        type_check!(end, Type::Integer, ctx)?
+                    }
+                    Iter::Array(array) => match array.type_infer(ctx)?.type_infer(ctx)? {
+                        Type::Array(typ) => *typ,
+                        Type::Error => Type::Error,
+                        typ => {
+                            let msg = format!("can't iterate over {} typed value", typ.format());
+                            let note = "iterate over an array or a range `start..end` instead";
+                            ctx.report(Diagnostic::error(msg).with_note(note));
+                            return None;
+                        }
+                    },
+                };
+                if ctx.argument_type.contains_key(name) {
+                    let msg = format!("can't reassign value to argument `{name}`");
+                    let note = "arguments are immutable, bind the value to other name";
+                    ctx.report(Diagnostic::error(msg).with_note(note));
+                    return None;
+                } else if let Some(exist) = ctx.variable_type.get(name).cloned() {
+                    type_check!(exist, typ, ctx)?;
+                } else {
+                    ctx.variable_type.insert(name.clone(), typ.clone());
+                }
+                Stmt::assign_narrowed(name, &typ, ctx)?;
+                self.loop_narrowed(ctx);
+                let entry = ctx.narrowed.clone();
+                let body = body.type_infer(ctx);
+                ctx.narrowed = entry;
+                body?;
                 Type::Void
             }
             Stmt::Break => Type::Void,
@@ -279,20 +631,23 @@ impl Node for Stmt {
                         Scope::Local => {
                             if !ctx.argument_type.contains_key(name) {
                                 let value_type = value.type_infer(ctx)?;
-                                if let Some(exist_val) = ctx.clone().variable_type.get(name) {
+                                if let Some(exist_val) = ctx.variable_type.get(name).cloned() {
                                     type_check!(exist_val, value_type, ctx)?;
                                 } else {
-                                    ctx.variable_type.insert(name.to_string(), value_type);
+                                    ctx.variable_type
+                                        .insert(name.to_string(), value_type.clone());
                                 }
+                                Stmt::assign_narrowed(name, &value_type, ctx)?;
                             } else {
-                                let msg = format!("can't reassign value to argument");
-                                ctx.occurred_error = Some(msg);
+                                let msg = format!("can't reassign value to argument `{name}`");
+                                let note = "arguments are immutable, bind the value to other name";
+                                ctx.report(Diagnostic::error(msg).with_note(note));
                                 return None;
                             }
                         }
                         Scope::Global => {
                             let value_type = value.type_infer(ctx)?;
-                            if let Some(exist_val) = ctx.clone().global_type.get(name) {
+                            if let Some(exist_val) = ctx.global_type.get(name).cloned() {
                                 type_check!(exist_val, value_type, ctx)?;
                             } else {
                                 ctx.global_type.insert(name.to_string(), value_type);
@@ -300,13 +655,15 @@ impl Node for Stmt {
                         }
                     },
                     Expr::Call(name, args) => {
-                        let var_ctx = ctx.variable_type.clone();
-                        let arg_ctx = ctx.argument_type.clone();
-                        ctx.variable_type.clear();
-                        ctx.argument_type.clear();
-                        compile_args!(args, ctx);
+                        let var_ctx = std::mem::take(&mut ctx.variable_type);
+                        let arg_ctx = std::mem::take(&mut ctx.argument_type);
+                        let flow = ctx.open_flow();
+                        let returns = (|| {
+                            compile_args!(args, ctx);
+                            value.type_infer(ctx)
+                        })();
                         let frame = Function {
-                            returns: value.type_infer(ctx)?,
+                            returns: returns.clone().unwrap_or(Type::Error),
                             variables: ctx.variable_type.clone(),
                             arguments: ctx.argument_type.clone(),
                         };
@@ -318,44 +675,75 @@ impl Node for Stmt {
                         .insert(name.to_owned(), frame);
                         ctx.variable_type = var_ctx;
                         ctx.argument_type = arg_ctx;
+                        ctx.close_flow(flow);
+                        returns?;
                     }
                     Expr::Operator(oper) => match *oper.clone() {
                         Op::Cast(Expr::Call(name, args), ret) => {
-                            let var_ctx = ctx.variable_type.clone();
-                            let arg_ctx = ctx.argument_type.clone();
-                            ctx.variable_type.clear();
-                            ctx.argument_type.clear();
-                            compile_args!(args.clone(), ctx);
-                            ctx.function_type.insert(
-                                name.to_owned(),
-                                Function {
-                                    variables: ctx.variable_type.clone(),
-                                    arguments: ctx.argument_type.clone(),
-                                    returns: ret.clone(),
-                                },
-                            );
-                            type_check!(value.type_infer(ctx)?, ret, ctx);
+                            let var_ctx = std::mem::take(&mut ctx.variable_type);
+                            let arg_ctx = std::mem::take(&mut ctx.argument_type);
+                            let flow = ctx.open_flow();
+                            let returns = (|| {
+                                compile_args!(args.clone(), ctx);
+                                ctx.function_type.insert(
+                                    name.to_owned(),
+                                    Function {
+                                        variables: ctx.variable_type.clone(),
+                                        arguments: ctx.argument_type.clone(),
+                                        returns: ret.clone(),
+                                    },
+                                );
+                                value.type_infer(ctx)
+                            })();
                             ctx.variable_type = var_ctx;
                             ctx.argument_type = arg_ctx;
+                            ctx.close_flow(flow);
+                            value_check!(returns?, ret, ctx)?;
                         }
+                        // Invalid target that's reported in expanding macros
                         _ => return None,
                     },
+                    Expr::Field(_, _) | Expr::Index(_, _) => {
+                        type_check!(name, value, ctx)?;
+                    }
                     _ => {
                         value.type_infer(ctx);
                     }
                 }
                 Type::Void
             }
-            Stmt::Type(name, value) => {
+            Stmt::Type(_, name, value) => {
                 ctx.type_alias.insert(name.to_string(), value.clone());
                 Type::Void
             }
-            Stmt::Macro(name, args, expr) => {
-                ctx.macro_code
-                    .insert(name.to_owned(), (args.clone(), expr.clone()));
+            // Macro calls are already expanded, and modules are imported
+            Stmt::Macro(_, _, _, _) | Stmt::Module(_, _) => Type::Void,
+            Stmt::Generic(params, define) => {
+                match &**define {
+                    Stmt::Type(_, name, typ) => {
+                        let generic = (params.clone(), typ.clone());
+                        ctx.generic_type.insert(name.clone(), generic);
+                    }
+                    Stmt::Let(_, sig, _) => {
+                        let (Expr::Call(name, _), _) = Stmt::signature(sig)? else {
+                            return None;
+                        };
+                        let generic = (params.clone(), *define.clone());
+                        ctx.generic_code.insert(name, generic);
+                    }
+                    _ => return None,
+                }
                 Type::Void
             }
-            Stmt::Try(expr, catch) => expr.type_infer(ctx).or(catch.type_infer(ctx))?,
+            Stmt::Try(expr, catch) => {
+                let checkpoint = ctx.diagnostics.len();
+                if let Some(typ) = expr.type_infer(ctx) {
+                    typ
+                } else {
+                    ctx.diagnostics.truncate(checkpoint);
+                    catch.type_infer(ctx)?
+                }
+            }
             Stmt::Import(_module, funcs) => {
                 let (fn_name, args, ret_typ) = funcs;
                 let mut arg_map = IndexMap::new();
@@ -373,6 +761,8 @@ impl Node for Stmt {
                 Type::Void
             }
             Stmt::Overload(id, (arg1, arg2), name) => {
+                // Operand types are resolved same as the terms looked up with
+                let (arg1, arg2) = (arg1.type_infer(ctx)?, arg2.type_infer(ctx)?);
                 let key = (*id, (arg1.format(), arg2.format()));
                 ctx.overload.insert(key, name.clone());
                 Type::Void
@@ -381,3 +771,334 @@ impl Node for Stmt {
         })
     }
 }
+
+impl Stmt {
+    /// Type parameters of the generic definition `<T, ...>`
+    fn parse_params(parser: &mut Parser) -> Option<Vec<String>> {
+        parser.expect(&TokenKind::Operator("<"))?;
+        let mut params = vec![parser.identifier()?];
+        while parser.eat(&TokenKind::Symbol(',')) {
+            params.push(parser.identifier()?);
+        }
+        parser.expect(&TokenKind::Operator(">"))?;
+        Some(params)
+    }
+
+    /// Whether the statement never reaches the following one, leaving the block or the loop
+    pub(crate) fn diverges(&self) -> bool {
+        match self {
+            Stmt::Return(_) | Stmt::Break | Stmt::Next => true,
+            Stmt::Expr(Expr::Block(Block(block))) => {
+                block.last().is_some_and(|(stmt, _)| stmt.diverges())
+            }
+            Stmt::If(_, then, Some(r#else)) => {
+                Stmt::Expr(then.clone()).diverges() && r#else.diverges()
+            }
+            _ => false,
+        }
+    }
+
+    /// Names of the variables that the statement refers and assigns.
+    /// Functions defined in it have their own scope, so they're skipped
+    pub(crate) fn variables(&self, refer: &mut IndexSet<String>, assign: &mut IndexSet<String>) {
+        match self {
+            Stmt::Expr(expr) | Stmt::Return(Some(expr)) => expr.variables(refer, assign),
+            Stmt::Let(Scope::Local, Expr::Variable(name), value) => {
+                assign.insert(name.clone());
+                value.variables(refer, assign);
+            }
+            Stmt::Let(_, Expr::Variable(_), value) => value.variables(refer, assign),
+            Stmt::Let(_, target @ (Expr::Field(_, _) | Expr::Index(_, _)), value) => {
+                target.variables(refer, assign);
+                value.variables(refer, assign);
+            }
+            Stmt::If(cond, then, r#else) => {
+                cond.variables(refer, assign);
+                then.variables(refer, assign);
+                if let Some(r#else) = r#else {
+                    r#else.variables(refer, assign);
+                }
+            }
+            Stmt::While(cond, body) => {
+                cond.variables(refer, assign);
+                body.variables(refer, assign);
+            }
+            Stmt::For(name, iter, body) => {
+                assign.insert(name.clone());
+                match iter {
+                    Iter::Array(array) => array.variables(refer, assign),
+                    Iter::Range(start, end) | Iter::RangeInclusive(start, end) => {
+                        start.variables(refer, assign);
+                        end.variables(refer, assign);
+                    }
+                }
+                body.variables(refer, assign);
+            }
+            Stmt::Try(expr, catch) => {
+                expr.variables(refer, assign);
+                catch.variables(refer, assign);
+            }
+            _ => {}
+        }
+    }
+
+    /// Track the nullable local variable whether it's null after the value is assigned
+    fn assign_narrowed(name: &str, value: &Type, ctx: &mut Compiler) -> Option<()> {
+        let Some(declared) = ctx.variable_type.get(name).cloned() else {
+            return Some(());
+        };
+        if let Type::Nullable(_) = declared.type_infer(ctx)? {
+            if let Type::Nullable(_) = value.type_infer(ctx)? {
+                ctx.narrowed.shift_remove(name);
+                ctx.nullable_assigned.insert(name.to_string());
+            } else {
+                ctx.narrowed.insert(name.to_string());
+            }
+        }
+        Some(())
+    }
+
+    /// Forget the null-checks of the variables that the loop may assign null to,
+    /// because they aren't known at the start of the iterations
+    fn loop_narrowed(&self, ctx: &mut Compiler) {
+        let (cond, body) = match self {
+            Stmt::While(cond, body) => (Some(cond), body),
+            Stmt::For(_, _, body) => (None, body),
+            _ => return,
+        };
+        if ctx.narrowed.is_empty() {
+            return;
+        }
+        // Loop is checked beforehand to find them, dropping the errors reported twice
+        let (outer, checkpoint) = (ctx.narrowed.clone(), ctx.diagnostics.len());
+        let assigned = std::mem::take(&mut ctx.nullable_assigned);
+        if let Some(cond) = cond {
+            cond.type_infer(ctx);
+            ctx.narrowed.extend(Op::narrowing(cond, true, ctx));
+        }
+        body.type_infer(ctx);
+        ctx.diagnostics.truncate(checkpoint);
+        let nulled = std::mem::replace(&mut ctx.nullable_assigned, assigned);
+        ctx.narrowed = outer.difference(&nulled).cloned().collect();
+        ctx.nullable_assigned.extend(nulled);
+    }
+
+    /// Call of the function definition and the annotated return type
+    pub(crate) fn signature(sig: &Expr) -> Option<(Expr, Option<Type>)> {
+        match sig {
+            Expr::Call(_, _) => Some((sig.clone(), None)),
+            Expr::Operator(oper) => match &**oper {
+                Op::Cast(call @ Expr::Call(_, _), ret) => Some((call.clone(), Some(ret.clone()))),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Instantiate the generic function for the types of the arguments,
+    /// that's declared once for each combination of the type arguments.
+    /// Returns the name of the instance like `name<int>`
+    pub fn instantiate(name: &str, args: &[Expr], ctx: &mut Compiler) -> Option<String> {
+        let (params, define) = ctx.generic_code.get(name).cloned()?;
+        let Stmt::Let(scope, sig, value) = define else {
+            return None;
+        };
+        let (Expr::Call(_, sig_args), ret) = Stmt::signature(&sig)? else {
+            return None;
+        };
+        if sig_args.len() != args.len() {
+            let (paramlen, arglen) = (sig_args.len(), args.len());
+            let errmsg = format!(
+                "arguments of function `{name}` length should be {paramlen}, but passed {arglen} values"
+            );
+            ctx.report(Diagnostic::error(errmsg));
+            return None;
+        }
+
+        // Type parameters are inferred from the types of the arguments
+        let mut bound = IndexMap::new();
+        for (sig_arg, arg) in sig_args.iter().zip(args) {
+            let Expr::Operator(oper) = sig_arg else {
+                continue;
+            };
+            if let Op::Cast(_, typ) = &**oper {
+                typ.unify(&arg.type_infer(ctx)?, &params, &mut bound, ctx)?;
+            }
+        }
+        let mut types = vec![];
+        for param in &params {
+            let Some(typ) = bound.get(param) else {
+                let errmsg = format!("can't infer type parameter `{param}` of function `{name}`");
+                let note = "type parameters are inferred from the types of arguments";
+                ctx.report(Diagnostic::error(errmsg).with_note(note));
+                return None;
+            };
+            types.push(typ.compress_alias(ctx));
+        }
+        let instance = Type::Generic(name.to_string(), types.clone()).format();
+        // Instance that's recursively called in its body is also found here
+        if ctx.instance.contains_key(&instance) {
+            return Some(instance);
+        }
+        ctx.instance
+            .insert(instance.clone(), (name.to_string(), types));
+
+        let mut call = Expr::Call(instance.clone(), sig_args.clone());
+        if let Some(ret) = ret {
+            call = Expr::Operator(Box::new(Op::Cast(call, ret)));
+        }
+        let define = Stmt::Let(scope, call, value);
+        // Instance is declared in the middle of checking the caller
+        let outer = std::mem::replace(&mut ctx.type_param, bound);
+        let (var_ctx, arg_ctx) = (ctx.variable_type.clone(), ctx.argument_type.clone());
+        let code = define.compile(ctx);
+        ctx.variable_type = var_ctx;
+        ctx.argument_type = arg_ctx;
+        let functions = match scope {
+            Scope::Global => &mut ctx.export_type,
+            Scope::Local => &mut ctx.function_type,
+        };
+        let mut function = functions.get(&instance).cloned().unwrap_or(Function {
+            variables: IndexMap::new(),
+            arguments: IndexMap::new(),
+            returns: Type::Error,
+        });
+        if code.is_some() {
+            // Types are resolved while the type parameters are bound
+            let resolve = |typ: Type, ctx: &mut Compiler| typ.type_infer(ctx).unwrap_or(typ);
+            for typ in function
+                .variables
+                .values_mut()
+                .chain(function.arguments.values_mut())
+            {
+                *typ = resolve(typ.clone(), ctx);
+            }
+            function.returns = resolve(function.returns, ctx);
+        } else {
+            // Failed instance doesn't cascade errors to the other calls
+            function.returns = Type::Error;
+        }
+        ctx.type_param = outer;
+        match scope {
+            Scope::Global => &mut ctx.export_type,
+            Scope::Local => &mut ctx.function_type,
+        }
+        .insert(instance.clone(), function);
+        Some(instance)
+    }
+
+    /// Bind the name that failed statement defines to the error type,
+    /// so that following statements don't cascade errors about it
+    pub fn poison(&self, ctx: &mut Compiler) {
+        match self {
+            Stmt::Let(Scope::Local, Expr::Variable(name), _)
+                if !ctx.argument_type.contains_key(name) =>
+            {
+                ctx.variable_type.entry(name.clone()).or_insert(Type::Error);
+            }
+            Stmt::Let(Scope::Global, Expr::Variable(name), _) => {
+                ctx.global_type.entry(name.clone()).or_insert(Type::Error);
+            }
+            // Variable with the type annotation `let name: type = value`, that's invalid
+            Stmt::Let(Scope::Local, Expr::Operator(oper), _) => {
+                if let Op::Cast(Expr::Variable(name), _) = &**oper
+                    && !ctx.argument_type.contains_key(name)
+                {
+                    ctx.variable_type.entry(name.clone()).or_insert(Type::Error);
+                }
+            }
+            Stmt::For(name, _, _) if !ctx.argument_type.contains_key(name) => {
+                ctx.variable_type.entry(name.clone()).or_insert(Type::Error);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stmt::Expr(expr) => write!(f, "{expr}"),
+            Stmt::Let(Scope::Local, name, value) => write!(f, "let {name} = {value}"),
+            Stmt::Let(Scope::Global, name, value) => write!(f, "pub let {name} = {value}"),
+            Stmt::If(cond, then, Some(r#else)) => write!(f, "if {cond} then {then} else {else}"),
+            Stmt::If(cond, then, None) => write!(f, "if {cond} then {then}"),
+            Stmt::While(cond, body) => write!(f, "while {cond} loop {body}"),
+            Stmt::For(name, Iter::Array(array), body) => {
+                write!(f, "for {name} in {array} loop {body}")
+            }
+            Stmt::For(name, Iter::Range(start, end), body) => {
+                write!(f, "for {name} in {start}..{end} loop {body}")
+            }
+            Stmt::For(name, Iter::RangeInclusive(start, end), body) => {
+                write!(f, "for {name} in {start}..={end} loop {body}")
+            }
+            Stmt::Type(Scope::Local, name, typ) => write!(f, "type {name} = {typ}"),
+            Stmt::Type(Scope::Global, name, typ) => write!(f, "pub type {name} = {typ}"),
+            Stmt::Generic(params, define) => {
+                let params = params.join(", ");
+                match &**define {
+                    Stmt::Type(Scope::Local, name, typ) => {
+                        write!(f, "type {name}<{params}> = {typ}")
+                    }
+                    Stmt::Type(Scope::Global, name, typ) => {
+                        write!(f, "pub type {name}<{params}> = {typ}")
+                    }
+                    Stmt::Let(scope, sig, value) => {
+                        let (Expr::Call(name, args), ret) =
+                            Stmt::signature(sig).ok_or(fmt::Error)?
+                        else {
+                            return Err(fmt::Error);
+                        };
+                        let args = args.iter().map(|x| x.to_string());
+                        let args = args.collect::<Vec<_>>().join(", ");
+                        let ret = ret.map(|x| format!(": {x}")).unwrap_or_default();
+                        let scope = if let Scope::Global = scope {
+                            "pub "
+                        } else {
+                            ""
+                        };
+                        write!(f, "{scope}let {name}<{params}>({args}){ret} = {value}")
+                    }
+                    _ => Err(fmt::Error),
+                }
+            }
+            Stmt::Try(expr, r#catch) => write!(f, "try {expr} catch {catch}"),
+            Stmt::Macro(scope, name, params, body) => {
+                let params = params.iter().map(|x| x.to_string());
+                let scope = if let Scope::Global = scope {
+                    "pub "
+                } else {
+                    ""
+                };
+                write!(
+                    f,
+                    "{scope}macro {name}({}) = {body}",
+                    params.collect::<Vec<_>>().join(", ")
+                )
+            }
+            Stmt::Overload(id, (lhs, rhs), name) => {
+                let dummy = || Expr::Literal(Value::Integer(0));
+                if Op::Neg(dummy()).overload_id() == Some(*id) {
+                    return write!(f, "overload {name} = -{lhs}");
+                }
+                let id_of = |op| Op::binary(op, dummy(), dummy())?.overload_id();
+                let op = OPERATOR.into_iter().find(|op| id_of(op) == Some(*id));
+                let op = op.ok_or(fmt::Error)?;
+                write!(f, "overload {name} = {lhs} {op} {rhs}")
+            }
+            Stmt::Import(module, (name, args, ret)) => {
+                let module = module.as_ref().map(|x| format!("{x}.")).unwrap_or_default();
+                let args = args.iter().map(|(name, typ)| format!("{name}: {typ}"));
+                let args = args.collect::<Vec<_>>().join(", ");
+                write!(f, "load {module}{name}({args}): {ret}")
+            }
+            Stmt::Module(path, Some(alias)) => write!(f, "import \"{}\" as {alias}", escape(path)),
+            Stmt::Module(path, None) => write!(f, "import \"{}\"", escape(path)),
+            Stmt::Return(Some(expr)) => write!(f, "return {expr}"),
+            Stmt::Return(None) => write!(f, "return"),
+            Stmt::Break => write!(f, "break"),
+            Stmt::Next => write!(f, "next"),
+        }
+    }
+}
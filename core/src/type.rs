@@ -1,7 +1,9 @@
 use crate::*;
+use std::fmt::{self, Display};
 
 pub type Dict = IndexMap<String, (i32, Type)>;
-pub type Enum = Vec<String>;
+/// Variants of the enumerate type and the types of the fields they carry
+pub type Enum = IndexMap<String, Vec<Type>>;
 #[derive(Clone, Debug)]
 pub enum Type {
     Integer,
@@ -9,55 +11,125 @@ pub enum Type {
     Bool,
     String,
     Array(Box<Type>),
+    /// Pointer that may be null `T?`, others are never null
+    Nullable(Box<Type>),
     Dict(Dict),
     Enum(Enum),
+    /// Function that's referred by the index in the table `fn(T, ...): T`
+    Function(Vec<Type>, Box<Type>),
     Alias(String),
+    /// Generic type alias applied to the type arguments `Name<T, ...>`
+    Generic(String, Vec<Type>),
     Void,
+    /// Type of the expression that failed checking, compatible with any type
+    Error,
 }
 
 impl Node for Type {
-    fn parse(source: &str) -> Option<Type> {
-        match source.trim() {
-            "int" => Some(Type::Integer),
-            "num" => Some(Type::Number),
-            "bool" => Some(Type::Bool),
-            "str" => Some(Type::String),
-            "void" => Some(Type::Void),
-            source => {
-                let source = source.trim();
-                if source.starts_with("[") && source.ends_with("]") {
-                    let source = source.get(1..source.len() - 1)?.trim();
-                    Some(Type::Array(Box::new(Type::parse(source)?)))
-                } else if source.starts_with("@{") && source.ends_with("}") {
-                    let source = source.get(2..source.len() - 1)?.trim();
-                    let mut result = IndexMap::new();
-                    let mut offset = 0;
-                    for line in tokenize(source, &[","], false, true, false)? {
-                        let (name, value) = line.split_once(":")?;
-                        let name = name.trim().to_string();
-                        if !is_identifier(&name) {
-                            return None;
-                        };
-                        let typ = Type::parse(value)?;
-                        result.insert(name, (offset, typ.clone()));
-                        offset += BYTES;
+    fn parse(parser: &mut Parser) -> Option<Type> {
+        use TokenKind::{Identifier, Operator, Symbol};
+        let typ = match parser.peek().cloned() {
+            Some(Identifier(name)) => {
+                parser.advance();
+                match name.as_str() {
+                    "int" => Type::Integer,
+                    "num" => Type::Number,
+                    "bool" => Type::Bool,
+                    "str" => Type::String,
+                    "void" => Type::Void,
+                    "fn" if parser.is(&Symbol('(')) => {
+                        parser.advance();
+                        let mut args = vec![];
+                        while !parser.is(&Symbol(')')) {
+                            args.push(Type::parse(parser)?);
+                            if !parser.eat(&Symbol(',')) {
+                                break;
+                            }
+                        }
+                        parser.expect(&Symbol(')'))?;
+                        parser.expect(&Operator(":"))?;
+                        Type::Function(args, Box::new(Type::parse(parser)?))
+                    }
+                    _ => {
+                        // Type defined in the imported module `name.Type`
+                        let mut name = name;
+                        if let (Some(Symbol('.')), Some(Identifier(member))) =
+                            (parser.peek(), parser.peek_nth(1).cloned())
+                        {
+                            parser.advance();
+                            parser.advance();
+                            name = format!("{name}.{member}");
+                        }
+                        // Comparison `a < b` isn't taken as the type arguments
+                        let args = parser.speculate(|parser| {
+                            parser.expect(&Operator("<"))?;
+                            let mut args = vec![Type::parse(parser)?];
+                            while parser.eat(&Symbol(',')) {
+                                args.push(Type::parse(parser)?);
+                            }
+                            parser.close_angle().then_some(args)
+                        });
+                        match args {
+                            Some(args) => Type::Generic(name, args),
+                            None => Type::Alias(name),
+                        }
+                    }
+                }
+            }
+            Some(Symbol('[')) => {
+                parser.advance();
+                let typ = Type::parse(parser)?;
+                parser.expect(&Symbol(']'))?;
+                Type::Array(Box::new(typ))
+            }
+            Some(Symbol('@')) => {
+                parser.advance();
+                parser.expect(&Symbol('{'))?;
+                let mut result = IndexMap::new();
+                let mut offset = 0;
+                while !parser.is(&Symbol('}')) {
+                    let name = parser.identifier()?;
+                    parser.expect(&Operator(":"))?;
+                    result.insert(name, (offset, Type::parse(parser)?));
+                    offset += BYTES;
+                    if !parser.eat(&Symbol(',')) {
+                        break;
+                    }
+                }
+                parser.expect(&Symbol('}'))?;
+                Type::Dict(result)
+            }
+            Some(Symbol('(')) => {
+                parser.advance();
+                let mut result = IndexMap::new();
+                loop {
+                    let name = parser.identifier()?;
+                    // Payload of the variant `Name(type, ...)`
+                    let mut fields = vec![];
+                    if parser.eat(&Symbol('(')) {
+                        while !parser.is(&Symbol(')')) {
+                            fields.push(Type::parse(parser)?);
+                            if !parser.eat(&Symbol(',')) {
+                                break;
+                            }
+                        }
+                        parser.expect(&Symbol(')'))?;
+                    }
+                    result.insert(name, fields);
+                    if !parser.eat(&Operator("|")) {
+                        break;
                     }
-                    Some(Type::Dict(result))
-                } else if source.starts_with("(") && source.ends_with(")") {
-                    let source = source.get(1..source.len() - 1)?.trim();
-                    let result = tokenize(source, &["|"], false, true, false)?;
-                    let result: Vec<String> = result.iter().map(|x| x.trim().to_string()).collect();
-                    if !result.iter().all(|x| is_identifier(x)) {
-                        return None;
-                    };
-                    Some(Type::Enum(result))
-                } else if is_identifier(&source) {
-                    Some(Type::Alias(source.to_string()))
-                } else {
-                    None
                 }
+                parser.expect(&Symbol(')'))?;
+                Type::Enum(result)
             }
+            _ => return parser.error(format!("expected type, found {}", parser.found())),
+        };
+        // Nullable type `T?`
+        if parser.eat(&Operator("?")) {
+            return Some(Type::Nullable(Box::new(typ)));
         }
+        Some(typ)
     }
 
     fn compile(&self, ctx: &mut Compiler) -> Option<String> {
@@ -68,8 +140,10 @@ impl Node for Type {
                 | Type::Bool
                 | Type::String
                 | Type::Array(_)
+                | Type::Nullable(_)
                 | Type::Dict(_)
-                | Type::Enum(_) => "i32",
+                | Type::Enum(_)
+                | Type::Function(_, _) => "i32",
                 _ => return None,
             }
             .to_string(),
@@ -88,51 +162,327 @@ impl Type {
                 return Some(self.compress_alias(ctx));
             }
         }
-        let result = match self {
+        match self {
             Type::Alias(name) => {
+                if let Some(typ) = ctx.type_param.get(name) {
+                    return Some(typ.clone());
+                }
                 let Some(typ) = ctx.type_alias.get(name).cloned() else {
+                    if ctx.generic_type.contains_key(name) {
+                        return Type::Generic(name.clone(), vec![]).solve_alias(ctx, xpct);
+                    }
                     let msg = format!("undefined type alias `{name}`");
-                    ctx.occurred_error = Some(msg);
+                    ctx.report(Diagnostic::error(msg));
                     return None;
                 };
                 typ.solve_alias(ctx, xpct.clone())
             }
+            Type::Generic(name, args) => {
+                let alias = Type::instantiate(name, args, ctx)?;
+                alias.solve_alias(ctx, xpct)
+            }
+            Type::Function(args, ret) => {
+                let xpct = [xpct.clone(), vec![self.clone()]].concat();
+                let args = args
+                    .iter()
+                    .map(|typ| typ.solve_alias(ctx, xpct.clone()))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(Type::Function(args, Box::new(ret.solve_alias(ctx, xpct)?)))
+            }
             Type::Array(typ) => Some(Type::Array(Box::new(
                 typ.solve_alias(ctx, [xpct.clone(), vec![self.clone()]].concat())?,
             ))),
+            Type::Nullable(typ) => {
+                match typ.solve_alias(ctx, [xpct.clone(), vec![self.clone()]].concat())? {
+                    typ @ Type::Nullable(_) => Some(typ),
+                    typ @ (Type::String
+                    | Type::Array(_)
+                    | Type::Dict(_)
+                    | Type::Alias(_)
+                    | Type::Error) => Some(Type::Nullable(Box::new(typ))),
+                    typ => {
+                        let errmsg = format!("{} typed value can't be null", typ.format());
+                        let note = "only strings, arrays and dictionaries are nullable";
+                        ctx.report(Diagnostic::error(errmsg).with_note(note));
+                        None
+                    }
+                }
+            }
             Type::Dict(dict) => {
                 let mut a = IndexMap::new();
                 let mut offset = 0;
                 for (name, (_, typ)) in dict {
                     let typ = typ.solve_alias(ctx, [xpct.clone(), vec![self.clone()]].concat())?;
-                    a.insert(name.clone(), (offset.clone(), typ.clone()));
+                    a.insert(name.clone(), (offset, typ.clone()));
                     offset += BYTES
                 }
                 Some(Type::Dict(a))
             }
             _ => Some(self.clone()),
-        };
-        result
+        }
     }
 
     pub fn compress_alias(&self, ctx: &Compiler) -> Type {
         let mut aliases = ctx.type_alias.iter();
         let typ = match self {
             Type::Array(typ) => Type::Array(Box::new(typ.compress_alias(ctx))),
+            Type::Nullable(typ) => Type::Nullable(Box::new(typ.compress_alias(ctx))),
             Type::Dict(dict) => Type::Dict(
                 dict.iter()
-                    .map(|(k, (o, t))| (k.clone(), (o.clone(), t.compress_alias(ctx))))
+                    .map(|(k, (o, t))| (k.clone(), (*o, t.compress_alias(ctx))))
                     .collect(),
             ),
+            Type::Enum(variants) => Type::Enum(
+                variants
+                    .iter()
+                    .map(|(k, fields)| {
+                        (
+                            k.clone(),
+                            fields.iter().map(|t| t.compress_alias(ctx)).collect(),
+                        )
+                    })
+                    .collect(),
+            ),
+            Type::Generic(name, args) => Type::Generic(
+                name.clone(),
+                args.iter().map(|t| t.compress_alias(ctx)).collect(),
+            ),
+            Type::Function(args, ret) => Type::Function(
+                args.iter().map(|t| t.compress_alias(ctx)).collect(),
+                Box::new(ret.compress_alias(ctx)),
+            ),
             _ => self.clone(),
         };
         if let Some(i) = aliases.find(|(_, v)| **v == typ) {
             Type::Alias(i.0.clone())
+        } else if ctx.type_alias.contains_key(&typ.format()) {
+            // Instance of the generic type alias
+            Type::Alias(typ.format())
         } else {
             typ
         }
     }
 
+    /// Alias of the generic type alias instantiated with the type arguments,
+    /// that's named like `Name<int>` and defined on the first use
+    fn instantiate(name: &str, args: &[Type], ctx: &mut Compiler) -> Option<Type> {
+        let Some((params, template)) = ctx.generic_type.get(name).cloned() else {
+            let msg = format!("type alias `{name}` doesn't take type arguments");
+            ctx.report(Diagnostic::error(msg));
+            return None;
+        };
+        if params.len() != args.len() {
+            let (paramlen, arglen) = (params.len(), args.len());
+            let errmsg = format!(
+                "generic type `{name}` takes {paramlen} type arguments, but {arglen} are given"
+            );
+            let note = format!("write the type like `{name}<{}>`", params.join(", "));
+            ctx.report(Diagnostic::error(errmsg).with_note(note));
+            return None;
+        }
+        let mut types = vec![];
+        for typ in args {
+            types.push(match typ {
+                Type::Alias(name) if ctx.type_alias.contains_key(name) => typ.clone(),
+                _ => typ.type_infer(ctx)?.compress_alias(ctx),
+            });
+        }
+        let alias = Type::Generic(name.to_string(), types.clone()).format();
+        if !ctx.type_alias.contains_key(&alias) {
+            // Defined ahead for the recursive type referring itself
+            ctx.type_alias
+                .insert(alias.clone(), Type::Alias(alias.clone()));
+            ctx.instance
+                .insert(alias.clone(), (name.to_string(), types.clone()));
+            let bound = params.into_iter().zip(types).collect();
+            let Some(typ) = template.substitute(&bound, ctx) else {
+                ctx.type_alias.shift_remove(&alias);
+                return None;
+            };
+            ctx.type_alias.insert(alias.clone(), typ);
+        }
+        Some(Type::Alias(alias))
+    }
+
+    /// Replace the type parameters with the types bound to them,
+    /// instantiating the generic type aliases in it
+    pub fn substitute(&self, bound: &IndexMap<String, Type>, ctx: &mut Compiler) -> Option<Type> {
+        let each = |types: &[Type], ctx: &mut Compiler| {
+            types
+                .iter()
+                .map(|t| t.substitute(bound, ctx))
+                .collect::<Option<Vec<_>>>()
+        };
+        Some(match self {
+            Type::Alias(name) => bound.get(name).cloned().unwrap_or(self.clone()),
+            Type::Generic(name, args) => Type::instantiate(name, &each(args, ctx)?, ctx)?,
+            Type::Array(typ) => Type::Array(Box::new(typ.substitute(bound, ctx)?)),
+            Type::Nullable(typ) => Type::Nullable(Box::new(typ.substitute(bound, ctx)?)),
+            Type::Function(args, ret) => {
+                Type::Function(each(args, ctx)?, Box::new(ret.substitute(bound, ctx)?))
+            }
+            Type::Dict(dict) => {
+                let mut result = IndexMap::new();
+                for (key, (offset, typ)) in dict {
+                    result.insert(key.clone(), (*offset, typ.substitute(bound, ctx)?));
+                }
+                Type::Dict(result)
+            }
+            Type::Enum(variants) => {
+                let mut result = IndexMap::new();
+                for (key, fields) in variants {
+                    result.insert(key.clone(), each(fields, ctx)?);
+                }
+                Type::Enum(result)
+            }
+            _ => self.clone(),
+        })
+    }
+
+    /// Bind the type parameters in the type of function argument,
+    /// so that it matches the type of the value actually passed
+    pub fn unify(
+        &self,
+        actual: &Type,
+        params: &[String],
+        bound: &mut IndexMap<String, Type>,
+        ctx: &mut Compiler,
+    ) -> Option<()> {
+        let actual = actual.type_infer(ctx)?;
+        match self {
+            Type::Alias(name) if params.contains(name) => {
+                if let Some(exist) = bound.get(name).cloned() {
+                    type_check!(exist, actual, ctx)?;
+                } else {
+                    bound.insert(name.clone(), actual);
+                }
+            }
+            Type::Generic(name, args) => {
+                let Type::Alias(alias) = actual.compress_alias(ctx) else {
+                    return Some(());
+                };
+                if let Some((generic, types)) = ctx.instance.get(&alias).cloned()
+                    && generic == *name
+                    && types.len() == args.len()
+                {
+                    for (arg, typ) in args.iter().zip(types) {
+                        arg.unify(&typ, params, bound, ctx)?;
+                    }
+                }
+            }
+            Type::Array(typ) => {
+                if let Type::Array(actual) = actual {
+                    typ.unify(&actual, params, bound, ctx)?;
+                }
+            }
+            // Non-null value can be given to the nullable argument too
+            Type::Nullable(typ) => match actual {
+                Type::Nullable(actual) => typ.unify(&actual, params, bound, ctx)?,
+                actual => typ.unify(&actual, params, bound, ctx)?,
+            },
+            Type::Function(args, ret) => {
+                if let Type::Function(actual_args, actual_ret) = actual {
+                    for (typ, actual) in args.iter().zip(actual_args.iter()) {
+                        typ.unify(actual, params, bound, ctx)?;
+                    }
+                    ret.unify(&actual_ret, params, bound, ctx)?;
+                }
+            }
+            Type::Dict(dict) => {
+                if let Type::Dict(actual) = actual {
+                    for (key, (_, typ)) in dict {
+                        if let Some((_, field)) = actual.get(key) {
+                            typ.unify(field, params, bound, ctx)?;
+                        }
+                    }
+                }
+            }
+            Type::Enum(variants) => {
+                if let Type::Enum(actual) = actual {
+                    for (key, fields) in variants {
+                        for (typ, field) in fields.iter().zip(actual.get(key).into_iter().flatten())
+                        {
+                            typ.unify(field, params, bound, ctx)?;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        Some(())
+    }
+
+    /// Whether the value is a pointer to the tag and the fields in heap,
+    /// that's the case of the enumerate type having any variant with fields
+    pub fn is_tagged(&self) -> bool {
+        matches!(self, Type::Enum(e) if e.values().any(|fields| !fields.is_empty()))
+    }
+
+    /// Whether the value of the other type can be given where this type is expected.
+    /// Aliases are resolved in the nested positions too, and non-null value is accepted
+    /// where the nullable one is expected, even in the fields of the dictionary
+    pub fn accepts(&self, value: &Type, ctx: &Compiler) -> bool {
+        self.accepts_assuming(value, true, ctx, &mut vec![])
+    }
+
+    /// Recursive aliases are assumed to match while their definitions are compared
+    fn accepts_assuming(
+        &self,
+        value: &Type,
+        is_widening: bool,
+        ctx: &Compiler,
+        assumed: &mut Vec<(Type, Type, bool)>,
+    ) -> bool {
+        if self == value || matches!((self, value), (Type::Error, _) | (_, Type::Error)) {
+            return true;
+        }
+        let pair = (self.clone(), value.clone(), is_widening);
+        if assumed.contains(&pair) {
+            return true;
+        }
+        let resolve = |typ: &Type| match typ {
+            Type::Alias(name) => (ctx.type_param.get(name))
+                .or_else(|| ctx.type_alias.get(name))
+                .filter(|define| *define != typ)
+                .cloned(),
+            _ => None,
+        };
+        if let Some(typ) = resolve(self) {
+            assumed.push(pair);
+            return typ.accepts_assuming(value, is_widening, ctx, assumed);
+        } else if let Some(typ) = resolve(value) {
+            assumed.push(pair);
+            return self.accepts_assuming(&typ, is_widening, ctx, assumed);
+        }
+        let mut same = |a: &Type, b: &Type| a.accepts_assuming(b, false, ctx, assumed);
+        match (self, value) {
+            (Type::Nullable(a), Type::Nullable(b)) => {
+                a.accepts_assuming(b, is_widening, ctx, assumed)
+            }
+            (Type::Nullable(a), b) if is_widening => a.accepts_assuming(b, true, ctx, assumed),
+            // Array is invariant, because its elements can be reassigned through the other name
+            (Type::Array(a), Type::Array(b)) => same(a, b),
+            (Type::Dict(a), Type::Dict(b)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b).all(|((key, (_, a)), (name, (_, b)))| {
+                        key == name && a.accepts_assuming(b, is_widening, ctx, assumed)
+                    })
+            }
+            (Type::Enum(a), Type::Enum(b)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b).all(|((key, a), (name, b))| {
+                        key == name
+                            && a.len() == b.len()
+                            && a.iter().zip(b).all(|(a, b)| same(a, b))
+                    })
+            }
+            (Type::Function(a, x), Type::Function(b, y)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b)) && same(x, y)
+            }
+            _ => false,
+        }
+    }
+
     pub fn format(&self) -> String {
         match self {
             Type::Integer => "int".to_string(),
@@ -147,9 +497,31 @@ impl Type {
                     .collect::<Vec<_>>()
                     .join(", ")
             ),
-            Type::Enum(e) => format!("( {} )", e.join(" | ")),
+            Type::Enum(e) => format!(
+                "( {} )",
+                e.iter()
+                    .map(|(name, fields)| if fields.is_empty() {
+                        name.to_string()
+                    } else {
+                        let fields = fields.iter().map(|typ| typ.format());
+                        format!("{name}({})", fields.collect::<Vec<_>>().join(", "))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            ),
             Type::Array(typ) => format!("[{}]", typ.format()),
+            Type::Nullable(typ) => format!("{}?", typ.format()),
+            Type::Function(args, ret) => {
+                let args = args.iter().map(|typ| typ.format());
+                let args = args.collect::<Vec<_>>().join(", ");
+                format!("fn({args}): {}", ret.format())
+            }
             Type::Alias(name) => name.to_string(),
+            Type::Generic(name, args) => {
+                let args = args.iter().map(|typ| typ.format());
+                format!("{name}<{}>", args.collect::<Vec<_>>().join(", "))
+            }
+            Type::Error => "{error}".to_string(),
         }
     }
 }
@@ -162,11 +534,22 @@ impl PartialEq for Type {
             (Type::Bool, Type::Bool) => true,
             (Type::String, Type::String) => true,
             (Type::Void, Type::Void) => true,
+            (Type::Error, Type::Error) => true,
             (Type::Dict(a), Type::Dict(b)) => a == b,
-            (Type::Enum(a), Type::Enum(b)) => a == b,
+            // Order of variants matters, because it decides their tags
+            (Type::Enum(a), Type::Enum(b)) => a.iter().eq(b.iter()),
             (Type::Array(a), Type::Array(b)) => a == b,
+            (Type::Nullable(a), Type::Nullable(b)) => a == b,
+            (Type::Function(a, x), Type::Function(b, y)) => a == b && x == y,
             (Type::Alias(a), Type::Alias(b)) => a == b,
+            (Type::Generic(a, x), Type::Generic(b, y)) => a == b && x == y,
             _ => false,
         }
     }
 }
+
+impl Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format())
+    }
+}
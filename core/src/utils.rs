@@ -2,15 +2,28 @@ use crate::*;
 
 pub const BYTES: i32 = 4;
 pub const SPACE: [&str; 5] = [" ", "　", "\n", "\t", "\r"];
-pub const OPERATOR: [&str; 23] = [
-    "+", "-", "*", "/", "%", "==", "=", "!=", "<<", ">>", "<=", ">=", "<", ">", "&&", "||", "&",
-    "|", "^", ":", "!", "?", "~",
+pub const OPERATOR: [&str; 29] = [
+    "+", "-", "*", "/", "%", "==", "=>", "=", "!=", "<<", ">>", "<=", ">=", "<", ">", "&&", "||",
+    "&", "|", "^", ":", "!", "?", "??", "?.", "~", "..", "..=", "...",
 ];
-pub const RESERVED: [&str; 15] = [
-    "pub", "let", "type", "if", "then", "else", "while", "loop", "break", "next", "return", "load",
-    "as", "try", "catch",
+pub const RESERVED: [&str; 19] = [
+    "pub", "let", "type", "if", "then", "else", "while", "for", "in", "loop", "break", "next",
+    "return", "load", "as", "try", "catch", "match", "with",
 ];
 
+/// Identifier of the function in WebAssembly text format, whose name may have characters
+/// that can't be used in it, like the type arguments of generic instance `f<[int], str>`
+pub fn mangle(name: &str) -> String {
+    let is_idchar = |c: char| c.is_ascii_alphanumeric() || "!#$&'*+-./:<=>?@\\^_`|~".contains(c);
+    name.chars()
+        .filter(|c| *c != ' ')
+        .map(|c| match c {
+            c if is_idchar(c) => c.to_string(),
+            c => format!("%{:X}", c as u32),
+        })
+        .collect()
+}
+
 pub fn expand_local(ctx: &mut Compiler) -> Option<String> {
     Some(join!(
         ctx.variable_type
@@ -78,20 +91,64 @@ macro_rules! is_ptr {
 macro_rules! type_check {
     ($lhs: expr, $rhs: expr, $ctx: expr) => {{
         let lhs = $lhs.type_infer($ctx)?.type_infer($ctx)?;
-        let rhs = $rhs.type_infer($ctx)?.type_infer($ctx)?;
-        if lhs == rhs {
+        // Right side that doesn't match the left one is reported at its span if it's known
+        let rhs_node = &$rhs;
+        let rhs = rhs_node.type_infer($ctx)?.type_infer($ctx)?;
+        if let Type::Error = rhs {
+            Some(lhs.clone())
+        } else if lhs == Type::Error {
+            Some(rhs.clone())
+        } else if lhs.accepts(&rhs, $ctx) {
             Some(lhs.clone())
         } else {
-            $ctx.occurred_error = Some(format!(
+            let errmsg = format!(
                 "type mismatch between {} and {}",
                 lhs.format(),
                 rhs.format()
-            ));
+            );
+            $ctx.report(Diagnostic::error(errmsg).with_span(rhs_node.span()));
+            None
+        }
+    }};
+}
+
+/// Check the value given where the type is expected, reporting the mismatch between them in order
+#[macro_export]
+macro_rules! value_check {
+    ($value: expr, $expected: expr, $ctx: expr) => {{
+        let value_node = &$value;
+        let value = value_node.type_infer($ctx)?.type_infer($ctx)?;
+        let expected = $expected.type_infer($ctx)?.type_infer($ctx)?;
+        if let Type::Error = value {
+            Some(expected.clone())
+        } else if expected.accepts(&value, $ctx) {
+            Some(expected.clone())
+        } else {
+            // Value that may be null isn't given where the non-null one is expected
+            let errmsg = format!(
+                "type mismatch between {} and {}",
+                value.format(),
+                expected.format()
+            );
+            $ctx.report(Diagnostic::error(errmsg).with_span(value_node.span()));
             None
         }
     }};
 }
 
+/// Type of the value that comes from either of the branches, that's nullable if one of them is
+#[macro_export]
+macro_rules! branch_check {
+    ($lhs: expr, $rhs: expr, $ctx: expr) => {{
+        let lhs = $lhs.type_infer($ctx)?.type_infer($ctx)?;
+        if $rhs.type_infer($ctx)?.type_infer($ctx)?.accepts(&lhs, $ctx) {
+            type_check!($rhs, lhs, $ctx)
+        } else {
+            type_check!($lhs, $rhs, $ctx)
+        }
+    }};
+}
+
 #[macro_export]
 macro_rules! compile_compare {
     ($oper: expr, $ctx: expr, $lhs: expr, $rhs: expr) => {{
@@ -112,7 +169,7 @@ macro_rules! compile_compare {
 
 #[macro_export]
 macro_rules! address_calc {
-    ($array: expr, $index: expr, $typ: expr) => {
+    ($array: expr, $index: expr) => {
         Expr::Operator(Box::new(Op::Add(
             Expr::Operator(Box::new(Op::Add(
                 Expr::Literal(Value::Integer(BYTES)),
@@ -135,12 +192,13 @@ macro_rules! compile_args {
         for arg in $args {
             let Expr::Operator(oper) = arg else {
                 let msg = "function argument definition needs type annotation";
-                $ctx.occurred_error = Some(msg.to_string());
+                let note = "write the argument like `name: type`";
+                $ctx.report(Diagnostic::error(msg).with_note(note));
                 return None;
             };
             let Op::Cast(Expr::Variable(name), typ) = *oper.clone() else {
                 let msg = "function argument name should be identifier";
-                $ctx.occurred_error = Some(msg.to_string());
+                $ctx.report(Diagnostic::error(msg));
                 return None;
             };
             if let Some(typ) = typ.type_infer($ctx) {
@@ -154,17 +212,22 @@ macro_rules! compile_args {
 
 #[macro_export]
 macro_rules! import_args {
-    ($sigs: expr) => {{
-        let Op::Cast(Expr::Call(name, args), ret_typ) = Op::parse($sigs)? else {
-            return None;
+    ($sigs: expr, $parser: expr, $start: expr) => {{
+        let span = $parser.span_from($start);
+        let errmsg = "import signature should be like `name(arg: type, ...): type`";
+        let Expr::Operator(sigs) = $sigs else {
+            return $parser.error_at(span, errmsg);
+        };
+        let Op::Cast(Expr::Call(name, args), ret_typ) = *sigs else {
+            return $parser.error_at(span, errmsg);
         };
         let mut args_typ = vec![];
         for arg in args {
             let Expr::Operator(arg) = arg else {
-                return None;
+                return $parser.error_at(span, errmsg);
             };
             let Op::Cast(Expr::Variable(arg_name), arg_typ) = *arg.clone() else {
-                return None;
+                return $parser.error_at(span, errmsg);
             };
             args_typ.push((arg_name, arg_typ));
         }
@@ -200,7 +263,7 @@ macro_rules! compile_arithmetic {
 macro_rules! correct {
     ($lhs: expr, $rhs: expr , $ctx: expr, $pat: pat) => {{
         let ret = type_check!($lhs, $rhs, $ctx)?;
-        if let $pat = ret {
+        if let $pat | Type::Error = ret {
             Some(ret)
         } else {
             let msg = format!(
@@ -208,7 +271,7 @@ macro_rules! correct {
                 $lhs.type_infer($ctx)?.format(),
                 $rhs.type_infer($ctx)?.format()
             );
-            $ctx.occurred_error = Some(msg);
+            $ctx.report(Diagnostic::error(msg));
             None
         }
     }};
@@ -1,4 +1,5 @@
 use crate::*;
+use std::fmt::{self, Display};
 
 #[derive(Debug, Clone)]
 pub enum Value {
@@ -7,47 +8,61 @@ pub enum Value {
     Bool(bool),
     Array(Vec<Expr>),
     Dict(IndexMap<String, Expr>),
-    Enum(Type, String),
+    /// Enumerator with the values of its fields `Type#Variant(expr, ...)`
+    Enum(Type, String, Vec<Expr>),
     String(String),
 }
 
 impl Node for Value {
-    fn parse(source: &str) -> Option<Self> {
-        // Integer literal
-        if let Ok(n) = source.parse::<i32>() {
-            Some(Value::Integer(n))
-        // Number literal
-        } else if let Ok(n) = source.parse::<f32>() {
-            Some(Value::Number(n))
-        // Boolean literal `true | false`
-        } else if let Ok(n) = source.parse::<bool>() {
-            Some(Value::Bool(n))
-        // String literal `"..."`
-        } else if source.starts_with("\"") && source.ends_with("\"") {
-            let source = source.get(1..source.len() - 1)?;
-            Some(Value::String(source.to_string()))
-        // Array `[expr, ...]`
-        } else if source.starts_with("[") && source.ends_with("]") {
-            let source = source.get(1..source.len() - 1)?.trim();
-            let elms = tokenize(source, &[","], false, true, false)?;
-            let elms = elms.iter().map(|i| Expr::parse(&i));
-            Some(Value::Array(elms.collect::<Option<Vec<_>>>()?))
-        // Dict `@{ field: expr, ... }`
-        } else if source.starts_with("@{") && source.ends_with("}") {
-            let token = source.get(2..source.len() - 1)?.trim();
-            let mut result = IndexMap::new();
-            for line in tokenize(token, &[","], false, true, false)? {
-                let (name, value) = line.trim().split_once(":")?;
-                let name = name.trim().to_string();
-                if !is_identifier(&name) {
-                    return None;
-                };
-                result.insert(name, Expr::parse(value)?);
+    fn parse(parser: &mut Parser) -> Option<Self> {
+        use TokenKind::{Identifier, Number, Operator, String as Str, Symbol};
+        Some(match parser.peek().cloned() {
+            // Integer or number literal
+            Some(Number(n)) => {
+                parser.advance();
+                Value::number(&n)?
             }
-            Some(Value::Dict(result))
-        } else {
-            None
-        }
+            // Boolean literal `true | false`
+            Some(Identifier(n)) if n == "true" || n == "false" => {
+                parser.advance();
+                Value::Bool(n == "true")
+            }
+            // String literal `"..."`
+            Some(Str(str)) => {
+                parser.advance();
+                Value::String(str)
+            }
+            // Array `[expr, ...]`
+            Some(Symbol('[')) => {
+                parser.advance();
+                let mut elms = vec![];
+                while !parser.is(&Symbol(']')) {
+                    elms.push(Expr::parse(parser)?);
+                    if !parser.eat(&Symbol(',')) {
+                        break;
+                    }
+                }
+                parser.expect(&Symbol(']'))?;
+                Value::Array(elms)
+            }
+            // Dict `@{ field: expr, ... }`
+            Some(Symbol('@')) => {
+                parser.advance();
+                parser.expect(&Symbol('{'))?;
+                let mut result = IndexMap::new();
+                while !parser.is(&Symbol('}')) {
+                    let name = parser.identifier()?;
+                    parser.expect(&Operator(":"))?;
+                    result.insert(name, Expr::parse(parser)?);
+                    if !parser.eat(&Symbol(',')) {
+                        break;
+                    }
+                }
+                parser.expect(&Symbol('}'))?;
+                Value::Dict(result)
+            }
+            _ => return parser.error(format!("expected expression, found {}", parser.found())),
+        })
     }
 
     fn compile(&self, ctx: &mut Compiler) -> Option<String> {
@@ -58,91 +73,54 @@ impl Node for Value {
             Value::Bool(n) => value(if *n { 1 } else { 0 }).compile(ctx)?,
             Value::String(str) => {
                 let result = value(ctx.allocator).compile(ctx)?;
-                let code = format!(r#"(data {result} "{str}\00")"#);
+                // Bytes other than printable ASCII are written as hex escapes `\hh`
+                let data = str.bytes().map(|byte| match byte {
+                    b' '..=b'~' if byte != b'"' && byte != b'\\' => (byte as char).to_string(),
+                    _ => format!("\\{byte:02x}"),
+                });
+                let code = format!(r#"(data {result} "{}\00")"#, data.collect::<String>());
                 ctx.allocator += str.len() as i32 + 1;
                 ctx.static_data.push(code);
                 result
             }
+            // Length followed by the elements is allocated in heap, every time it's evaluated
             Value::Array(array) => {
-                let Type::Array(inner_type) = self.type_infer(ctx)? else {
+                let typ = self.type_infer(ctx)?;
+                let Type::Array(inner_type) = &typ else {
                     return None;
                 };
-                let array = array.clone();
-                let mut result: Vec<_> = vec![];
-                let pointer;
-
-                if is_ptr!(inner_type, ctx) {
-                    let mut inner_codes = vec![];
-                    for elm in array.clone() {
-                        inner_codes.push(elm.compile(ctx)?)
-                    }
-                    pointer = ctx.allocator;
-                    let poke = Expr::Poke(value(ctx.allocator), value(array.len() as i32));
-                    result.push(poke.compile(ctx)?);
-                    ctx.allocator += BYTES;
-                    for code in inner_codes {
-                        result.push(format!(
-                            "({typ}.store {addr} {code})",
-                            typ = inner_type.compile(ctx)?,
-                            addr = value(ctx.allocator).compile(ctx)?,
-                        ));
-                        ctx.allocator += BYTES;
-                    }
-                } else {
-                    pointer = ctx.allocator;
-                    let poke = Expr::Poke(value(ctx.allocator), value(array.len() as i32));
-                    result.push(poke.compile(ctx)?);
-                    ctx.allocator += BYTES;
-                    for elm in array {
-                        type_check!(inner_type, elm.type_infer(ctx)?, ctx)?;
-                        let poke = Expr::Poke(value(ctx.allocator), Box::new(elm));
-                        result.push(poke.compile(ctx)?);
-                        ctx.allocator += BYTES
-                    }
+                let mut words = vec![(Type::Integer, value(array.len() as i32).compile(ctx)?)];
+                for elm in array {
+                    type_check!(inner_type, elm.type_infer(ctx)?, ctx)?;
+                    words.push((*inner_type.clone(), elm.compile(ctx)?));
                 }
-                join!([value(pointer).compile(ctx)?, join!(result)])
+                let layout = ctx.object_layout(&typ)?;
+                Value::allocate("array", layout, words, ctx)?
             }
             Value::Dict(dict) => {
-                let mut result: Vec<_> = vec![];
-                let Type::Dict(_) = self.type_infer(ctx)? else {
+                let typ @ Type::Dict(_) = self.type_infer(ctx)? else {
                     return None;
                 };
-
-                let mut prestore = IndexMap::new();
-                for (name, elm) in dict {
-                    let typ = elm.type_infer(ctx)?;
-                    if is_ptr!(typ, ctx) {
-                        prestore.insert(name, elm.compile(ctx)?);
-                    }
+                let mut words = vec![];
+                for elm in dict.values() {
+                    words.push((elm.type_infer(ctx)?, elm.compile(ctx)?));
                 }
-
-                let pointer = ctx.allocator;
-                for (name, elm) in dict {
-                    let typ = elm.type_infer(ctx)?;
-                    result.push(format!(
-                        "({typ}.store {addr} {value})",
-                        typ = typ.clone().compile(ctx)?,
-                        addr = value(ctx.allocator).compile(ctx)?,
-                        value = prestore.get(name).cloned().or_else(|| elm.compile(ctx))?
-                    ));
-                    ctx.allocator += BYTES;
-                }
-
-                join!([value(pointer).compile(ctx)?, join!(result)])
+                let layout = ctx.object_layout(&typ)?;
+                Value::allocate("dict", layout, words, ctx)?
             }
-            Value::Enum(typ, key) => {
-                let typ = typ.type_infer(ctx)?;
-                let Type::Enum(enum_type) = typ.clone() else {
-                    let error_message = format!("can't access enumerator to {}", typ.format());
-                    ctx.occurred_error = Some(error_message);
-                    return None;
-                };
-                let Some(variant) = enum_type.iter().position(|item| item == key) else {
-                    let error_message = format!("`{key}` is invalid variant of {}", typ.format());
-                    ctx.occurred_error = Some(error_message);
-                    return None;
-                };
-                value(variant as i32).compile(ctx)?
+            Value::Enum(typ, key, args) => {
+                let (tag, fields) = Value::variant(typ, key, ctx)?;
+                if !typ.type_infer(ctx)?.is_tagged() {
+                    return value(tag as i32).compile(ctx);
+                }
+                // Tag followed by the fields is allocated in heap
+                let mut words = vec![(Type::Integer, value(tag as i32).compile(ctx)?)];
+                for (arg, typ) in args.iter().zip(fields) {
+                    words.push((typ, arg.compile(ctx)?));
+                }
+                let types = words.iter().map(|(typ, _)| typ.clone());
+                let layout = ctx.layout(&types.collect::<Vec<_>>())?;
+                Value::allocate("enum", layout, words, ctx)?
             }
         })
     }
@@ -159,7 +137,8 @@ impl Node for Value {
                     let typ = e.type_infer(ctx)?;
                     if typ != origin {
                         let errmsg = "array elements must be of the same type";
-                        ctx.occurred_error = Some(errmsg.to_owned());
+                        let note = format!("expected {}, found {}", origin.format(), typ.format());
+                        ctx.report(Diagnostic::error(errmsg).with_note(note));
                         return None;
                     }
                 }
@@ -175,7 +154,104 @@ impl Node for Value {
                 }
                 Type::Dict(result)
             }
-            Value::Enum(typ, _) => typ.type_infer(ctx)?,
+            Value::Enum(typ, key, args) => {
+                let (_, fields) = Value::variant(typ, key, ctx)?;
+                if args.len() != fields.len() {
+                    let errmsg = format!(
+                        "variant `{key}` has {} fields, but {} values are given",
+                        fields.len(),
+                        args.len()
+                    );
+                    ctx.report(Diagnostic::error(errmsg));
+                    return None;
+                }
+                for (arg, field) in args.iter().zip(fields) {
+                    type_check!(field, arg, ctx)?;
+                }
+                typ.type_infer(ctx)?
+            }
         })
     }
 }
+
+impl Value {
+    /// Code that allocates the object in heap and stores the words of the types,
+    /// whose pointer is kept in a temporary local variable named by the kind
+    fn allocate(
+        kind: &str,
+        layout: i32,
+        words: Vec<(Type, String)>,
+        ctx: &mut Compiler,
+    ) -> Option<String> {
+        let pointer = format!("{kind}.{}", ctx.variable_type.len());
+        ctx.variable_type.insert(pointer.clone(), Type::Integer);
+        let size = words.len() as i32 * BYTES;
+        let mut result = vec![format!(
+            "(local.set ${pointer} {})",
+            ctx.alloc(size, layout)
+        )];
+        for (index, (typ, code)) in words.into_iter().enumerate() {
+            result.push(format!(
+                "({typ}.store offset={offset} (local.get ${pointer}) {code})",
+                typ = typ.compile(ctx)?,
+                offset = index as i32 * BYTES
+            ));
+        }
+        result.push(format!("(local.get ${pointer})"));
+        Some(join!(result))
+    }
+
+    /// Tag of the enumerator and types of its fields
+    pub fn variant(typ: &Type, key: &str, ctx: &mut Compiler) -> Option<(usize, Vec<Type>)> {
+        let typ = typ.type_infer(ctx)?;
+        let Type::Enum(enum_type) = typ.clone() else {
+            let error_message = format!("can't access enumerator to {}", typ.format());
+            ctx.report(Diagnostic::error(error_message));
+            return None;
+        };
+        let Some((tag, _, fields)) = enum_type.get_full(key) else {
+            let error_message = format!("`{key}` is invalid variant of {}", typ.format());
+            let variants = enum_type.keys().cloned().collect::<Vec<_>>();
+            let note = format!("available variants are {}", variants.join(", "));
+            ctx.report(Diagnostic::error(error_message).with_note(note));
+            return None;
+        };
+        Some((tag, fields.clone()))
+    }
+
+    /// Integer if it fits in 32 bits, otherwise floating point number
+    pub fn number(literal: &str) -> Option<Value> {
+        if let Ok(n) = literal.parse::<i32>() {
+            Some(Value::Integer(n))
+        } else {
+            Some(Value::Number(literal.parse::<f32>().ok()?))
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(n) => write!(f, "{n}"),
+            // Decimal point is needed to be distinguished from integer
+            Value::Number(n) if n.fract() == 0.0 && n.is_finite() => write!(f, "{n:.1}"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::String(str) => write!(f, "\"{}\"", escape(str)),
+            Value::Array(elms) => {
+                let elms = elms.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+                write!(f, "[{}]", elms.join(", "))
+            }
+            Value::Dict(dict) if dict.is_empty() => write!(f, "@{{}}"),
+            Value::Dict(dict) => {
+                let fields = dict.iter().map(|(name, value)| format!("{name}: {value}"));
+                write!(f, "@{{ {} }}", fields.collect::<Vec<_>>().join(", "))
+            }
+            Value::Enum(typ, variant, args) if args.is_empty() => write!(f, "{typ}#{variant}"),
+            Value::Enum(typ, variant, args) => {
+                let args = args.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+                write!(f, "{typ}#{variant}({})", args.join(", "))
+            }
+        }
+    }
+}
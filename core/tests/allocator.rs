@@ -0,0 +1,220 @@
+mod common;
+use common::{Program, errors, run_int};
+use mystia_core::{Allocator, Compiler};
+use wasmi::Val;
+
+fn program(source: &str, heap: Allocator) -> Program {
+    let mut compiler = Compiler::new();
+    compiler.heap = heap;
+    Program::load(&compiler.build(source).unwrap().wat)
+}
+
+/// Program whose memory starts from the pages and can grow up to the maximum
+fn limited(source: &str, heap: Allocator, memory: usize, max: Option<usize>) -> Program {
+    let mut compiler = Compiler::new();
+    compiler.heap = heap;
+    compiler.memory = memory;
+    compiler.max_memory = max;
+    Program::load(&compiler.build(source).unwrap().wat)
+}
+
+/// Program collecting garbage in the heap of less than 1 MiB after the shadow stack
+fn collect(source: &str) -> Result<Program, Vec<String>> {
+    let mut compiler = Compiler::new();
+    compiler.heap = Allocator::Gc;
+    compiler.memory = 16;
+    compiler.max_memory = Some(16);
+    match compiler.build(source) {
+        Ok(artifact) => Ok(Program::load(&artifact.wat)),
+        Err(errors) => Err(errors.into_iter().map(|x| x.message).collect()),
+    }
+}
+
+fn malloc(program: &mut Program, size: i32) -> i32 {
+    program.call("malloc", &[Val::I32(size)]).i32().unwrap()
+}
+
+fn free(program: &mut Program, ptr: i32) {
+    let func = program.instance.get_func(&program.store, "free").unwrap();
+    func.call(&mut program.store, &[Val::I32(ptr)], &mut [])
+        .unwrap();
+}
+
+fn pages(program: &Program) -> u32 {
+    let memory = program.instance.get_memory(&program.store, "mem").unwrap();
+    memory.current_pages(&program.store).into()
+}
+
+fn allocator(program: &Program) -> i32 {
+    let global = program.instance.get_global(&program.store, "allocator");
+    global.unwrap().get(&program.store).i32().unwrap()
+}
+
+#[test]
+fn reuse_freed_blocks() {
+    let mut program = program("0", Allocator::FreeList);
+    let a = malloc(&mut program, 12);
+    let b = malloc(&mut program, 100);
+    free(&mut program, a);
+    // Block of the same size class is reused, but the other one isn't
+    assert_eq!(malloc(&mut program, 9), a);
+    let c = malloc(&mut program, 12);
+    assert!(c != a && c != b);
+    free(&mut program, b);
+    free(&mut program, c);
+    assert_eq!(malloc(&mut program, 12), c);
+    assert_eq!(malloc(&mut program, 120), b);
+}
+
+#[test]
+fn ignore_null_and_static() {
+    let mut program = program(r#"let s = "static"; 0"#, Allocator::FreeList);
+    let a = malloc(&mut program, 8);
+    free(&mut program, -1);
+    free(&mut program, 0);
+    assert_ne!(malloc(&mut program, 8), a);
+}
+
+#[test]
+fn bump_never_reuses() {
+    let mut program = program("0", Allocator::Bump);
+    let a = malloc(&mut program, 12);
+    free(&mut program, a);
+    assert_eq!(malloc(&mut program, 12), a + 12);
+}
+
+#[test]
+fn drop_objects() {
+    let source = r#"let a = [1, 2, 3];
+        drop(a);
+        let b = [7, 8, 9];
+        let i = 0;
+        while i < 1000 loop {
+            let s = "abc" + "def";
+            drop(s);
+            let i + 1
+        };
+        b[0] + b[2]"#;
+    assert_eq!(run_int(source), 16);
+    // Second array reuses the block of the first one, and so do the strings
+    let mut program = program(source, Allocator::FreeList);
+    let start = allocator(&program);
+    program.call("_start", &[]);
+    assert_eq!(allocator(&program) - start, 32 + 16);
+}
+
+#[test]
+fn drop_primitive() {
+    assert_eq!(errors("drop(1)"), ["can't drop int typed value"]);
+}
+
+#[test]
+fn collect_garbage() {
+    // Strings of 3 MiB in total are allocated
+    let source = r#"let s = "";
+        let i = 0;
+        while i < 100000 loop {
+            let s = "abc" + i: str;
+            let i + 1
+        };
+        strlen(s)"#;
+    let mut program = collect(source).unwrap();
+    assert_eq!(program.call("_start", &[]).i32(), Some(8));
+    assert!(allocator(&program) < 16 << 16);
+}
+
+#[test]
+fn keep_reachable() {
+    let source = r#"type List = ( Nil | Cons(str, List) );
+        let sum(l: List): int = match l with
+            List#Nil => 0,
+            List#Cons(x, rest) => x: int + sum(rest);
+        let list = List#Nil;
+        let pair = ["a" + "b", "c" + "d"];
+        for i in 0..1000 loop {
+            for _ in 0..100 loop { let garbage = "garbage" + "string" };
+            let list = List#Cons(i: str, list)
+        };
+        let tail = |x: str| x + pair[0] + pair[1];
+        tail(sum(list): str)"#;
+    let mut program = collect(source).unwrap();
+    let result = program.call("_start", &[]).i32().unwrap();
+    assert_eq!(program.read_str(result), "499500abcd");
+}
+
+#[test]
+fn too_many_fields() {
+    let fields = (0..24).map(|i| format!("f{i}: 0")).collect::<Vec<_>>();
+    let source = format!(r#"let d = @{{ {}, s: "" }}; 0"#, fields.join(", "));
+    assert!(
+        collect(&source).is_err_and(
+            |errors| errors == ["GC can't trace objects referring others after 23 fields"]
+        )
+    );
+}
+
+#[test]
+fn export_objects() {
+    // Strings returned to the host aren't kept on the shadow stack, that'd overflow
+    let mut program = collect(r#"pub let greet(n: int) = "hi " + n: str; 0"#).unwrap();
+    program.call("_start", &[]);
+    for n in 0..40000 {
+        let result = program.call("greet", &[Val::I32(n)]).i32().unwrap();
+        assert_eq!(program.read_str(result), format!("hi {n}"));
+    }
+}
+
+/// Linked list of strings, that keeps 80 bytes or more for each node
+const LIST: &str = r#"type List = ( Nil | Cons(str, List) );
+    let build(n: int) = {
+        let list = List#Nil;
+        let text = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed ";
+        for i in 0..n loop { let list = List#Cons(text + i: str, list) };
+        list
+    };
+    let count(l: List): int = match l with
+        List#Nil => 0,
+        List#Cons(_, rest) => 1 + count(rest);"#;
+
+#[test]
+fn grow_memory() {
+    // Heap starts after the shadow stack of 128 KiB in GC mode
+    for (heap, memory) in [
+        (Allocator::Bump, 1),
+        (Allocator::FreeList, 1),
+        (Allocator::Gc, 3),
+    ] {
+        let mut program = limited(&format!("{LIST} count(build(1000))"), heap, memory, None);
+        assert_eq!(program.call("_start", &[]).i32(), Some(1000));
+        assert!(pages(&program) > memory as u32);
+    }
+    // Host can allocate more than the memory has too
+    let mut program = limited("0", Allocator::FreeList, 1, None);
+    let ptr = malloc(&mut program, 100000);
+    assert!(ptr + 100000 <= (pages(&program) << 16) as i32);
+}
+
+#[test]
+fn out_of_memory() {
+    let source = format!("{LIST} pub let run(n: int) = count(build(n)); 0");
+    for heap in [Allocator::Bump, Allocator::FreeList, Allocator::Gc] {
+        let mut program = limited(&source, heap, 3, Some(4));
+        program.call("_start", &[]);
+        assert_eq!(program.call("run", &[Val::I32(100)]).i32(), Some(100));
+        let error = program.trap("run", &[Val::I32(100000)]);
+        assert_eq!(error, "out of memory");
+        assert_eq!(pages(&program), 4);
+    }
+}
+
+#[test]
+fn fresh_literals() {
+    // Literal makes a new object every time it's evaluated, and `memcpy` copies it explicitly
+    let source = "let node(value: int) = @{ value: value, items: [value, value] };
+        let a = node(1);
+        let b = node(2);
+        let c = memcpy(a);
+        let c.value = 5;
+        a.value * 1000 + b.value * 100 + a.items[0] * 10 + c.value";
+    assert_eq!(run_int(source), 1215);
+}
@@ -0,0 +1,39 @@
+mod common;
+use common::{Program, run_int};
+use mystia_core::Compiler;
+
+fn program(source: &str, bounds_check: bool) -> Program {
+    let mut compiler = Compiler::new();
+    compiler.bounds_check = bounds_check;
+    Program::load(&compiler.build(source).unwrap().wat)
+}
+
+#[test]
+fn negative_index() {
+    // Negative index counts from the end in both modes
+    let source = "let a = [1, 2, 3]; let a[-2] = 5; a[-1] * 100 + a[1] * 10 + a[0]";
+    assert_eq!(run_int(source), 351);
+    let mut program = program(source, false);
+    assert_eq!(program.call("_start", &[]).i32(), Some(351));
+}
+
+#[test]
+fn out_of_bounds() {
+    for (source, message) in [
+        ("[1, 2, 3][10]", "index out of bounds: index 10, length 3"),
+        ("[1, 2, 3][-4]", "index out of bounds: index -4, length 3"),
+        (
+            r#"let a = ["a"]; let a[1] = "b"; 0"#,
+            "index out of bounds: index 1, length 1",
+        ),
+    ] {
+        let mut program = program(source, true);
+        assert_eq!(program.trap("_start", &[]), message);
+    }
+}
+
+#[test]
+fn wrap_index() {
+    let mut program = program("let a = [1, 2, 3]; a[10] * 10 + a[-4]", false);
+    assert_eq!(program.call("_start", &[]).i32(), Some(23));
+}
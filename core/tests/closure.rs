@@ -0,0 +1,90 @@
+mod common;
+use common::{compile, errors, run_int};
+
+const APPLY: &str = "let apply(f: fn(int): int, x: int) = f(x);";
+
+#[test]
+fn lambda() {
+    let source = format!("{APPLY} apply(|x: int| x * 2, 21)");
+    assert_eq!(run_int(&source), 42);
+    let source = "let answer = || 42; answer()";
+    assert_eq!(run_int(source), 42);
+}
+
+#[test]
+fn capture_local() {
+    let source =
+        format!("{APPLY} let k = 10; let scale = |x: int| x * k; scale(4) + apply(scale, 0)");
+    assert_eq!(run_int(&source), 40);
+}
+
+#[test]
+fn capture_argument() {
+    let source = format!(
+        "{APPLY} let make_adder(n: int) = |x: int| x + n;
+        let add2 = make_adder(2);
+        let add40 = make_adder(40);
+        apply(add2, 0) + add40(0)"
+    );
+    assert_eq!(run_int(&source), 42);
+}
+
+#[test]
+fn capture_by_value() {
+    let source = "let k = 1; let f = || k; let k = 2; f() * 10 + k";
+    assert_eq!(run_int(source), 12);
+}
+
+#[test]
+fn nested_lambda() {
+    let source = "let k = 30;
+        let f = |x: int| { let y = x + 2; let g = |z: int| z + y + k; g(0) };
+        f(10)";
+    assert_eq!(run_int(source), 42);
+}
+
+#[test]
+fn higher_order_generic() {
+    let source = "let map<T>(xs: [T], f: fn(T): T) = {
+            for i in 0..3 loop { let xs[i] = f(xs[i]) };
+            xs
+        };
+        let offset = 10;
+        let xs = map([1, 2, 3], |x: int| x + offset);
+        xs[0] + xs[1] + xs[2]";
+    assert_eq!(run_int(source), 36);
+}
+
+#[test]
+fn captures_only_referred() {
+    let wat = compile("let a = 1; let b = 2; let f = |x: int| x + b; f(a)");
+    assert!(wat.contains("(call $alloc (i32.const 8) (i32.const 0))"));
+    assert!(wat.contains("(local.set $b (i32.load offset=4 (global.get $closure)))"));
+    assert!(!wat.contains("(local.set $a (i32.load"));
+    // Parameter of the nested lambda isn't taken as the variable of the enclosing scope
+    let wat = compile("let a = 1; let f = |x: int| { let g = |a: int| a + x; g(2) }; f(a)");
+    assert!(!wat.contains("(local.set $a (i32.load"));
+    let source = "let a = 1; let f = |x: int| { let g = |a: int| a + x; g(2) }; f(a) * 10 + a";
+    assert_eq!(run_int(source), 31);
+}
+
+#[test]
+fn assign_captured() {
+    let source = "let n = 10; let f = |x: int| { let n + x; n }; f(5); n";
+    assert_eq!(
+        errors(source),
+        ["can't assign value to captured variable `n`"]
+    );
+    // Variable defined later is another one than the local of the lambda
+    let source = "let f = |x: int| { let t = x; t }; let t = 5; f(1) + t";
+    assert_eq!(run_int(source), 6);
+}
+
+#[test]
+fn type_mismatch() {
+    let source = format!("{APPLY} apply(|x: num| x * 2.0, 1)");
+    assert_eq!(
+        errors(&source),
+        ["type mismatch between fn(num): num and fn(int): int"]
+    );
+}
@@ -0,0 +1,161 @@
+//! Compile programs and run them on the WebAssembly interpreter
+#![allow(dead_code)]
+
+use mystia_core::Compiler;
+use wasmi::{Caller, Engine, Error, Instance, Linker, Memory, Module, Store, Val};
+
+/// Compile the program and assemble it to make sure the output is valid
+pub fn compile(source: &str) -> String {
+    let wat = Compiler::new()
+        .build(source)
+        .unwrap_or_else(|e| panic!("failed to compile: {e:?}"))
+        .wat;
+    wat::parse_str(&wat).unwrap_or_else(|e| panic!("invalid WAT: {e}\n{wat}"));
+    wat
+}
+
+/// Error messages of the program that fails to compile
+pub fn errors(source: &str) -> Vec<String> {
+    let errors = Compiler::new().build(source).expect_err("should fail");
+    errors.into_iter().map(|x| x.message).collect()
+}
+
+pub struct Program {
+    pub store: Store<()>,
+    pub instance: Instance,
+}
+
+impl Program {
+    pub fn new(source: &str) -> Program {
+        Program::load(&compile(source))
+    }
+
+    /// Instantiate the program compiled to WebAssembly text format
+    pub fn load(wat: &str) -> Program {
+        Program::link(wat, |_| {})
+    }
+
+    /// Instantiate the program with the functions of the host that it loads
+    pub fn link(wat: &str, host: impl FnOnce(&mut Linker<()>)) -> Program {
+        let wasm = wat::parse_str(wat).unwrap();
+        let engine = Engine::default();
+        let module = Module::new(&engine, &wasm[..]).unwrap();
+        let mut store = Store::new(&engine, ());
+        // Programs run without the functions of the host other than the one to abort,
+        // unless the test gives them
+        let mut linker = Linker::<()>::new(&engine);
+        linker
+            .func_wrap("env", "abort", |caller: Caller<'_, ()>, message: i32| {
+                let memory = caller.get_export("mem").and_then(|x| x.into_memory());
+                Err::<(), _>(Error::new(read_str(memory.unwrap(), &caller, message)))
+            })
+            .unwrap();
+        host(&mut linker);
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        Program { store, instance }
+    }
+
+    /// Call the exported function, that returns a value
+    pub fn call(&mut self, name: &str, args: &[Val]) -> Val {
+        let func = self.instance.get_func(&self.store, name).unwrap();
+        let mut result = [Val::I32(0)];
+        func.call(&mut self.store, args, &mut result).unwrap();
+        result[0].clone()
+    }
+
+    /// Call the exported function that traps, getting the error
+    pub fn trap(&mut self, name: &str, args: &[Val]) -> String {
+        let func = self.instance.get_func(&self.store, name).unwrap();
+        let mut result = [Val::I32(0)];
+        let error = func.call(&mut self.store, args, &mut result).unwrap_err();
+        error.to_string()
+    }
+
+    pub fn read_str(&self, addr: i32) -> String {
+        let memory = self.instance.get_memory(&self.store, "mem").unwrap();
+        read_str(memory, &self.store, addr)
+    }
+}
+
+/// Run the program and get the value it returns
+pub fn run(source: &str) -> Val {
+    Program::new(source).call("_start", &[])
+}
+
+/// Run the program that returns an integer
+pub fn run_int(source: &str) -> i32 {
+    run(source).i32().unwrap()
+}
+
+/// Run the program that returns a string
+pub fn run_str(source: &str) -> String {
+    let mut program = Program::new(source);
+    let addr = program.call("_start", &[]).i32().unwrap();
+    program.read_str(addr)
+}
+
+/// Null terminated UTF-8 string in the memory
+pub fn read_str(memory: Memory, store: impl wasmi::AsContext, addr: i32) -> String {
+    let data = memory.data(&store);
+    let bytes = &data[addr as usize..];
+    let end = bytes.iter().position(|x| *x == 0).unwrap();
+    String::from_utf8(bytes[..end].to_vec()).unwrap()
+}
+
+/// Debug dump of the syntax tree without source code positions,
+/// that the operands are wrapped with
+pub fn dump(tree: &impl std::fmt::Debug) -> String {
+    let debug = format!("{tree:?}");
+    let mut result = String::new();
+    let mut rest = debug.as_str();
+    while let Some(start) = rest.find(", Span {") {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        rest = &rest[rest.find('}').unwrap() + 1..];
+    }
+    let debug = result + rest;
+    // Parentheses of the wrappers are dropped with them, by the depth they're opened at
+    let (mut result, mut depth, mut wrappers) = (String::new(), 0, vec![]);
+    let mut rest = debug.as_str();
+    while let Some(c) = rest.chars().next() {
+        if let Some(inner) = rest.strip_prefix("Spanned(") {
+            wrappers.push(depth);
+            depth += 1;
+            rest = inner;
+            continue;
+        }
+        match c {
+            // Strings are copied as they are, whose parentheses aren't counted
+            '"' => {
+                let mut is_escaped = false;
+                let (end, _) = (rest.char_indices().skip(1))
+                    .find(|(_, c)| {
+                        let is_end = *c == '"' && !is_escaped;
+                        is_escaped = *c == '\\' && !is_escaped;
+                        is_end
+                    })
+                    .unwrap();
+                result.push_str(&rest[..=end]);
+                rest = &rest[end + 1..];
+                continue;
+            }
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if wrappers.last() == Some(&depth) {
+                    wrappers.pop();
+                    rest = &rest[1..];
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    result
+}
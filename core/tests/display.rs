@@ -0,0 +1,318 @@
+mod common;
+use common::dump;
+use mystia_core::*;
+use proptest::{collection::vec, option, prelude::*, sample::select};
+use std::fs::{read_dir, read_to_string};
+
+const BINARY: [&str; 19] = [
+    "+", "-", "*", "/", "%", ">>", "<<", "==", "!=", "<", ">", ">=", "<=", "&", "|", "^", "&&",
+    "||", "??",
+];
+
+fn name() -> impl Strategy<Value = String> {
+    select(vec!["a", "b", "foo", "bar_1", "x"]).prop_map(String::from)
+}
+
+fn type_name() -> impl Strategy<Value = String> {
+    select(vec!["T", "Point", "Shape"]).prop_map(String::from)
+}
+
+fn typ() -> impl Strategy<Value = Type> {
+    let leaf = prop_oneof![
+        Just(Type::Integer),
+        Just(Type::Number),
+        Just(Type::Bool),
+        Just(Type::String),
+        type_name().prop_map(Type::Alias),
+        type_name().prop_map(|name| Type::Alias(format!("geo.{name}"))),
+        vec(type_name(), 1..4).prop_map(|names| {
+            Type::Enum(names.into_iter().map(|name| (name, vec![])).collect())
+        }),
+    ];
+    leaf.prop_recursive(3, 12, 3, |inner| {
+        prop_oneof![
+            inner.clone().prop_map(|typ| Type::Array(Box::new(typ))),
+            inner
+                .clone()
+                .prop_filter("postfix", |typ| {
+                    !matches!(typ, Type::Function(_, _) | Type::Nullable(_))
+                })
+                .prop_map(|typ| Type::Nullable(Box::new(typ))),
+            (type_name(), vec(inner.clone(), 1..3))
+                .prop_map(|(name, args)| Type::Generic(name, args)),
+            (vec(inner.clone(), 0..3), inner.clone())
+                .prop_map(|(args, ret)| Type::Function(args, Box::new(ret))),
+            vec((type_name(), vec(inner.clone(), 0..3)), 1..4)
+                .prop_map(|variants| Type::Enum(variants.into_iter().collect())),
+            vec((name(), inner), 1..4).prop_map(|fields| {
+                let mut dict = Dict::new();
+                for (name, typ) in fields {
+                    let offset = dict.len() as i32 * BYTES;
+                    dict.entry(name).or_insert((offset, typ));
+                }
+                Type::Dict(dict)
+            }),
+        ]
+    })
+}
+
+fn enum_type() -> impl Strategy<Value = Type> {
+    prop_oneof![
+        type_name().prop_map(Type::Alias),
+        vec((type_name(), vec(typ(), 0..3)), 1..4)
+            .prop_map(|variants| Type::Enum(variants.into_iter().collect())),
+    ]
+}
+
+fn literal() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        any::<i32>().prop_map(Value::Integer),
+        any::<f32>()
+            .prop_filter("finite", |n| n.is_finite())
+            .prop_map(Value::Number),
+        any::<bool>().prop_map(Value::Bool),
+        "(?s).{0,8}".prop_map(Value::String),
+        (enum_type(), type_name()).prop_map(|(typ, variant)| Value::Enum(typ, variant, vec![])),
+    ]
+}
+
+/// Alternative patterns of the arm, where variables can be bound only without alternatives
+fn patterns() -> impl Strategy<Value = Vec<Pattern>> {
+    let literal = literal().prop_filter("matchable", |value| {
+        matches!(
+            value,
+            Value::Integer(_) | Value::String(_) | Value::Enum(_, _, _)
+        )
+    });
+    let binding = prop_oneof![name(), Just("_".to_string())];
+    let single = prop_oneof![Just(Pattern::Wildcard), literal.prop_map(Pattern::Literal)];
+    prop_oneof![
+        vec(single, 1..3),
+        (enum_type(), type_name(), vec(binding, 1..3))
+            .prop_map(|(typ, variant, names)| vec![Pattern::Variant(typ, variant, names)]),
+    ]
+}
+
+fn oper(op: Op) -> Expr {
+    Expr::Operator(Box::new(op))
+}
+
+fn block(stmts: Vec<Stmt>) -> Block {
+    Block(stmts.into_iter().map(|x| (x, Span::default())).collect())
+}
+
+fn expr() -> impl Strategy<Value = Expr> {
+    let leaf = prop_oneof![
+        literal().prop_map(Expr::Literal),
+        name().prop_map(Expr::Variable),
+    ];
+    leaf.prop_recursive(4, 32, 3, |inner| {
+        prop_oneof![
+            (select(BINARY.to_vec()), inner.clone(), inner.clone())
+                .prop_map(|(op, lhs, rhs)| oper(Op::binary(op, lhs, rhs).unwrap())),
+            (select(vec!["-", "!", "~"]), inner.clone())
+                .prop_map(|(op, expr)| oper(Op::unary(op, expr).unwrap())),
+            (inner.clone(), typ()).prop_map(|(expr, typ)| oper(Op::Cast(expr, typ))),
+            inner.clone().prop_map(|expr| oper(Op::NullCheck(expr))),
+            typ().prop_map(|typ| oper(Op::Nullable(typ))),
+            (name(), vec(inner.clone(), 0..3), option::of(name())).prop_map(
+                |(name, args, rest)| {
+                    let spread = rest.map(Expr::Spread);
+                    Expr::Call(name, args.into_iter().chain(spread).collect())
+                }
+            ),
+            (inner.clone(), inner.clone())
+                .prop_map(|(array, index)| Expr::Index(Box::new(array), Box::new(index))),
+            (inner.clone(), name()).prop_map(|(dict, name)| Expr::Field(Box::new(dict), name)),
+            (inner.clone(), name())
+                .prop_map(|(dict, name)| Expr::OptionalField(Box::new(dict), name)),
+            inner.clone().prop_map(|expr| Expr::Clone(Box::new(expr))),
+            vec(inner.clone(), 0..3).prop_map(|elms| Expr::Literal(Value::Array(elms))),
+            (enum_type(), type_name(), vec(inner.clone(), 1..3))
+                .prop_map(|(typ, variant, args)| Expr::Literal(Value::Enum(typ, variant, args))),
+            vec((name(), inner.clone()), 0..3)
+                .prop_map(|fields| Expr::Literal(Value::Dict(fields.into_iter().collect()))),
+            (inner.clone(), vec((patterns(), inner.clone()), 1..3))
+                .prop_map(|(subject, arms)| Expr::Match(Box::new(subject), arms)),
+            (vec((name(), typ()), 0..3), inner.clone())
+                .prop_map(|(params, body)| Expr::Lambda(params, Box::new(body))),
+            vec(inner.prop_map(Stmt::Expr), 1..3).prop_map(|stmts| Expr::Block(block(stmts))),
+        ]
+    })
+}
+
+fn iter() -> impl Strategy<Value = Iter> {
+    prop_oneof![
+        expr().prop_map(Iter::Array),
+        (expr(), expr()).prop_map(|(start, end)| Iter::Range(start, end)),
+        (expr(), expr()).prop_map(|(start, end)| Iter::RangeInclusive(start, end)),
+    ]
+}
+
+fn scope() -> impl Strategy<Value = Scope> {
+    prop_oneof![Just(Scope::Local), Just(Scope::Global)]
+}
+
+fn stmt() -> impl Strategy<Value = Stmt> {
+    let args = || vec((name(), typ()), 0..3);
+    let function = || {
+        (name(), args(), option::of(typ()), expr()).prop_map(|(name, args, ret, body)| {
+            let args = args
+                .into_iter()
+                .map(|(name, typ)| oper(Op::Cast(Expr::Variable(name), typ)));
+            let mut sig = Expr::Call(name, args.collect());
+            if let Some(ret) = ret {
+                sig = oper(Op::Cast(sig, ret));
+            }
+            Stmt::Let(Scope::Local, sig, body)
+        })
+    };
+    // Null-coalescing operator `??` can't be overloaded
+    let overloadable = BINARY
+        .into_iter()
+        .filter(|op| *op != "??")
+        .collect::<Vec<_>>();
+    let overload = (select(overloadable), typ(), typ(), name()).prop_map(|(op, lhs, rhs, name)| {
+        let dummy = Expr::Variable(String::new());
+        let id = Op::binary(op, dummy.clone(), dummy)
+            .unwrap()
+            .overload_id()
+            .unwrap();
+        Stmt::Overload(id, (lhs, rhs), name)
+    });
+    let negate = (typ(), name()).prop_map(|(typ, name)| {
+        let id = Op::Neg(Expr::Variable(String::new()))
+            .overload_id()
+            .unwrap();
+        Stmt::Overload(id, (typ, Type::Void), name)
+    });
+    let simple = prop_oneof![
+        expr().prop_map(Stmt::Expr),
+        (any::<bool>(), name(), expr()).prop_map(|(is_pub, name, value)| {
+            let scope = if is_pub { Scope::Global } else { Scope::Local };
+            Stmt::Let(scope, Expr::Variable(name), value)
+        }),
+        function(),
+        (any::<bool>(), vec(type_name(), 1..3), function()).prop_map(|(is_pub, params, define)| {
+            let Stmt::Let(_, sig, body) = define else {
+                unreachable!()
+            };
+            let scope = if is_pub { Scope::Global } else { Scope::Local };
+            Stmt::Generic(params, Box::new(Stmt::Let(scope, sig, body)))
+        }),
+        (scope(), type_name(), typ()).prop_map(|(scope, name, typ)| Stmt::Type(scope, name, typ)),
+        (scope(), vec(type_name(), 1..3), type_name(), typ()).prop_map(
+            |(scope, params, name, typ)| {
+                Stmt::Generic(params, Box::new(Stmt::Type(scope, name, typ)))
+            }
+        ),
+        (
+            scope(),
+            name(),
+            vec((name(), any::<bool>(), option::of(expr())), 0..3),
+            option::of(name()),
+            expr()
+        )
+            .prop_map(|(scope, name, params, rest, body)| {
+                // Parameters having default values are trailing, followed by the rest one
+                let mut params: Vec<_> = params
+                    .into_iter()
+                    .map(|(name, is_bound, default)| MacroParam {
+                        name,
+                        is_bound,
+                        is_rest: false,
+                        default,
+                    })
+                    .collect();
+                params.sort_by_key(|x| x.default.is_some());
+                params.extend(rest.map(|name| MacroParam {
+                    name,
+                    is_bound: false,
+                    is_rest: true,
+                    default: None,
+                }));
+                Stmt::Macro(scope, name, params, body)
+            }),
+        ("[a-z./]{1,12}", option::of(name())).prop_map(|(path, alias)| Stmt::Module(path, alias)),
+        (option::of(name()), name(), args(), typ())
+            .prop_map(|(module, name, args, ret)| Stmt::Import(module, (name, args, ret))),
+        overload,
+        negate,
+        (expr(), expr()).prop_map(|(cond, body)| Stmt::While(cond, body)),
+        (name(), iter(), expr()).prop_map(|(name, iter, body)| Stmt::For(name, iter, body)),
+        option::of(expr()).prop_map(Stmt::Return),
+        Just(Stmt::Break),
+        Just(Stmt::Next),
+    ];
+    simple.prop_recursive(2, 6, 2, |inner| {
+        prop_oneof![
+            (expr(), expr(), option::of(inner.clone())).prop_map(|(cond, then, r#else)| Stmt::If(
+                cond,
+                then,
+                r#else.map(Box::new)
+            )),
+            (expr(), inner).prop_map(|(expr, r#catch)| Stmt::Try(expr, Box::new(r#catch))),
+        ]
+    })
+}
+
+proptest! {
+    #[test]
+    fn expr_round_trips(expr in expr()) {
+        let source = expr.to_string();
+        let mut parser = Parser::new(&source);
+        let parsed = Expr::parse(&mut parser);
+        prop_assert!(parser.is_eof() && parser.diagnostics.is_empty(), "`{}`", source);
+        prop_assert_eq!(dump(&parsed.unwrap()), dump(&expr), "`{}`", source);
+    }
+
+    #[test]
+    fn block_round_trips(stmts in vec(stmt(), 1..4)) {
+        let block = block(stmts);
+        let source = block.to_string();
+        let parsed = parse_source(&source);
+        prop_assert!(parsed.is_ok(), "`{}`", source);
+        prop_assert_eq!(dump(&parsed.unwrap()), dump(&block), "`{}`", source);
+    }
+}
+
+#[test]
+fn nested_try_round_trips() {
+    // Negated null-check in the catch is kept apart from negative literals and the operands
+    let x = || Expr::Variable("x".to_string());
+    let operands = [
+        x(),
+        Expr::Literal(Value::Integer(5)),
+        Expr::Literal(Value::Integer(i32::MIN)),
+        Expr::Literal(Value::Number(-0.5)),
+        oper(Op::Neg(x())),
+        oper(Op::NullCheck(x())),
+        oper(Op::Nullable(Type::Integer)),
+        oper(Op::Cast(Expr::Literal(Value::Integer(-5)), Type::Integer)),
+        Expr::Index(Box::new(Expr::Literal(Value::Integer(-5))), Box::new(x())),
+        Expr::Lambda(vec![], Box::new(x())),
+    ];
+    for operand in operands {
+        let negated = oper(Op::Neg(oper(Op::NullCheck(operand))));
+        let inner = Stmt::Try(negated.clone(), Box::new(Stmt::Expr(negated.clone())));
+        let block = block(vec![Stmt::Try(x(), Box::new(inner)), Stmt::Expr(negated)]);
+        let source = block.to_string();
+        let parsed = parse_source(&source).unwrap_or_else(|_| panic!("`{source}`"));
+        assert_eq!(dump(&parsed), dump(&block), "`{source}`");
+    }
+}
+
+#[test]
+fn examples_round_trip() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../example");
+    for entry in read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|x| x != "ms") {
+            continue;
+        }
+        let ast = parse_source(&read_to_string(&path).unwrap())
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e[0].message));
+        let reparsed = parse_source(&ast.to_string()).unwrap();
+        assert_eq!(dump(&reparsed), dump(&ast), "{}", path.display());
+    }
+}
@@ -0,0 +1,97 @@
+mod common;
+use common::{errors, run, run_int};
+use wasmi::Val;
+
+const SHAPE: &str = "type Shape = ( Circle(num) | Rect(num, num) | Empty );
+let area(s: Shape) = match s with
+    Shape#Circle(r) => 3.0 * r * r,
+    Shape#Rect(w, h) => w * h,
+    Shape#Empty => 0.0;";
+
+const LIST: &str = "type List = ( Nil | Cons(int, List) );
+let sum(l: List): int = match l with
+    List#Nil => 0,
+    List#Cons(x, rest) => x + sum(rest);";
+
+fn run_num(source: &str) -> f32 {
+    run(source).f32().unwrap().to_float()
+}
+
+#[test]
+fn destructure_fields() {
+    let area = |shape: &str| run_num(&format!("{SHAPE} area(Shape#{shape})"));
+    assert_eq!(area("Circle(2.0)"), 12.0);
+    assert_eq!(area("Rect(2.0, 3.5)"), 7.0);
+    assert_eq!(area("Empty"), 0.0);
+}
+
+#[test]
+fn recursive_variant() {
+    let source = format!("{LIST} sum(List#Cons(1, List#Cons(20, List#Cons(300, List#Nil))))");
+    assert_eq!(run_int(&source), 321);
+}
+
+#[test]
+fn heap_layout() {
+    // Pointer to the tag followed by the fields
+    let mut program = common::Program::new(&format!(
+        "{SHAPE} pub let make(w: num, h: num) = Shape#Rect(w, h)"
+    ));
+    let args = [Val::F32(1.5.into()), Val::F32(2.5.into())];
+    let addr = program.call("make", &args).i32().unwrap() as usize;
+    let memory = program.instance.get_memory(&program.store, "mem").unwrap();
+    let data = &memory.data(&program.store)[addr..addr + 12];
+    let word = |i: usize| data[i * 4..i * 4 + 4].try_into().unwrap();
+    assert_eq!(i32::from_le_bytes(word(0)), 1);
+    assert_eq!(f32::from_le_bytes(word(1)), 1.5);
+    assert_eq!(f32::from_le_bytes(word(2)), 2.5);
+}
+
+#[test]
+fn ignored_fields_and_wildcard() {
+    let source = format!("{SHAPE} match Shape#Rect(4.0, 5.0) with Shape#Rect(_, h) => h, _ => 0.0");
+    assert_eq!(run_num(&source), 5.0);
+    let source = format!("{SHAPE} match Shape#Rect(4.0, 5.0) with Shape#Rect => 1, _ => 0");
+    assert_eq!(run_int(&source), 1);
+}
+
+#[test]
+fn wrong_fields() {
+    assert_eq!(
+        errors(&format!("{SHAPE} area(Shape#Rect(1.0))")),
+        ["variant `Rect` has 2 fields, but 1 values are given"]
+    );
+    assert_eq!(
+        errors(&format!("{SHAPE} area(Shape#Circle(1))")),
+        ["type mismatch between num and int"]
+    );
+    assert_eq!(
+        errors(&format!(
+            "{SHAPE} match Shape#Empty with Shape#Rect(w) => w, _ => 0.0"
+        )),
+        ["variant `Rect` has 2 fields, but 1 variables are bound"]
+    );
+}
+
+#[test]
+fn tagged_enums_are_not_compared() {
+    assert_eq!(
+        errors(&format!("{SHAPE} Shape#Empty == Shape#Empty")),
+        ["can't compare enumerators having fields"]
+    );
+}
+
+#[test]
+fn non_exhaustive_tagged_enum() {
+    let source = format!("{SHAPE} match Shape#Empty with Shape#Circle(r) => r");
+    assert_eq!(errors(&source), ["match on Shape isn't exhaustive"]);
+}
+
+#[test]
+fn format_type() {
+    let source = "type Shape = ( Circle(num) | Rect(num, num) | Empty ); 1: Shape";
+    assert_eq!(
+        errors(source),
+        ["type int can't convert to ( Circle(num) | Rect(num, num) | Empty )"]
+    );
+}
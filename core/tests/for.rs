@@ -0,0 +1,68 @@
+mod common;
+use common::{errors, run_int};
+
+#[test]
+fn ranges() {
+    assert_eq!(
+        run_int("let s = 0; for i in 1..5 loop { let s + i }; s"),
+        10
+    );
+    assert_eq!(
+        run_int("let s = 0; for i in 1..=5 loop { let s + i }; s"),
+        15
+    );
+    assert_eq!(run_int("let s = 0; for i in 5..1 loop { let s + i }; s"), 0);
+}
+
+#[test]
+fn array_elements() {
+    let source = "let s = 0; for x in [3, 40, 500] loop { let s + x }; s";
+    assert_eq!(run_int(source), 543);
+    let source = "let s = 0.0; for x in [1.5, 2.5] loop { let s + x }; s: int";
+    assert_eq!(run_int(source), 4);
+}
+
+#[test]
+fn break_and_next() {
+    let source = "let s = 0;
+        for i in 0..100 loop {
+            if i == 10 then { break };
+            if i % 2 == 1 then { next };
+            let s + i
+        };
+        s";
+    assert_eq!(run_int(source), 20);
+}
+
+#[test]
+fn nested_loops() {
+    let source = "let s = 0;
+        for i in 0..3 loop { for j in 0..=i loop { let s + 1 } };
+        s";
+    assert_eq!(run_int(source), 6);
+}
+
+#[test]
+fn end_is_evaluated_once() {
+    let source = "pub let count = 0;
+        let tick() = { pub let count = count + 1; 3 };
+        for i in 0..tick() loop { let i + 0 };
+        count";
+    assert_eq!(run_int(source), 1);
+}
+
+#[test]
+fn not_iterable() {
+    assert_eq!(
+        errors("for x in 1.5 loop { x }"),
+        ["can't iterate over num typed value"]
+    );
+    assert_eq!(
+        errors("for x in 0..1.5 loop { x }"),
+        ["type mismatch between num and int"]
+    );
+    assert_eq!(
+        errors("let x = \"a\"; for x in 0..3 loop { x }"),
+        ["type mismatch between str and int"]
+    );
+}
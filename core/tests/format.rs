@@ -0,0 +1,63 @@
+use mystia_core::{Cst, format_source};
+use std::{
+    fs::{read_dir, read_to_string},
+    path::PathBuf,
+};
+
+fn examples() -> Vec<(PathBuf, String)> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../example");
+    let mut sources = vec![];
+    for entry in read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|x| x == "ms") {
+            let source = read_to_string(&path).unwrap();
+            sources.push((path, source));
+        }
+    }
+    sources
+}
+
+#[test]
+fn cst_is_lossless() {
+    for (path, source) in examples() {
+        let cst = Cst::parse(&source).unwrap();
+        assert_eq!(cst.to_string(), source, "{}", path.display());
+    }
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    for (path, source) in examples() {
+        let formatted = format_source(&source)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e[0].message));
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+}
+
+#[test]
+fn canonical_style() {
+    let source = "load f(x:int):int; load g(): void;\n\n\n\
+        type T=(A|B);\n\
+        let h(x:int)={ ~~ comment ~~\n\
+        let y=-x*2;\n\
+          if !(y>0) then y\n\
+              else f( y )[ 0 ]\n\
+        };\n\
+        let d=@{a:[1,2],b:{ 1 }}";
+    let expected = "load f(x: int): int;\nload g(): void;\n\n\
+        type T = ( A | B );\n\
+        let h(x: int) = { ~~ comment ~~\n    \
+            let y = -x * 2;\n    \
+            if !(y > 0) then y\n    \
+            else f(y)[0]\n\
+        };\n\
+        let d = @{ a: [1, 2], b: { 1 } }\n";
+    assert_eq!(format_source(source).unwrap(), expected);
+}
+
+#[test]
+fn lambda_parameters() {
+    let source = "let f=| x:int , y:int |(x|y)*2;let g=||f(1, 2)";
+    let expected = "let f = |x: int, y: int| (x | y) * 2;\nlet g = || f(1, 2)\n";
+    assert_eq!(format_source(source).unwrap(), expected);
+}
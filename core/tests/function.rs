@@ -0,0 +1,67 @@
+mod common;
+use common::{compile, errors, run_int};
+use mystia_core::Compiler;
+
+const APPLY: &str = "let apply(f: fn(int): int, x: int) = f(x);
+let double(x: int) = x * 2;
+let inc(x: int) = x + 1;";
+
+#[test]
+fn pass_function() {
+    let source = format!("{APPLY} apply(double, 20) + apply(inc, 1)");
+    assert_eq!(run_int(&source), 42);
+}
+
+#[test]
+fn function_in_variable() {
+    let source = format!("{APPLY} let f = double; let g = f; g(10) + apply(g, 11)");
+    assert_eq!(run_int(&source), 42);
+    let source = format!("{APPLY} let f = double; let f = inc; f(41)");
+    assert_eq!(run_int(&source), 42);
+}
+
+#[test]
+fn generic_higher_order() {
+    let source = format!(
+        "{APPLY} let map<T>(xs: [T], f: fn(T): T) = {{
+            for i in 0..3 loop {{ let xs[i] = f(xs[i]) }};
+            xs
+        }};
+        let xs = map([1, 2, 3], double);
+        xs[0] + xs[1] + xs[2]"
+    );
+    assert_eq!(run_int(&source), 12);
+}
+
+#[test]
+fn function_table() {
+    let wat = compile(&format!(
+        "{APPLY} apply(double, 1) + apply(inc, 1) + apply(double, 2)"
+    ));
+    assert!(wat.contains("(table $table (export \"table\") 2 funcref)"));
+    assert!(wat.contains("(elem (i32.const 0) $double $inc)"));
+    assert!(wat.contains("call_indirect"));
+}
+
+#[test]
+fn type_mismatch() {
+    let source = format!("{APPLY} let half(x: num) = x / 2.0; apply(half, 1)");
+    assert_eq!(
+        errors(&source),
+        ["type mismatch between fn(num): num and fn(int): int"]
+    );
+    let source = format!("{APPLY} apply(1, 1)");
+    assert_eq!(
+        errors(&source),
+        ["type mismatch between int and fn(int): int"]
+    );
+}
+
+#[test]
+fn mismatch_span() {
+    // Operand that doesn't match is underlined, not the whole statement
+    let source = format!("{APPLY} let a = apply(double, 1) + \"x\"; a");
+    let errors = Compiler::new().build(&source).unwrap_err();
+    let span = errors[0].span.unwrap();
+    assert_eq!(&source[span.start..span.end], "\"x\"");
+}
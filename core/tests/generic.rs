@@ -0,0 +1,105 @@
+mod common;
+use common::{compile, errors, run, run_int};
+
+const OPTION: &str = "type Option<T> = ( Some(T) | None );
+let unwrap_or<T>(value: Option<T>, or: T) = match value with
+    Option<T>#Some(inner) => inner,
+    Option<T>#None => or;";
+
+#[test]
+fn identity() {
+    let source = "let id<T>(x: T) = x; id(2.5): int + id(40)";
+    assert_eq!(run_int(source), 42);
+}
+
+#[test]
+fn generic_type_alias() {
+    let source =
+        format!("{OPTION} unwrap_or(Option<int>#Some(42), 0) + unwrap_or(Option<int>#None, 1)");
+    assert_eq!(run_int(&source), 43);
+    let source = format!("{OPTION} unwrap_or(Option<[int]>#None, [1, 2, 3])[2]");
+    assert_eq!(run_int(&source), 3);
+}
+
+#[test]
+fn nested_type_arguments() {
+    let source = format!(
+        "{OPTION} let x = Option<Option<int>>#Some(Option<int>#Some(7));
+        x.unwrap_or(Option<int>#None).unwrap_or(0)"
+    );
+    assert_eq!(run_int(&source), 7);
+}
+
+#[test]
+fn recursive_generic_type() {
+    let source = "type List<T> = ( Nil | Cons(T, List<T>) );
+        let length<T>(l: List<T>): int = match l with
+            List<T>#Nil => 0,
+            List<T>#Cons(_, rest) => 1 + length(rest);
+        let l = List<num>#Cons(1.5, List<num>#Cons(2.5, List<num>#Nil));
+        length(l) * 10 + length(List<str>#Cons(\"a\", List<str>#Nil))";
+    assert_eq!(run_int(source), 21);
+}
+
+#[test]
+fn instance_per_type_arguments() {
+    let wat = compile("let id<T>(x: T) = x; id(1) + id(2); id([1]); id(1.5)");
+    let funcs = |name: &str| wat.matches(&format!("(func ${name} ")).count();
+    assert_eq!(funcs("id<int>"), 1);
+    assert_eq!(funcs("id<%5Bint%5D>"), 1);
+    assert_eq!(funcs("id<num>"), 1);
+}
+
+#[test]
+fn instance_in_block() {
+    let source = "let id<T>(x: T) = x; let a = { id(1) }; a + id(2)";
+    assert_eq!(run_int(source), 3);
+    let wat = compile(source);
+    assert_eq!(wat.matches("(func $id<int> ").count(), 1);
+}
+
+#[test]
+fn type_parameter_in_body() {
+    let source = "let first<T>(xs: [T]) = { let x = xs[0]: T; x }; first([1.5, 2.5])";
+    assert_eq!(run(source).f32().unwrap().to_float(), 1.5);
+}
+
+#[test]
+fn mismatched_arguments() {
+    assert_eq!(
+        errors("let pair<T>(a: T, b: T) = a; pair(1, \"a\")"),
+        ["type mismatch between int and str"]
+    );
+    assert_eq!(
+        errors("let make<T>(n: int) = n; make(1)"),
+        ["can't infer type parameter `T` of function `make`"]
+    );
+}
+
+#[test]
+fn wrong_type_arguments() {
+    assert_eq!(
+        errors(&format!("{OPTION} Option<int, str>#None")),
+        ["generic type `Option` takes 1 type arguments, but 2 are given"]
+    );
+    assert_eq!(
+        errors(&format!("{OPTION} Option#None")),
+        ["generic type `Option` takes 1 type arguments, but 0 are given"]
+    );
+    assert_eq!(
+        errors("type Point = @{ x: int }; 1: Point<int>"),
+        ["type alias `Point` doesn't take type arguments"]
+    );
+}
+
+#[test]
+fn comparison_is_not_type_arguments() {
+    assert_eq!(
+        run_int("let a = 1; let b = 2; let c = 3; if a < b && c > b then 1 else 0"),
+        1
+    );
+    assert_eq!(
+        run_int("let a = 1; let b = 64; if a < b >> 1 then 1 else 0"),
+        1
+    );
+}
@@ -0,0 +1,298 @@
+mod common;
+use common::{Program, compile, errors, read_str, run_int};
+use mystia_core::Compiler;
+use wasmi::{Caller, Linker, Memory};
+
+const TIMES: &str = "macro times(let n, body) = {
+    let i = 0;
+    while i < n loop {
+        body;
+        let i + 1
+    }
+};";
+
+#[test]
+fn substitute_expression() {
+    let source = "macro add(x, xs) = x + xs[0]; add(1, [41])";
+    assert_eq!(run_int(source), 42);
+    let source = "macro inc(n) = { try n + 1 catch n + 1.0 }; (inc(5): num + inc(3.5)): int";
+    assert_eq!(run_int(source), 10);
+}
+
+#[test]
+fn evaluate_once() {
+    let source = "let c = 0; macro twice(x) = x + x; twice({ let c + 1; c })";
+    assert_eq!(run_int(source), 3);
+    let source = "let c = 0; macro twice(let x) = x + x; twice({ let c + 1; c })";
+    assert_eq!(run_int(source), 2);
+}
+
+#[test]
+fn hygiene() {
+    let source = format!("{TIMES} let i = 1; let total = 0; times(3, {{ let total + i }}); total");
+    assert_eq!(run_int(&source), 3);
+    let source = format!("{TIMES} let total = 0; times(3, times(2, {{ let total + 1 }})); total");
+    assert_eq!(run_int(&source), 6);
+}
+
+#[test]
+fn assign_caller_variable() {
+    // Variable in scope where it's called is assigned, not the renamed one
+    let source = "let count = 0; macro bump() = { let count = count + 1 }; bump(); bump(); count";
+    assert_eq!(run_int(source), 2);
+    let source = "let f(n: int) = { let m = n; macro bump() = { let m + 1 }; bump(); m }; f(41)";
+    assert_eq!(run_int(source), 42);
+}
+
+#[test]
+fn local_not_leaked() {
+    let source = "macro square(let x) = { let y = x * x; y }; square(3) + y";
+    assert_eq!(errors(source), ["undefined variable `y`"]);
+}
+
+#[test]
+fn call_parameter() {
+    let source = "macro call(f) = f(41); let inc(x: int) = x + 1; call(inc)";
+    assert_eq!(run_int(source), 42);
+    let source = "macro call(let f) = f(41); call(|x: int| x + 1)";
+    assert_eq!(run_int(source), 42);
+    let source = "macro call(f) = f(41); call(|x: int| x + 1)";
+    assert_eq!(
+        errors(source),
+        ["can't call `(|x: int| x + 1)` given to macro parameter `f`"]
+    );
+}
+
+#[test]
+fn invalid_expansion() {
+    let source = "macro f(x) = f(x) + 1; f(1)";
+    assert_eq!(errors(source), ["macro `f` can't be expanded recursively"]);
+    let source = "macro f(x, y) = x + y; f(1)";
+    assert_eq!(
+        errors(source),
+        ["arguments of macro `f` length should be 2, but passed 1 values"]
+    );
+}
+
+#[test]
+fn invalid_let_target() {
+    let errmsg = "left side of `let` must be a variable, field, index or function signature";
+    for source in ["let b: int = 5; b", "let s = 0; let s + 1 - 1; s"] {
+        assert_eq!(errors(source), [errmsg], "{source}");
+    }
+    // Checking goes on to the type errors after it
+    let source = "let b: int = 5; let c = b + 1; 1 + \"x\"";
+    assert_eq!(
+        errors(source),
+        [errmsg, "type mismatch between int and str"]
+    );
+}
+
+#[test]
+fn expanded_source() {
+    let source = format!("{TIMES} let i_0 = 1; times(2, i_0)");
+    let ast = Compiler::new().expand(&source).unwrap();
+    let expanded = ast.to_string();
+    assert!(expanded.contains("let n_0 = 2; { let i_1 = 0; while i_1 < n_0 loop"));
+    assert!(expanded.contains("{ i_0; let i_1 = i_1 + 1 }"));
+}
+
+const SUM: &str = "macro sum(...xs) = {
+    let total = 0;
+    for x in xs loop { let total + x };
+    total
+};";
+
+#[test]
+fn rest_parameter() {
+    let source = format!("{SUM} sum(1, 2, 3) * 10 + sum()");
+    assert_eq!(run_int(&source), 60);
+    let source = "macro vec(...xs) = xs; let v = vec(4, 5, 6); v[0] + v[2]";
+    assert_eq!(run_int(source), 10);
+    let source = "macro size(...xs) = { let n = 0; for _x in xs loop { let n + 1 }; n };
+        size(1, 2.5, \"three\", [4])";
+    assert_eq!(run_int(source), 4);
+}
+
+#[test]
+fn rest_loop_control() {
+    // Loop over the rest leaves the iteration by `next` and the whole by `break`
+    let source = "macro first_over(limit, ...xs) = {
+            let found = -1;
+            for x in xs loop {
+                if x <= limit then { next };
+                let found = x;
+                break
+            };
+            found
+        };
+        first_over(2, 1, 5, 9) * 10 + first_over(9, 1, 2)";
+    assert_eq!(run_int(source), 49);
+    // Enclosing loop isn't left by them
+    let source = "macro count(...xs) = {
+            let n = 0;
+            for x in xs loop { if x == 0 then { break }; let n + 1 };
+            n
+        };
+        let total = 0;
+        for i in 0..3 loop { let total + count(1, i, 2) };
+        total";
+    assert_eq!(run_int(source), 7);
+}
+
+#[test]
+fn spread_arguments() {
+    let source =
+        format!("{SUM} macro forward(first, ...xs) = sum(...xs, first * 10); forward(4, 1, 2)");
+    assert_eq!(run_int(&source), 43);
+    let source =
+        "macro call(f, ...xs) = f(...xs); let add(a: int, b: int) = a + b; call(add, 40, 2)";
+    assert_eq!(run_int(source), 42);
+    let source = "let f(x: int) = x; f(...x)";
+    assert_eq!(errors(source), ["`...x` isn't the rest parameter of macro"]);
+}
+
+#[test]
+fn default_value() {
+    let source = "macro add(a, b = 2, c = b * 10) = a + b + c; add(1) * 100 + add(1, 1, 1)";
+    assert_eq!(run_int(source), 2303);
+    let source = "macro add(a, b = 2) = a + b; add()";
+    assert_eq!(
+        errors(source),
+        ["arguments of macro `add` length should be 1 to 2, but passed 0 values"]
+    );
+    let source = format!("{SUM} macro f(a, ...xs) = a; f()");
+    assert_eq!(
+        errors(&source),
+        ["arguments of macro `f` length should be at least 1, but passed 0 values"]
+    );
+}
+
+#[test]
+fn invalid_parameters() {
+    let source = "macro f(...xs, a) = a; 1";
+    assert_eq!(errors(source)[0], "rest parameter `...xs` must be the last");
+    let source = "macro f(a = 1, b) = a; 1";
+    assert_eq!(
+        errors(source)[0],
+        "parameter `b` must have the default value"
+    );
+}
+
+fn memory(caller: &Caller<'_, ()>) -> Memory {
+    caller
+        .get_export("mem")
+        .and_then(|x| x.into_memory())
+        .unwrap()
+}
+
+fn read_array(caller: &Caller<'_, ()>, addr: i32) -> Vec<i32> {
+    let data = memory(caller).data(caller);
+    let word = |addr: usize| i32::from_le_bytes(data[addr..addr + 4].try_into().unwrap());
+    let length = word(addr as usize) as usize;
+    (0..length)
+        .map(|i| word(addr as usize + 4 * (i + 1)))
+        .collect()
+}
+
+/// Copy the bytes to the memory allocated by the program
+fn write_bytes(caller: &mut Caller<'_, ()>, bytes: &[u8]) -> i32 {
+    let malloc = caller.get_export("malloc").and_then(|x| x.into_func());
+    let malloc = malloc.unwrap().typed::<i32, i32>(&*caller).unwrap();
+    let addr = malloc.call(&mut *caller, bytes.len() as i32).unwrap();
+    memory(caller).write(caller, addr as usize, bytes).unwrap();
+    addr
+}
+
+fn write_str(caller: &mut Caller<'_, ()>, text: &str) -> i32 {
+    write_bytes(caller, &[text.as_bytes(), &[0]].concat())
+}
+
+fn write_array(caller: &mut Caller<'_, ()>, elms: &[i32]) -> i32 {
+    let mut bytes = (elms.len() as i32).to_le_bytes().to_vec();
+    bytes.extend(elms.iter().flat_map(|x| x.to_le_bytes()));
+    write_bytes(caller, &bytes)
+}
+
+/// Functions on the strings that example/calc.ms loads, as the ones of JavaScript
+fn string_host(linker: &mut Linker<()>) {
+    let text = |caller: &Caller<'_, ()>, addr: i32| read_str(memory(caller), caller, addr);
+    linker
+        .func_wrap(
+            "env",
+            "split",
+            move |mut caller: Caller<'_, ()>, a: i32, b: i32| {
+                let (text, sep) = (text(&caller, a), text(&caller, b));
+                let parts: Vec<String> = match sep.as_str() {
+                    "" => text.chars().map(String::from).collect(),
+                    sep => text.split(sep).map(String::from).collect(),
+                };
+                let addrs: Vec<_> = parts.iter().map(|x| write_str(&mut caller, x)).collect();
+                write_array(&mut caller, &addrs)
+            },
+        )
+        .unwrap()
+        .func_wrap("env", "strlen", move |caller: Caller<'_, ()>, a: i32| {
+            text(&caller, a).len() as i32
+        })
+        .unwrap()
+        .func_wrap(
+            "env",
+            "slice",
+            |mut caller: Caller<'_, ()>, a: i32, b: i32, c: i32| {
+                let elms = read_array(&caller, a);
+                let len = elms.len() as i32;
+                let index = |n: i32| (if n < 0 { len + n } else { n }).clamp(0, len) as usize;
+                let (start, end) = (index(b), index(c));
+                write_array(&mut caller, &elms[start..end.max(start)])
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "env",
+            "append",
+            |mut caller: Caller<'_, ()>, a: i32, b: i32| {
+                let elms = [read_array(&caller, a), read_array(&caller, b)].concat();
+                write_array(&mut caller, &elms)
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "env",
+            "join",
+            move |mut caller: Caller<'_, ()>, a: i32, b: i32| {
+                let parts: Vec<_> = read_array(&caller, a)
+                    .into_iter()
+                    .map(|x| text(&caller, x))
+                    .collect();
+                let joined = parts.join(&text(&caller, b));
+                write_str(&mut caller, &joined)
+            },
+        )
+        .unwrap()
+        .func_wrap("env", "arrlen", |caller: Caller<'_, ()>, a: i32| {
+            read_array(&caller, a).len() as i32
+        })
+        .unwrap();
+}
+
+#[test]
+fn calc_example() {
+    // Lexer adds the tokens by the macro assigning the variable of the function
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../example/calc.ms");
+    let source = std::fs::read_to_string(path).unwrap();
+    let mut program = Program::link(&compile(&source), string_host);
+    let addr = program.call("_start", &[]).i32().unwrap();
+    assert_eq!(
+        program.read_str(addr),
+        "(i32.sub (i32.mul (i32.const 3) (i32.add (i32.const 1) (i32.const 2))) (i32.const 4))"
+    );
+}
+
+#[test]
+fn statement_types_by_expansion() {
+    // Value of the statement is dropped only in the expansion that has one
+    let source = "let nop() = { let x = 1 }; let one() = 1;
+        macro m(f) = { f(); 2 }; m(nop) + m(one)";
+    assert_eq!(run_int(source), 4);
+}
@@ -0,0 +1,103 @@
+mod common;
+use common::{errors, run_int, run_str};
+
+const KIND: &str = "type Kind = ( Add | Sub | Mul | Div );
+let apply(k: Kind, a: int, b: int) = match k with
+    Kind#Add => a + b,
+    Kind#Sub => a - b,
+    Kind#Mul | Kind#Div => a * b;";
+
+#[test]
+fn enum_variants() {
+    let run = |kind| run_int(&format!("{KIND} apply(Kind#{kind}, 6, 3)"));
+    assert_eq!(
+        [run("Add"), run("Sub"), run("Mul"), run("Div")],
+        [9, 3, 18, 18]
+    );
+}
+
+#[test]
+fn enum_uses_jump_table() {
+    let wat = common::compile(&format!("{KIND} apply(Kind#Add, 1, 2)"));
+    assert!(wat.contains("(br_table 0 1 2 2 2 "), "{wat}");
+}
+
+#[test]
+fn integers_and_wildcard() {
+    let name = r#"let name(n: int) = match n with 0 => "zero", 1 | -1 => "one", _ => "many";"#;
+    for (n, expected) in [(0, "zero"), (1, "one"), (-1, "one"), (7, "many")] {
+        assert_eq!(run_str(&format!("{name} name({n})")), expected);
+    }
+}
+
+#[test]
+fn strings() {
+    let source = r#"let code(s: str) = match s with "a" => 1, "b" | "c" => 2, _ => 0;
+        code("a") * 100 + code("c") * 10 + code("z")"#;
+    assert_eq!(run_int(source), 120);
+}
+
+#[test]
+fn wildcard_catches_rest_of_enum() {
+    let source = format!("{KIND} match Kind#Div with Kind#Add => 1, _ => 2");
+    assert_eq!(run_int(&source), 2);
+}
+
+#[test]
+fn subject_is_evaluated_once() {
+    let source = "pub let count = 0;
+        let tick() = { pub let count = count + 1; count };
+        let x = match tick() with 1 => 10, 2 => 20, _ => 30;
+        x + count";
+    assert_eq!(run_int(source), 11);
+}
+
+#[test]
+fn nested_match() {
+    let source = "let f(a: int, b: int) = match a with
+            0 => (match b with 0 => 1, _ => 2),
+            _ => (match b with 0 => 3, _ => 4);
+        f(0, 0) + f(0, 1) * 10 + f(1, 0) * 100 + f(1, 1) * 1000";
+    assert_eq!(run_int(source), 4321);
+}
+
+#[test]
+fn void_match_may_skip_arms() {
+    let source = "let x = 0; match 5 with 1 => { let x = 1 }, 2 => { let x = 2 }; x";
+    assert_eq!(run_int(source), 0);
+}
+
+#[test]
+fn non_exhaustive_enum() {
+    let source = format!("{KIND} match Kind#Add with Kind#Add => 1, Kind#Mul => 2");
+    assert_eq!(errors(&source), ["match on Kind isn't exhaustive"]);
+    let wrong = format!("{KIND} match Kind#Add with Kind#Add => 1, Kind#Nop => 2, _ => 0");
+    assert_eq!(
+        errors(&wrong),
+        ["`Nop` is invalid variant of ( Add | Sub | Mul | Div )"]
+    );
+}
+
+#[test]
+fn non_exhaustive_value() {
+    assert_eq!(
+        errors("match 1 with 1 => 2"),
+        ["match on int isn't exhaustive"]
+    );
+}
+
+#[test]
+fn mismatched_pattern() {
+    assert_eq!(
+        errors(r#"match 1 with "a" => 2, _ => 3"#),
+        [r#"pattern `"a"` doesn't match int typed value"#]
+    );
+    assert_eq!(
+        errors("match 1.5 with _ => 3"),
+        ["can't match num typed value"]
+    );
+    assert_eq!(
+        errors("match 1 with x => 3"),
+        ["expected pattern, found `x`"]
+    );
+}
@@ -0,0 +1,131 @@
+mod common;
+use common::Program;
+use mystia_core::{Compiler, Diagnostic};
+use std::{env::temp_dir, fs};
+
+/// Write the files in the directory for the test, returning the compiler of the main one
+fn files(test: &str, files: &[(&str, &str)]) -> (Compiler, String) {
+    let dir = temp_dir().join(format!("mystia-{test}-{}", std::process::id()));
+    for (path, source) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+    let mut compiler = Compiler::new();
+    compiler.file = dir.join(files[0].0).to_string_lossy().to_string();
+    (compiler, files[0].1.to_string())
+}
+
+fn run_files(test: &str, sources: &[(&str, &str)]) -> (i32, String) {
+    let (mut compiler, source) = files(test, sources);
+    let wat = compiler
+        .build(&source)
+        .unwrap_or_else(|e| panic!("{e:?}"))
+        .wat;
+    let result = Program::load(&wat).call("_start", &[]).i32().unwrap();
+    (result, wat)
+}
+
+fn errors_files(test: &str, sources: &[(&str, &str)]) -> Vec<Diagnostic> {
+    let (mut compiler, source) = files(test, sources);
+    compiler.build(&source).expect_err("should fail")
+}
+
+const GEOMETRY: &str = "pub type Point = @{ x: int, y: int };
+pub type Shape = ( Square(int) | Rect(int, int) );
+let square(x: int) = x * x;
+pub let dist2(a: Point, b: Point) = square(a.x - b.x) + square(a.y - b.y);
+pub let area(shape: Shape) = match shape with
+    Shape#Square(w) => square(w),
+    Shape#Rect(w, h) => w * h;
+pub macro twice(let n) = square(n) / n * 2";
+
+#[test]
+fn import_definitions() {
+    let main = r#"import "./lib/geometry.ms" as geo;
+        let square(x: int) = x + 1000;
+        let p = @{ x: 3, y: 4 }: geo.Point;
+        let f = geo.area;
+        geo.dist2(@{ x: 0, y: 0 }, p) + f(geo.Shape#Rect(2, 3)) + geo.twice(5) + square(0)"#;
+    let (result, wat) = run_files(
+        "import",
+        &[("main.ms", main), ("lib/geometry.ms", GEOMETRY)],
+    );
+    assert_eq!(result, 1041);
+    // Names are qualified by the file, and public functions aren't exported
+    assert!(wat.contains("(func $geometry.square"));
+    assert!(wat.contains("(func $square"));
+    assert!(!wat.contains("(export \"area\")"));
+}
+
+#[test]
+fn import_once() {
+    let main = r#"import "./a.ms"; import "./b.ms" as other; a.f() * 10 + other.f()"#;
+    let a = r#"import "./lib/c.ms"; pub let f() = c.value() + 1"#;
+    let b = r#"import "./lib/c.ms" as c; pub let f() = c.value() + 2"#;
+    let c = "pub let value() = 1";
+    let files = [("main.ms", main), ("a.ms", a), ("b.ms", b), ("lib/c.ms", c)];
+    let (result, wat) = run_files("once", &files);
+    assert_eq!(result, 23);
+    assert_eq!(wat.matches("(func $c.value").count(), 1);
+}
+
+#[test]
+fn access_error() {
+    let main = r#"import "./geometry.ms" as geo; geo.square(1)"#;
+    let errors = errors_files("private", &[("main.ms", main), ("geometry.ms", GEOMETRY)]);
+    assert_eq!(errors[0].message, "`square` of module `geo` is private");
+    let main = r#"import "./geometry.ms" as geo; geo.volume(1)"#;
+    let errors = errors_files("undefined", &[("main.ms", main), ("geometry.ms", GEOMETRY)]);
+    assert_eq!(errors[0].message, "module `geo` doesn't define `volume`");
+}
+
+#[test]
+fn circular_import() {
+    let main = r#"import "./a.ms"; 1"#;
+    let a = r#"import "./b.ms"; pub let f() = 1"#;
+    let b = r#"import "./a.ms"; pub let g() = 2"#;
+    let errors = errors_files("circular", &[("main.ms", main), ("a.ms", a), ("b.ms", b)]);
+    assert_eq!(errors[0].message, "circular import of `./a.ms`");
+    assert!(errors[0].file.as_ref().unwrap().ends_with("/b.ms"));
+    assert!(errors[0].notes[0].ends_with("main.ms -> a.ms -> b.ms -> a.ms"));
+}
+
+#[test]
+fn diagnostic_file() {
+    let main = "import \"./lib.ms\";\nlib.f(1)";
+    let lib = "pub let f(x: int) = 0;\npub let g(x: int) =\n    x + \"a\"";
+    let (mut compiler, source) = files("diagnostic", &[("main.ms", main), ("lib.ms", lib)]);
+    let errors = compiler.build(&source).expect_err("should fail");
+    assert_eq!(errors[0].message, "type mismatch between int and str");
+    assert!(errors[0].file.as_ref().unwrap().ends_with("/lib.ms"));
+    let rendered = compiler.render(&errors[0], &source, "main.ms");
+    assert!(rendered.contains("lib.ms:3:9\n"), "{rendered}");
+
+    let lib = "let x = 1;\npub let f() = x";
+    let errors = errors_files("statement", &[("main.ms", main), ("lib.ms", lib)]);
+    let message = "module can only have definitions at the top level";
+    assert_eq!(errors[0].message, message);
+}
+
+#[test]
+fn import_package() {
+    let main = r#"import "geometry"; geometry.dist2(@{ x: 0, y: 0 }, @{ x: 3, y: 4 })"#;
+    let (mut compiler, source) = files(
+        "package",
+        &[("app/main.ms", main), ("geometry/src/lib.ms", GEOMETRY)],
+    );
+    let dir = std::path::Path::new(&compiler.file).parent().unwrap();
+    let entry = dir
+        .join("../geometry/src/lib.ms")
+        .to_string_lossy()
+        .to_string();
+    compiler.packages.insert(String::from("geometry"), entry);
+    compiler.memory = 2;
+    let wat = compiler
+        .build(&source)
+        .unwrap_or_else(|e| panic!("{e:?}"))
+        .wat;
+    assert_eq!(Program::load(&wat).call("_start", &[]).i32(), Some(25));
+    assert!(wat.contains("(memory $mem (export \"mem\") 2)"));
+}
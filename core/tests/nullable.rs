@@ -0,0 +1,140 @@
+mod common;
+use common::{Program, errors, run_int};
+use mystia_core::{Allocator, Compiler};
+
+#[test]
+fn narrowing() {
+    // Null-checked variable is non-null in the branch, and after the early return
+    let source = r#"
+        let find(names: [str], key: str): str? = {
+            let found = str!;
+            for name in names loop {
+                if name == key then { let found = name }
+            };
+            found
+        };
+        let size(name: str?) = {
+            if !(name?) then { return -1 };
+            strlen(name)
+        };
+        let name = find(["a", "bc"], "bc");
+        let length = { if name? then strlen(name) else 0 };
+        length * 10 + size(find(["a"], "bc"))
+    "#;
+    assert_eq!(run_int(source), 19);
+}
+
+#[test]
+fn linked_list() {
+    let source = r#"
+        type List = @{ value: int, rest: List? };
+        let cons(value: int, rest: List?): List = @{ value: value, rest: rest };
+        let sum(list: List?) = {
+            let total = 0;
+            let node = list;
+            while node? loop {
+                let total + node.value;
+                let node = node.rest
+            };
+            total
+        };
+        sum(cons(1, cons(2, cons(3, List!)))) * 10 + sum(List!)
+    "#;
+    assert_eq!(run_int(source), 60);
+    // Null isn't traced as an object in GC mode
+    let mut compiler = Compiler::new();
+    compiler.heap = Allocator::Gc;
+    let mut program = Program::load(&compiler.build(source).unwrap().wat);
+    assert_eq!(program.call("_start", &[]).i32(), Some(60));
+}
+
+#[test]
+fn build_list_in_loop() {
+    // Literal holding the non-null node matches the alias whose field is nullable
+    let source = r#"
+        type List = @{ value: int, rest: List? };
+        let head = List!;
+        for i in 1..4 loop {
+            let head = @{ value: i, rest: head }
+        };
+        let first = @{ value: 10, rest: head }: List;
+        let total = 0;
+        let node = first: List?;
+        while node? loop {
+            let total + node.value;
+            let node = node.rest
+        };
+        total
+    "#;
+    assert_eq!(run_int(source), 16);
+    // Nullable field isn't given where the non-null one is expected
+    let source = r#"
+        type List = @{ value: int, rest: List? };
+        let node = @{ value: 1, rest: List! };
+        let strict = node: @{ value: int, rest: List };
+        0
+    "#;
+    assert_eq!(
+        errors(source),
+        [
+            "type @{ value: int, rest: @{ value: int, rest: List? }? } can't convert to @{ value: int, rest: @{ value: int, rest: List? } }"
+        ]
+    );
+}
+
+#[test]
+fn coalesce_and_optional_field() {
+    let source = r#"
+        type Person = @{ name: str, friend: Person? };
+        let person(name: str, friend: Person?): Person = @{ name: name, friend: friend };
+        let alice = person("alice", Person!);
+        let bob = person("bob", alice);
+        let nobody = Person!;
+        let names = [bob.friend?.name ?? "", alice.friend?.name ?? "none", nobody?.name ?? "x"];
+        strlen(names[0]) * 100 + strlen(names[1]) * 10 + strlen(names[2])
+    "#;
+    assert_eq!(run_int(source), 541);
+}
+
+#[test]
+fn unchecked_access() {
+    for (source, message) in [
+        (
+            "let a = [int]!; a[0]",
+            "can't index access to [int]? that may be null",
+        ),
+        (
+            "let p = @{ x: 1 }: @{ x: int }?; p.x",
+            "can't field access to @{ x: int }? that may be null",
+        ),
+        (
+            "let p = @{ x: 1 }: @{ x: int }?; p?.x",
+            "can't optional access to int typed field `x`, that can't be null",
+        ),
+        (
+            r#"let s = "a": str?; strlen(s)"#,
+            "type mismatch between str? and str",
+        ),
+        ("int!", "int typed value can't be null"),
+        ("let x = 1; x ?? 2", "can't null-coalesce int typed value"),
+    ] {
+        assert_eq!(errors(source), [message], "{source}");
+    }
+}
+
+#[test]
+fn loop_assigns_null() {
+    // Null-check before the loop doesn't hold in it, if the body assigns null
+    let source = r#"
+        let s = "abc": str?;
+        let n = 0;
+        if s? then {
+            while n < 3 loop {
+                let n + strlen(s);
+                let s = str!
+            }
+        };
+        n
+    "#;
+    assert_eq!(errors(source), ["type mismatch between str? and str"]);
+}
@@ -0,0 +1,105 @@
+mod common;
+use common::dump;
+use mystia_core::{Expr, Node, OPERATOR, Parser};
+
+/// Binary operators from the loosest to the tightest binding
+const TIERS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<", ">", "<=", ">="],
+    &["??"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Operators that aren't binary ones in expressions
+const NOT_BINARY: [&str; 10] = ["=", "=>", ":", "!", "?", "?.", "~", "..", "..=", "..."];
+
+fn parse(source: &str) -> Option<Expr> {
+    let mut parser = Parser::new(source);
+    let expr = Expr::parse(&mut parser).filter(|_| parser.is_eof());
+    expr.filter(|_| parser.diagnostics.is_empty())
+}
+
+/// Assert both have the same syntax tree, which parentheses don't appear in
+fn assert_grouping(source: &str, expected: &str) {
+    let actual = parse(source).unwrap_or_else(|| panic!("failed to parse `{source}`"));
+    let expected = parse(expected).unwrap_or_else(|| panic!("failed to parse `{expected}`"));
+    assert_eq!(dump(&actual), dump(&expected), "`{source}`");
+}
+
+fn tier(op: &str) -> usize {
+    TIERS.iter().position(|ops| ops.contains(&op)).unwrap()
+}
+
+#[test]
+fn every_operator_is_covered() {
+    for op in OPERATOR {
+        let is_binary = TIERS.iter().any(|ops| ops.contains(&op));
+        assert!(is_binary ^ NOT_BINARY.contains(&op), "`{op}`");
+    }
+}
+
+#[test]
+fn binary_operator_pairs() {
+    let binaries = TIERS.concat();
+    for a in &binaries {
+        for b in &binaries {
+            let source = format!("x {a} y {b} z");
+            if tier(a) > tier(b) || (tier(a) == tier(b) && tier(a) != tier("==")) {
+                assert_grouping(&source, &format!("(x {a} y) {b} z"));
+            } else if tier(a) < tier(b) {
+                assert_grouping(&source, &format!("x {a} (y {b} z)"));
+            } else {
+                assert!(parse(&source).is_none(), "`{source}` should be rejected");
+            }
+        }
+    }
+}
+
+#[test]
+fn prefix_operators() {
+    for prefix in ["-", "!", "~"] {
+        for op in TIERS.concat() {
+            assert_grouping(&format!("{prefix}x {op} y"), &format!("({prefix}x) {op} y"));
+            assert_grouping(&format!("x {op} {prefix}y"), &format!("x {op} ({prefix}y)"));
+        }
+        assert_grouping(&format!("{prefix}x: int"), &format!("({prefix}x): int"));
+        assert_grouping(&format!("{prefix}x?"), &format!("{prefix}(x?)"));
+        assert_grouping(
+            &format!("{prefix} {prefix}x"),
+            &format!("{prefix}({prefix}x)"),
+        );
+    }
+}
+
+#[test]
+fn postfix_operators() {
+    for op in TIERS.concat() {
+        assert_grouping(&format!("x? {op} y?"), &format!("(x?) {op} (y?)"));
+        assert_grouping(&format!("x {op} T!"), &format!("x {op} (T!)"));
+        assert_grouping(&format!("T#A {op} x"), &format!("(T#A) {op} x"));
+    }
+    assert_grouping("x.y?", "(x.y)?");
+    assert_grouping("x[0]?", "(x[0])?");
+    assert_grouping("x? ?", "(x?)?");
+    assert_grouping("x?.y?", "(x?.y)?");
+    assert_grouping("x?.y.z", "(x?.y).z");
+    assert_grouping("x?.y ?? z", "(x?.y) ?? z");
+}
+
+#[test]
+fn cast_operator() {
+    for op in TIERS.concat() {
+        assert_grouping(
+            &format!("x: int {op} y: num"),
+            &format!("(x: int) {op} (y: num)"),
+        );
+    }
+    assert_grouping("x: int: str", "(x: int): str");
+    assert_grouping("x?: bool", "(x?): bool");
+}
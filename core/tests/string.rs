@@ -0,0 +1,151 @@
+mod common;
+use common::{compile, run_int, run_str};
+use mystia_core::{Allocator, Compiler, Value, parse_source};
+
+/// Compile the program and assemble it to make sure the output is valid,
+/// whose heap starts right after the static data by the bump allocator
+fn build(source: &str) -> String {
+    let mut compiler = Compiler::new();
+    compiler.heap = Allocator::Bump;
+    let wat = compiler.build(source).unwrap().wat;
+    wat::parse_str(&wat).unwrap_or_else(|e| panic!("invalid WAT: {e}\n{wat}"));
+    wat
+}
+
+/// Addresses and decoded bytes of the data segments
+fn data_segments(wat: &str) -> Vec<(i32, Vec<u8>)> {
+    let mut result = vec![];
+    for segment in wat.split("(data (i32.const ").skip(1) {
+        let (addr, rest) = segment.split_once(") \"").unwrap();
+        let mut bytes = vec![];
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    bytes.push(u8::from_str_radix(&hex, 16).unwrap());
+                }
+                _ => bytes.extend(c.to_string().as_bytes()),
+            }
+        }
+        result.push((addr.parse().unwrap(), bytes));
+    }
+    result
+}
+
+/// Message of the abort when the memory can't grow, stored after the strings of the program
+/// that allocates
+const ABORT: &str = "out of memory\0";
+
+fn allocator(wat: &str) -> i32 {
+    let (_, rest) = wat
+        .split_once(r#"(export "allocator") (mut i32) (i32.const "#)
+        .unwrap();
+    rest[..rest.find(')').unwrap()].parse().unwrap()
+}
+
+#[test]
+fn escape_sequences() {
+    let wat = build(r#"pub let main() = "tab\tquote\"back\\slash\nend\u{41}\u{1F600}""#);
+    let expected = "tab\tquote\"back\\slash\nendA😀\0";
+    assert_eq!(data_segments(&wat)[0], (0, expected.as_bytes().to_vec()));
+    assert_eq!(allocator(&wat), expected.len() as i32);
+}
+
+#[test]
+fn japanese_text_is_stored_as_utf8() {
+    let wat = build(r#"pub let main() = { let a = "こんにちは、世界"; "ミスティア" }"#);
+    let first = "こんにちは、世界\0".as_bytes();
+    let second = "ミスティア\0".as_bytes();
+    let second_addr = first.len() as i32;
+    assert_eq!(
+        data_segments(&wat),
+        [(0, first.to_vec()), (second_addr, second.to_vec())]
+    );
+    assert_eq!(allocator(&wat), second_addr + second.len() as i32);
+    assert!(wat.contains(&format!("(i32.const {second_addr}))")));
+}
+
+#[test]
+fn escapes_in_fstring() {
+    let ast = parse_source(r#"let x = f"\{\"{1}\"\}\n""#).unwrap();
+    assert_eq!(ast.to_string(), r#"let x = "{\"" + { 1 }: str + "\"}\n""#);
+}
+
+#[test]
+fn invalid_escape_sequences() {
+    for (source, errmsg) in [
+        (r#""\q""#, "unknown escape sequence `\\q`"),
+        (r#""\u41""#, "unicode escape must be in the form `\\u{...}`"),
+        (r#""\u{110000}""#, "invalid unicode escape `\\u{110000}`"),
+        (r#""\u{+41}""#, "invalid unicode escape `\\u{+41}`"),
+        (r#"f"{1}\q""#, "unknown escape sequence `\\q`"),
+    ] {
+        let errors = parse_source(source).unwrap_err();
+        assert_eq!(errors[0].message, errmsg, "{source}");
+    }
+}
+
+#[test]
+fn display_escapes_string() {
+    let str = "\"こんにちは\"\\\n\t\u{7}";
+    let printed = Value::String(str.to_string()).to_string();
+    assert_eq!(printed, r#""\"こんにちは\"\\\n\t\u{7}""#);
+    let ast = parse_source(&printed).unwrap();
+    assert_eq!(format!("{ast}"), printed);
+}
+
+#[test]
+fn builtins_without_imports() {
+    let source =
+        r#"let name = "Mystia"; f"Hello, {name}! {strlen(name)} {-2147483648} {0.1 + 0.2}""#;
+    assert_eq!(run_str(source), "Hello, Mystia! 6 -2147483648 0.3");
+    let wat = compile(source);
+    // Only the function to abort is imported, telling the message after the strings
+    assert_eq!(wat.matches("(import").count(), 1);
+    let segments = data_segments(&build(source));
+    assert_eq!(segments.last().unwrap().1, ABORT.as_bytes());
+    // Program that neither allocates nor indexes imports nothing
+    let wat = compile("let a = 1 + 2; a * 2");
+    assert!(!wat.contains("$str/"));
+    assert!(!wat.contains("(import"));
+    assert!(!wat.contains("out of memory"));
+}
+
+#[test]
+fn format_numbers() {
+    for (source, expected) in [
+        ("3.14", "3.14"),
+        ("-0.5", "-0.5"),
+        ("1.9999999", "2"),
+        ("-0.0000001", "0"),
+        ("1.0 / 0.0", "Infinity"),
+        ("-1.0 / 0.0", "-Infinity"),
+        ("0.0 / 0.0", "NaN"),
+    ] {
+        assert_eq!(run_str(&format!("({source}): str")), expected, "{source}");
+    }
+}
+
+#[test]
+fn parse_numbers() {
+    for (source, expected) in [
+        ("\" -12.5e1xyz\": num", "-125"),
+        ("\"42abc\": int", "42"),
+        ("\"7.9\": int", "7"),
+        ("\".5\": num", "0.5"),
+        ("\"x\": num", "NaN"),
+        ("\"x\": int", "0"),
+    ] {
+        assert_eq!(run_str(&format!("{source}: str")), expected, "{source}");
+    }
+}
+
+#[test]
+fn compare_strings() {
+    let source = r#"let s = "ab" + "c";
+        let flag(x: bool) = { if x then 1 else 0 };
+        flag(s == "abc") * 100 + flag(s != "abc") * 10 + flag("ab" == "abc")"#;
+    assert_eq!(run_int(source), 100);
+}
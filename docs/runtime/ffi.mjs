@@ -1,58 +1,91 @@
 const BYTES = 4;
 
-export function read(instance, type, value) {
+// Descriptors named after the aliases are kept for the recursive references in them
+export function read(instance, type, value, aliases = {}) {
     const memoryView = new Uint8Array(instance.exports.mem.buffer);
+    if (type.alias) aliases = { ...aliases, [type.alias]: type };
     if (type == "int") {
         return value;
     } else if (type == "num") {
         return Math.round(value * 1e6) / 1e6;
     } else if (type == "bool") {
         return value != 0;
+    } else if (type.type == "nullable") {
+        // Null pointer is -1, because the address 0 can be valid
+        return value == -1 ? null : read(instance, type.element, value, aliases);
     } else if (type == "str") {
-        if (value == -1) return null;
         let stringLength = value;
         while (memoryView[stringLength] != 0) stringLength++;
         const stringBytes = memoryView.slice(value, stringLength);
         const textDecoder = new TextDecoder("utf-8");
         return textDecoder.decode(stringBytes);
     } else if (type.type == "array") {
-        if (value == -1) return null;
         const innerType = type.element;
         let [result, addr] = [[], value + BYTES];
         const length = concatBytes(memoryView.slice(value, addr), false);
         for (let index = 0; index < length; index++) {
             const sliced = memoryView.slice(addr, addr + BYTES);
             const elem = concatBytes(sliced, innerType == "num");
-            result.push(read(instance, innerType, elem));
+            result.push(read(instance, innerType, elem, aliases));
             addr += BYTES;
         }
         return result;
     } else if (type.type == "dict") {
-        if (value == -1) return null;
         const [pointer, result] = [value, {}];
         for (let [name, field] of Object.entries(type.fields)) {
             const address = pointer + field.offset;
             const sliced = memoryView.slice(address, address + BYTES);
             const value = concatBytes(sliced, field.type == "num");
-            const fieldType = field.type.type == "alias" ? type : field.type;
-            result[name] = read(instance, fieldType, value);
+            result[name] = read(instance, field.type, value, aliases);
         }
         return result;
+    } else if (type.type == "enum" && type.payload) {
+        // Pointer to the tag followed by the fields
+        const tag = concatBytes(memoryView.slice(value, value + BYTES), false);
+        const fields = [];
+        let addr = value + BYTES;
+        for (const fieldType of type.payload[tag]) {
+            const sliced = memoryView.slice(addr, addr + BYTES);
+            const elem = concatBytes(sliced, fieldType == "num");
+            fields.push(read(instance, fieldType, elem, aliases));
+            addr += BYTES;
+        }
+        return { variant: type.enum[tag], fields };
     } else if (type.type == "enum") {
         return type.enum[value];
+    } else if (type.type == "function") {
+        const func = callable(instance, value);
+        return (...args) => {
+            args = args.map((arg, i) => write(instance, type.args[i], arg));
+            return read(instance, type.returns, func(...args), aliases);
+        };
     } else if (type.type == "alias") {
-        return null;
+        const define = aliases[type.name];
+        return define ? read(instance, define, value, aliases) : null;
     } else {
         return type;
     }
 }
 
+// Closure record has the index of the function in the exported table first
+export function callable(instance, value) {
+    const memoryView = new Uint8Array(instance.exports.mem.buffer);
+    const index = concatBytes(memoryView.slice(value, value + BYTES), false);
+    const func = instance.exports.table.get(index);
+    return (...args) => {
+        instance.exports.closure.value = value;
+        return func(...args);
+    };
+}
+
 export function write(instance, type, value) {
     const reader = (type) => (type == "num" ? "setFloat32" : "setInt32");
     const buffer = instance.exports.mem.buffer;
     if (type == null) return null;
     else if (type == "int") return value;
     else if (type == "num") return value;
+    else if (type.type == "nullable")
+        return value === null ? -1 : write(instance, type.element, value);
     else if (type == "str") {
         const utf8 = new TextEncoder().encode(value + "\0");
         const ptr = instance.exports.malloc(utf8.length);
@@ -1,4 +1,4 @@
-import { write, read, concatBytes } from "../ffi.mjs";
+import { write, read, concatBytes, callable } from "../ffi.mjs";
 
 export class MystiaStdLib {
     constructor() {
@@ -69,6 +69,9 @@ export class MystiaStdLib {
                 const array2 = read(this.instance, typ, b);
                 return write(this.instance, typ, [...array1, ...array2]);
             },
+            abort: (message) => {
+                throw new Error(read(this.instance, "str", message));
+            },
         };
     }
     set_wasm(instance) {
@@ -160,14 +163,14 @@ export class MystiaWebLib extends MystiaStdLib {
         };
         this.functions.evt_elm = (id, name, func) => {
             const elm = document.getElementById(getMystiaDom(id));
-            func = read(this.instance, "str", func);
+            const callback = callable(this.instance, func);
             name = read(this.instance, "str", name);
             if (name.includes("key")) {
                 document.body.addEventListener(name, (event) =>
-                    this.instance.exports[func](event.keyCode),
+                    callback(event.keyCode),
                 );
             } else {
-                elm.addEventListener(name, () => this.instance.exports[func]());
+                elm.addEventListener(name, () => callback(0));
             }
         };
     }
@@ -23,43 +23,98 @@ impl Mystia {
 #[wasm_bindgen]
 pub fn mystia(source: &str) -> Result<Mystia, String> {
     let mut compiler = Compiler::new();
-    if let Some(wat_code) = compiler.build(source) {
-        let bytes = wat::parse_str(wat_code.clone()).unwrap();
-        Ok(Mystia {
-            bytecode: bytes,
-            return_type: type_to_json(&compiler.program_return),
-        })
-    } else {
-        let error_message = "failed to parse, compile or check type consistency";
-        Err(compiler.occurred_error.unwrap_or(error_message.to_string()))
+    match compiler.build(source) {
+        Ok(artifact) => {
+            let bytes = wat::parse_str(artifact.wat).unwrap();
+            Ok(Mystia {
+                bytecode: bytes,
+                return_type: type_to_json(&compiler.program_return, &compiler),
+            })
+        }
+        Err(diagnostics) => Err(diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.render(source, "<input>"))
+            .collect::<Vec<_>>()
+            .join("\n\n")),
     }
 }
 
-pub fn type_to_json(typ: &Type) -> String {
+/// Descriptor of the type that the runtime reads the value by. Aliases are resolved
+/// into the descriptor marked `alias: name`, and the recursive reference to it
+/// `{ type: "alias", name }` is read by the enclosing descriptor of that name
+pub fn type_to_json(typ: &Type, ctx: &Compiler) -> String {
+    describe(typ, ctx, &mut vec![])
+}
+
+fn describe(typ: &Type, ctx: &Compiler, aliases: &mut Vec<String>) -> String {
+    let mut each = |types: &[Type]| {
+        let types = types.iter().map(|typ| describe(typ, ctx, aliases));
+        types.collect::<Vec<_>>().join(", ")
+    };
     match typ {
         Type::Integer => "\"int\"".to_string(),
         Type::Number => "\"num\"".to_string(),
         Type::Bool => "\"bool\"".to_string(),
         Type::String => "\"str\"".to_string(),
-        Type::Void => "null".to_string(),
+        Type::Void | Type::Error => "null".to_string(),
         Type::Dict(dict) => format!(
             "{{ type: \"dict\", fields: {{ {} }} }}",
             dict.iter()
                 .map(|(k, (offset, typ))| format!(
                     "{k}: {{ type: {}, offset: {offset} }}",
-                    type_to_json(typ)
+                    describe(typ, ctx, aliases)
                 ))
                 .collect::<Vec<_>>()
                 .join(", ")
         ),
-        Type::Array(typ) => format!("{{ type: \"array\", element: {} }}", type_to_json(typ)),
+        Type::Array(typ) => format!(
+            "{{ type: \"array\", element: {} }}",
+            describe(typ, ctx, aliases)
+        ),
+        Type::Nullable(typ) => format!(
+            "{{ type: \"nullable\", element: {} }}",
+            describe(typ, ctx, aliases)
+        ),
+        Type::Enum(e) if typ.is_tagged() => format!(
+            "{{ type: \"enum\", enum: [{}], payload: [{}] }}",
+            e.keys()
+                .map(|x| format!("\"{x}\""))
+                .collect::<Vec<_>>()
+                .join(", "),
+            e.values()
+                .map(|fields| format!("[{}]", each(fields)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
         Type::Enum(e) => format!(
             "{{ type: \"enum\", enum: [{}] }}",
-            e.iter()
+            e.keys()
                 .map(|x| format!("\"{x}\""))
                 .collect::<Vec<_>>()
                 .join(", ")
         ),
-        Type::Alias(name) => format!("{{ type: \"alias\", name: \"{name}\" }}"),
+        Type::Function(args, ret) => format!(
+            "{{ type: \"function\", args: [{}], returns: {} }}",
+            each(args),
+            describe(ret, ctx, aliases)
+        ),
+        Type::Alias(name) | Type::Generic(name, _) => {
+            let name = match typ {
+                Type::Generic(..) => typ.format(),
+                _ => name.clone(),
+            };
+            match ctx.type_alias.get(&name) {
+                Some(define) if !aliases.contains(&name) => {
+                    aliases.push(name.clone());
+                    let json = describe(define, ctx, aliases);
+                    aliases.pop();
+                    match json.strip_prefix("{ ") {
+                        Some(fields) => format!("{{ alias: \"{name}\", {fields}"),
+                        None => json,
+                    }
+                }
+                _ => format!("{{ type: \"alias\", name: \"{name}\" }}"),
+            }
+        }
     }
 }